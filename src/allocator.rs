@@ -1,37 +1,110 @@
-use ptr::GcBox;
+use heap_size::HeapSize;
+use ptr::{ErasedTemplate, GcBox, Handle};
+use slab::SlabAllocator;
+use stats::{AllocSite, SlabStats};
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::any::type_name;
 use std::cell::Cell;
-use std::collections::HashMap;
+#[cfg(feature = "quarantine")]
+use std::collections::VecDeque;
+#[cfg(feature = "quarantine")]
+use std::mem::size_of;
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use trace::{Trace, Tracer};
 use UntypedGcBox;
 use {AsTyped, AsUntyped};
 
 /// Type-erased allocation info
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub(crate) struct AllocInfo {
     pub ptr: NonNull<UntypedGcBox>,
+    handle: NonNull<Handle>,  // Must be updated whenever `ptr` changes
+    pins: Cell<usize>,        // The object can't be moved while pinned
+    template: ErasedTemplate, // Pointer to the object when it was allocated, for its metadata
     // unsafe is because it must be called with accompanying pointer
-    free: unsafe fn(NonNull<UntypedGcBox>), // Frees allocation and calls destructor
-    reachable: Cell<bool>,                  // Whether this has been found to be reachable
+    drop_value: unsafe fn(NonNull<UntypedGcBox>, ErasedTemplate), // Calls destructor, leaving the storage
+    reachable: Cell<bool>,    // Whether this has been found to be reachable
     inter_marks: Cell<usize>, // # of marks from objects for which is_marked_reachable == false
     // unsafe is because it must be called with accompanying pointer
-    refs: unsafe fn(NonNull<UntypedGcBox>) -> usize,
+    trace: Option<unsafe fn(NonNull<UntypedGcBox>, ErasedTemplate, &mut Tracer)>, // `None` if it can't hold a `Gc`
+    pub type_name: &'static str,
+    pub layout: Layout, // Layout of the `GcBox`, needed to release its storage
+    pub site: Option<AllocSite>, // Only recorded when site tracking is enabled
+    audit_expected: Cell<usize>, // Strong count the audit expects once a group of objects is freed
+    audit_external: Cell<usize>, // # of external refs seen by the last audit
+    audit_stable_runs: Cell<usize>, // # of audits `audit_external` didn't change for
+    pressure: Cell<usize>, // Bytes outside of the gc heap kept alive by the object
     // unsafe is because it must be called with accompanying pointer
-    trace: unsafe fn(NonNull<UntypedGcBox>) -> Tracer,
+    heap_size: Option<unsafe fn(NonNull<UntypedGcBox>, ErasedTemplate) -> usize>, // Only set by `alloc_measured`
+    heap_bytes: Cell<usize>, // Heap size as of the last measurement
 }
 
 impl AllocInfo {
-    fn new<T: Trace>(value: T) -> AllocInfo {
+    /// `traced` is whether the object can hold a `Gc`
+    ///
+    /// Unsafe because `ptr` must point to an initialized object
+    unsafe fn new<T: Trace + ?Sized>(
+        ptr: NonNull<GcBox<T>>,
+        handle: NonNull<Handle>,
+        site: Option<AllocSite>,
+        traced: bool,
+    ) -> AllocInfo {
         AllocInfo {
-            ptr: store_single_value(value).as_untyped(),
-            free: get_free::<T>(),
+            ptr: ptr.as_untyped(),
+            handle,
+            pins: Cell::new(0),
+            template: ErasedTemplate::new(ptr.as_ptr()),
+            drop_value: get_drop_value::<T>(),
             reachable: Cell::new(false),
             inter_marks: Cell::new(0),
-            refs: get_refs_accessor::<T>(),
-            trace: get_tracer::<T>(),
+            trace: if traced {
+                Some(get_tracer::<T>())
+            } else {
+                None
+            },
+            type_name: type_name::<T>(),
+            layout: Layout::for_value(ptr.as_ref()),
+            site,
+            audit_expected: Cell::new(0),
+            audit_external: Cell::new(0),
+            audit_stable_runs: Cell::new(0),
+            pressure: Cell::new(0),
+            heap_size: None,
+            heap_bytes: Cell::new(0),
         }
     }
 
+    pub fn pin(&self) {
+        self.pins.set(self.pins.get() + 1);
+    }
+    pub fn unpin(&self) {
+        self.pins.set(self.pins.get().saturating_sub(1));
+    }
+    pub fn is_pinned(&self) -> bool {
+        self.pins.get() > 0
+    }
+
+    /// Point the object's handle at the new location of its `GcBox`
+    fn moved_to(&mut self, ptr: NonNull<UntypedGcBox>) {
+        self.ptr = ptr;
+        self.handle().moved_to(ptr);
+    }
+
+    /// Make pointers to the object see it as dead before it is freed
+    pub fn kill(&self) {
+        self.handle().kill();
+    }
+    pub fn is_alive(&self) -> bool {
+        self.handle().is_alive()
+    }
+
+    fn handle(&self) -> &Handle {
+        // Unsafe is fine since the handle lives at least as long as the object
+        unsafe { self.handle.as_ref() }
+    }
+
     pub fn mark_reachable(&self) {
         self.reachable.set(true);
     }
@@ -53,114 +126,565 @@ impl AllocInfo {
     }
 
     pub fn ref_count(&self) -> usize {
-        // Unsafe is fine since this is only called with the accompanying
-        // valid pointer.
-        unsafe { (self.refs)(self.ptr) }
+        self.handle().strong_count()
+    }
+
+    #[cfg(feature = "debug-checks")]
+    pub fn weak_count(&self) -> usize {
+        self.handle().weak_count()
     }
 
-    pub(crate) fn children(&self) -> impl Iterator<Item = NonNull<UntypedGcBox>> {
+    /// Number of references to this object from outside of the gc heap.
+    ///
+    /// Only accurate after marking and before unmarking.
+    pub fn external_refs(&self) -> usize {
+        self.ref_count().saturating_sub(self.inter_marks())
+    }
+
+    /// Start checking which references to this object freeing some objects
+    /// drops
+    pub fn begin_audit(&self) {
+        self.audit_expected.set(self.ref_count());
+    }
+    /// A reference to this object that the `Trace` impl of an object about to
+    /// be freed reported
+    pub fn expect_dropped_ref(&self) {
+        self.audit_expected
+            .set(self.audit_expected.get().saturating_sub(1));
+    }
+    /// Returns how many more references to this object were dropped than
+    /// expected, and starts checking again. Those were held by a freed object
+    /// that didn't trace them.
+    pub fn finish_audit(&self) -> usize {
+        let untraced = self.audit_expected.get().saturating_sub(self.ref_count());
+        self.begin_audit();
+        untraced
+    }
+
+    /// Record the result of a leak audit for this object.
+    ///
+    /// `suspicious` is whether the object is only kept alive by references
+    /// from outside of the gc heap that no root explains.
+    pub fn record_audit(&self, suspicious: bool) {
+        let external = self.external_refs();
+        let runs = self.audit_stable_runs.get();
+        if !suspicious {
+            self.audit_stable_runs.set(0);
+        } else if runs > 0 && external == self.audit_external.get() {
+            self.audit_stable_runs.set(runs + 1);
+        } else {
+            // First audit with this number of external references
+            self.audit_stable_runs.set(1);
+        }
+        self.audit_external.set(external);
+    }
+
+    /// Number of consecutive audits where the object looked like it was only
+    /// alive because of an incomplete `Trace` impl.
+    pub fn audit_stable_runs(&self) -> usize {
+        self.audit_stable_runs.get()
+    }
+
+    pub fn audit_external(&self) -> usize {
+        self.audit_external.get()
+    }
+
+    pub fn add_pressure(&self, bytes: usize) {
+        self.pressure.set(self.pressure.get().saturating_add(bytes));
+    }
+    /// Returns how much pressure was actually removed
+    pub fn remove_pressure(&self, bytes: usize) -> usize {
+        let removed = bytes.min(self.pressure.get());
+        self.pressure.set(self.pressure.get() - removed);
+        removed
+    }
+    pub fn pressure(&self) -> usize {
+        self.pressure.get()
+    }
+
+    /// Current heap size of the object, if it was allocated with `alloc_measured`
+    pub fn measure_heap(&self) -> usize {
         // Unsafe is fine since this is only called with the accompanying
         // valid pointer.
-        let tracer = unsafe { (self.trace)(self.ptr) };
+        self.heap_size
+            .map_or(0, |heap_size| unsafe { heap_size(self.ptr, self.template) })
+    }
+    /// Update the heap size that was last measured
+    pub fn remeasure_heap(&self) {
+        self.heap_bytes.set(self.measure_heap());
+    }
+    pub fn heap_bytes(&self) -> usize {
+        self.heap_bytes.get()
+    }
+    pub fn is_measured(&self) -> bool {
+        self.heap_size.is_some()
+    }
+    /// All of the memory outside of the gc heap the object is known to keep alive
+    pub fn external_bytes(&self) -> usize {
+        self.pressure().saturating_add(self.heap_bytes())
+    }
+
+    /// Whether the object can't point to other objects, so it can never be
+    /// part of a cycle
+    pub fn is_leaf(&self) -> bool {
+        self.trace.is_none()
+    }
+
+    /// Trace the object. The same `tracer` should be used for a whole pass
+    /// over the heap so that shared allocations are only traced once.
+    pub(crate) fn children(&self, tracer: &mut Tracer) -> impl Iterator<Item = NonNull<Handle>> {
+        if let Some(trace) = self.trace {
+            // Unsafe is fine since this is only called with the accompanying
+            // valid pointer.
+            unsafe { trace(self.ptr, self.template, tracer) };
+        }
         tracer.results().map(|dest| dest.0)
     }
 }
 
+impl PartialEq for AllocInfo {
+    fn eq(&self, other: &AllocInfo) -> bool {
+        // Every allocation has its own info, so the pointer identifies it
+        self.ptr == other.ptr
+    }
+}
+
 impl Drop for AllocInfo {
     fn drop(&mut self) {
         // This is used as the destructor for the pointer, so it should the only
-        // reference to the object.
-        unsafe { (self.free)(self.ptr) };
+        // reference to the object. The allocator releases the storage.
+        unsafe { (self.drop_value)(self.ptr, self.template) };
+        #[cfg(feature = "quarantine")]
+        unsafe {
+            poison(self.ptr, self.layout.size())
+        };
     }
 }
 
+/// Byte that the storage of freed objects is overwritten with
+#[cfg(feature = "quarantine")]
+const POISON_BYTE: u8 = 0xDB;
+
+/// A `usize` filled with `POISON_BYTE`
+#[cfg(feature = "quarantine")]
+pub(crate) const POISON_WORD: usize = usize::from_ne_bytes([POISON_BYTE; size_of::<usize>()]);
+
+/// Number of freed objects whose storage is held onto before it is released
+#[cfg(feature = "quarantine")]
+const QUARANTINE_LEN: usize = 1024;
+
+/// Don't bother shrinking the table below this many slots
+const MIN_SHRINK_CAPACITY: usize = 64;
+
+/// Source of the ids that tell the objects of different allocators apart
+static NEXT_ALLOCATOR_ID: AtomicUsize = AtomicUsize::new(0);
+
 /// Handles allocation and freeing of objects.
-#[derive(Default, Debug, PartialEq)]
+///
+/// Storage for objects comes from a size-class `SlabAllocator`.
+#[derive(Debug, PartialEq)]
 pub(crate) struct Allocator {
-    pub items: HashMap<*mut UntypedGcBox, AllocInfo>,
+    id: usize, // Stored in the handles of this allocator's objects
+    // Dense, in no particular order. Each object's header and handle store
+    // its index.
+    pub items: Vec<AllocInfo>,
     // frees: Vec<AllocInfo>, // Only accessed in sweep func
+    // Poisoned storage of freed objects, oldest first
+    #[cfg(feature = "quarantine")]
+    quarantine: VecDeque<(NonNull<UntypedGcBox>, Layout)>,
+    // Handles of freed objects that `Gc`s or `Weak`s may still point to
+    dead_handles: Vec<NonNull<Handle>>,
+    slab: SlabAllocator,
+}
+
+impl Default for Allocator {
+    fn default() -> Allocator {
+        Allocator::new()
+    }
 }
 
 impl Allocator {
     pub fn new() -> Allocator {
+        Allocator::with_slab(SlabAllocator::new())
+    }
+    /// Get object storage from `backing` instead of the global allocator
+    pub fn with_backing(backing: Box<dyn GlobalAlloc>) -> Allocator {
+        Allocator::with_slab(SlabAllocator::with_backing(backing))
+    }
+    fn with_slab(slab: SlabAllocator) -> Allocator {
         Allocator {
+            id: NEXT_ALLOCATOR_ID.fetch_add(1, Ordering::Relaxed),
             items: Default::default(),
+            #[cfg(feature = "quarantine")]
+            quarantine: Default::default(),
+            dead_handles: Default::default(),
+            slab,
+        }
+    }
+    pub fn alloc<T: Trace>(&mut self, value: T, site: Option<AllocSite>) -> NonNull<Handle> {
+        let storage: NonNull<GcBox<T>> = self.slab.alloc(Layout::new::<GcBox<T>>()).cast();
+        let handle: NonNull<Handle> = self.slab.alloc(Layout::new::<Handle>()).cast();
+        // Unsafe is fine since the storage has the layout of a `GcBox<T>`,
+        // and the handle has the layout of a `Handle`
+        unsafe {
+            storage.as_ptr().write(GcBox::new(value));
+            handle
+                .as_ptr()
+                .write(Handle::new(storage.as_untyped(), self.id));
+        }
+        // Unsafe is fine since the object was just written
+        let info = unsafe { AllocInfo::new(storage, handle, site, T::needs_trace()) };
+        self.track(info);
+        handle
+    }
+    /// Like `alloc`, but the handle is created first and passed to `make`,
+    /// which returns the value. The handle looks dead until then.
+    pub fn alloc_cyclic<T: Trace, F>(&mut self, make: F, site: Option<AllocSite>) -> NonNull<Handle>
+    where
+        F: FnOnce(NonNull<Handle>) -> T,
+    {
+        let handle: NonNull<Handle> = self.slab.alloc(Layout::new::<Handle>()).cast();
+        // Unsafe is fine since the handle has the layout of a `Handle`
+        unsafe { handle.as_ptr().write(Handle::reserved(self.id)) };
+
+        /// Releases the handle like that of a freed object if `make` panics
+        struct Reserved<'r> {
+            dead_handles: &'r mut Vec<NonNull<Handle>>,
+            handle: NonNull<Handle>,
         }
+        impl<'r> Drop for Reserved<'r> {
+            fn drop(&mut self) {
+                self.dead_handles.push(self.handle);
+            }
+        }
+        let reserved = Reserved {
+            dead_handles: &mut self.dead_handles,
+            handle,
+        };
+        let value = make(handle);
+        ::std::mem::forget(reserved);
+
+        let storage: NonNull<GcBox<T>> = self.slab.alloc(Layout::new::<GcBox<T>>()).cast();
+        // Unsafe is fine since the storage has the layout of a `GcBox<T>`, and
+        // the handle was just written
+        let info = unsafe {
+            storage.as_ptr().write(GcBox::new(value));
+            handle.as_ref().moved_to(storage.as_untyped());
+            AllocInfo::new(storage, handle, site, T::needs_trace())
+        };
+        self.track(info);
+        handle
     }
-    pub fn alloc<T: Trace>(&mut self, value: T) -> NonNull<GcBox<T>> {
-        let info = AllocInfo::new(value);
-        let ptr = info.ptr;
-        self.items.insert(ptr.as_ptr(), info);
-        ptr.as_typed()
+    /// Like `alloc`, but moves the value out of a box, so that it can be
+    /// unsized. Whether an unsized value needs tracing can't be asked of its
+    /// type, so the caller says.
+    pub fn alloc_boxed<T: Trace + ?Sized>(
+        &mut self,
+        value: Box<T>,
+        needs_trace: bool,
+        site: Option<AllocSite>,
+    ) -> NonNull<Handle> {
+        let (layout, _) = Layout::new::<Cell<usize>>()
+            .extend(Layout::for_value(&*value))
+            .expect("object is too large");
+        // Same as the layout of the `GcBox`, since it is `repr(C)`
+        let layout = layout.pad_to_align();
+        let value_ptr: *const T = &*value;
+        let template = value_ptr as *const GcBox<T>;
+        let storage = self.slab.alloc(layout);
+        let handle: NonNull<Handle> = self.slab.alloc(Layout::new::<Handle>()).cast();
+        // Unsafe is fine since the storage has the layout of the `GcBox`, the
+        // metadata came from the value, and the handle has the layout of a
+        // `Handle`
+        let info = unsafe {
+            let storage = storage.cast::<UntypedGcBox>().as_typed_like(template);
+            GcBox::write_boxed(storage.as_ptr(), value);
+            handle
+                .as_ptr()
+                .write(Handle::new(storage.as_untyped(), self.id));
+            AllocInfo::new(storage, handle, site, needs_trace)
+        };
+        self.track(info);
+        handle
+    }
+    fn track(&mut self, info: AllocInfo) {
+        // Unsafe is fine since the object was just allocated
+        unsafe { header_slot(info.ptr) }.set(self.items.len());
+        info.handle().set_slot(self.items.len());
+        self.items.push(info);
+    }
+    /// Like `alloc`, but also keeps track of the value's heap size
+    pub fn alloc_measured<T: Trace + HeapSize>(
+        &mut self,
+        value: T,
+        site: Option<AllocSite>,
+    ) -> NonNull<Handle> {
+        let handle = self.alloc(value, site);
+        let info = self.items.last_mut().expect("object was just allocated");
+        info.heap_size = Some(get_heap_size::<T>());
+        info.remeasure_heap();
+        handle
+    }
+    /// Remove an object's info from the table
+    fn take(&mut self, ptr: NonNull<UntypedGcBox>) -> Option<AllocInfo> {
+        let slot = self.slot_for_ptr(ptr.as_ptr())?;
+        let info = self.items.swap_remove(slot);
+        if let Some(moved) = self.items.get(slot) {
+            // Unsafe is fine since every tracked object is alive
+            unsafe { header_slot(moved.ptr) }.set(slot);
+            moved.handle().set_slot(slot);
+        }
+        Some(info)
     }
     /// Just remove an object
     pub fn free(&mut self, ptr: NonNull<UntypedGcBox>) {
-        self.items.remove(&ptr.as_ptr()); // Will be deallocated by Drop
+        if let Some(info) = self.take(ptr) {
+            self.free_info(info);
+        }
+    }
+    /// Free every object
+    pub fn clear(&mut self) {
+        // Popping from the end doesn't move any other object's slot
+        while let Some(info) = self.items.pop() {
+            self.free_info(info);
+        }
+    }
+    fn free_info(&mut self, info: AllocInfo) {
+        let (ptr, layout) = (info.ptr, info.layout);
+        // Pointers to the object have to see it as dead during its destructor
+        self.kill_handle(&info);
+        drop(info); // Runs the destructor
+        self.release(ptr, layout);
+    }
+    /// Mark an object's handle as dead. It is released once nothing points to it.
+    fn kill_handle(&mut self, info: &AllocInfo) {
+        info.handle().kill();
+        self.dead_handles.push(info.handle);
+    }
+    /// Release the handles of freed objects that nothing points to anymore
+    pub fn release_unused_handles(&mut self) {
+        let slab = &mut self.slab;
+        self.dead_handles.retain(|&handle| {
+            // Unsafe is fine since dead handles aren't released until here
+            if unsafe { handle.as_ref() }.is_referenced() {
+                return true;
+            }
+            // Unsafe is fine since handles come from the slab with this layout
+            unsafe { slab.dealloc(handle.cast(), Layout::new::<Handle>()) };
+            false
+        });
+    }
+    /// Give an object's storage back to the slab once its contents are gone
+    fn release(&mut self, ptr: NonNull<UntypedGcBox>, layout: Layout) {
+        #[cfg(feature = "quarantine")]
+        self.quarantine(ptr, layout);
+        // Unsafe is fine since the storage came from the slab with this layout
+        #[cfg(not(feature = "quarantine"))]
+        unsafe {
+            self.slab.dealloc(ptr.cast(), layout)
+        };
     }
     /// Remove an object and return it's value
     ///
     /// Unsafe because `T` must be the type that was originally stored
     pub unsafe fn remove<T>(&mut self, ptr: NonNull<UntypedGcBox>) -> T {
         use std::mem::forget;
-        let item = self.items.remove(&ptr.as_ptr());
+        let item = self.take(ptr).expect("removed an untracked object");
+        self.kill_handle(&item);
         forget(item);
         // The unsafe part
-        let boxed: Box<GcBox<T>> = Box::from_raw(ptr.as_typed().as_ptr());
-        boxed.reclaim_value()
+        let gc_box: GcBox<T> = ptr.as_typed().as_ptr().read();
+        #[cfg(feature = "quarantine")]
+        poison(ptr, size_of::<GcBox<T>>());
+        self.release(ptr, Layout::new::<GcBox<T>>());
+        gc_box.reclaim_value()
     }
 
-    // pub fn is_ptr_tracked<T>(&self, ptr: *const T) -> bool {
-    //     let ptr: *const UntypedGcBox = ptr as *const _;
-    //     self.items.contains_key(&ptr)
-    // }
+    /// Move objects into the lowest free slots of their size class, then give
+    /// pages that end up empty back to the backing allocator.
+    ///
+    /// Pinned objects and objects too large for a size class stay put.
+    pub fn compact(&mut self) {
+        let mut by_class = vec![vec![]; self.slab.num_classes()];
+        for info in &self.items {
+            if info.is_pinned() {
+                continue;
+            }
+            if let Some(class) = SlabAllocator::class_for(info.layout) {
+                by_class[class].push(info.ptr.cast::<u8>());
+            }
+        }
+
+        for (class, objects) in by_class.into_iter().enumerate() {
+            for (from, to) in self.slab.plan_moves(class, objects) {
+                let from: NonNull<UntypedGcBox> = from.cast();
+                // Unsafe is fine since `from` is a tracked object
+                let slot = unsafe { header_slot(from) }.get();
+                let info = &mut self.items[slot];
+                // Unsafe is fine since `to` is a free slot of the same size
+                // class, and nothing can observe the object while it moves
+                unsafe {
+                    ::std::ptr::copy_nonoverlapping(
+                        from.as_ptr() as *const u8,
+                        to.as_ptr(),
+                        info.layout.size(),
+                    )
+                };
+                #[cfg(feature = "quarantine")]
+                unsafe {
+                    ::std::ptr::write_bytes(
+                        from.as_ptr() as *mut u8,
+                        POISON_BYTE,
+                        info.layout.size(),
+                    )
+                };
+                info.moved_to(to.cast());
+            }
+        }
+
+        self.slab.release_empty_pages();
+    }
+
+    pub fn slab_stats(&self) -> SlabStats {
+        self.slab.stats()
+    }
+
+    /// Hold onto freed storage, releasing the oldest storage if there is too
+    /// much of it
+    #[cfg(feature = "quarantine")]
+    fn quarantine(&mut self, ptr: NonNull<UntypedGcBox>, layout: Layout) {
+        self.quarantine.push_back((ptr, layout));
+        while self.quarantine.len() > QUARANTINE_LEN {
+            self.release_oldest();
+        }
+    }
+
+    #[cfg(feature = "quarantine")]
+    fn release_oldest(&mut self) {
+        if let Some((ptr, layout)) = self.quarantine.pop_front() {
+            // Unsafe is fine since the storage came from the slab with this
+            // layout, and its contents have already been dropped.
+            unsafe { self.slab.dealloc(ptr.cast(), layout) };
+        }
+    }
+
+    #[cfg(all(test, feature = "quarantine"))]
+    pub fn quarantined(&self) -> usize {
+        self.quarantine.len()
+    }
+
+    /// Index of the info for the object at `ptr`, if it is tracked
+    fn slot_for_ptr(&self, ptr: *const UntypedGcBox) -> Option<usize> {
+        let ptr = NonNull::new(ptr as *mut UntypedGcBox)?;
+        // Unsafe is fine since pointers given to the allocator point to live
+        // objects. Pointers reported by tracing go through their handles.
+        let slot = unsafe { header_slot(ptr) }.get();
+        match self.items.get(slot) {
+            Some(info) if info.ptr == ptr => Some(slot),
+            _ => None,
+        }
+    }
 
     pub(crate) fn info_for_ptr(&self, ptr: *const UntypedGcBox) -> Option<&AllocInfo> {
-        self.items.get(&(ptr as *mut _))
+        self.slot_for_ptr(ptr).map(|slot| &self.items[slot])
+    }
+
+    /// Index of the info for the object behind `handle`, if it is tracked
+    fn slot_for_handle(&self, handle: NonNull<Handle>) -> Option<usize> {
+        // Unsafe is fine since a handle isn't released while anything points
+        // to it, and whoever gave us the handle points to it
+        let handle_ref = unsafe { handle.as_ref() };
+        // Objects of other collectors are never looked at
+        if handle_ref.owner() != self.id {
+            return None;
+        }
+        // A freed object's slot may have been given to another object
+        let slot = handle_ref.slot();
+        match self.items.get(slot) {
+            Some(info) if info.handle == handle => Some(slot),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn info_for_handle(&self, handle: NonNull<Handle>) -> Option<&AllocInfo> {
+        self.slot_for_handle(handle).map(|slot| &self.items[slot])
     }
 
-    // Stub
     pub fn should_shrink_items(&self) -> bool {
-        false
+        let capacity = self.items.capacity();
+        capacity > MIN_SHRINK_CAPACITY && self.items.len() * 4 < capacity
     }
 
-    // Not a great way to shrink with HashMap
-    pub fn shrink_items(&mut self) {}
+    // Leave room to grow so that we don't immediately reallocate
+    pub fn shrink_items(&mut self) {
+        let target = (self.items.len() * 2).max(MIN_SHRINK_CAPACITY);
+        self.items.shrink_to(target);
+    }
 }
 
-fn store_single_value<T>(value: T) -> NonNull<GcBox<T>> {
-    let storage = Box::new(GcBox::new(value));
-    // Unsafe is for the call to `NonNull::new_unchecked`.
-    // The call can't fail since `Box::leak` returns a reference, which must
-    // be a valid, nonnull pointer.
-    unsafe { NonNull::new_unchecked(Box::leak(storage)) }
+/// The slot index stored in the header of the object at `ptr`
+///
+/// Unsafe because `ptr` must point to a `GcBox`, or to a slot of the slab
+unsafe fn header_slot<'a>(ptr: NonNull<UntypedGcBox>) -> &'a Cell<usize> {
+    // `GcBox` is `repr(C)` with the slot as its first field
+    ptr.cast::<Cell<usize>>().as_ref()
 }
 
-fn get_free<T>() -> unsafe fn(NonNull<UntypedGcBox>) {
-    /// Must be called with accompanying pointer
-    unsafe fn free<T>(ptr: NonNull<UntypedGcBox>) {
-        Box::<GcBox<T>>::from_raw(ptr.as_typed().as_ptr());
+impl Drop for Allocator {
+    fn drop(&mut self) {
+        // Destructors have to run before the slab's pages go away
+        self.clear();
+        #[cfg(feature = "quarantine")]
+        {
+            while !self.quarantine.is_empty() {
+                self.release_oldest();
+            }
+        }
     }
-    free::<T>
 }
 
-fn get_refs_accessor<T>() -> unsafe fn(NonNull<UntypedGcBox>) -> usize {
-    /// Must be called with accompanying pointer
-    unsafe fn refs<T>(ptr: NonNull<UntypedGcBox>) -> usize {
-        let ptr = ptr.as_typed();
+fn get_drop_value<T: ?Sized>() -> unsafe fn(NonNull<UntypedGcBox>, ErasedTemplate) {
+    /// Must be called with accompanying pointer and template
+    unsafe fn drop_value<T: ?Sized>(ptr: NonNull<UntypedGcBox>, template: ErasedTemplate) {
+        ::std::ptr::drop_in_place(ptr.as_typed_like(template.typed::<T>()).as_ptr());
+    }
+    drop_value::<T>
+}
+
+/// Overwrite `size` bytes of an object's storage with the poison pattern
+///
+/// Unsafe because `size` must be at most the size of the storage, and the
+/// contents must already be dropped or moved out
+#[cfg(feature = "quarantine")]
+unsafe fn poison(ptr: NonNull<UntypedGcBox>, size: usize) {
+    ::std::ptr::write_bytes(ptr.as_ptr() as *mut u8, POISON_BYTE, size);
+}
+
+fn get_heap_size<T: HeapSize + ?Sized>() -> unsafe fn(NonNull<UntypedGcBox>, ErasedTemplate) -> usize
+{
+    /// Must be called with accompanying pointer and template
+    unsafe fn heap_size<T: HeapSize + ?Sized>(
+        ptr: NonNull<UntypedGcBox>,
+        template: ErasedTemplate,
+    ) -> usize {
+        let ptr = ptr.as_typed_like(template.typed::<T>());
         let gc_box: &GcBox<T> = ptr.as_ref();
-        gc_box.strong_count()
+        gc_box.borrow().heap_size()
     }
-    refs::<T>
+    heap_size::<T>
 }
 
-fn get_tracer<T: Trace>() -> unsafe fn(NonNull<UntypedGcBox>) -> Tracer {
-    /// Must be called with accompanying pointer
-    unsafe fn tracer<T: Trace>(ptr: NonNull<UntypedGcBox>) -> Tracer {
-        let mut tracer = Tracer::new();
-        let ptr = ptr.as_typed();
+fn get_tracer<T: Trace + ?Sized>() -> unsafe fn(NonNull<UntypedGcBox>, ErasedTemplate, &mut Tracer)
+{
+    /// Must be called with accompanying pointer and template
+    unsafe fn tracer<T: Trace + ?Sized>(
+        ptr: NonNull<UntypedGcBox>,
+        template: ErasedTemplate,
+        tracer: &mut Tracer,
+    ) {
+        let ptr = ptr.as_typed_like(template.typed::<T>());
         let gc_box: &GcBox<T> = ptr.as_ref();
         tracer.add_target(gc_box.borrow());
-        tracer
     }
     tracer::<T>
 }
@@ -228,12 +752,163 @@ mod tests {
         }
     }
 
+    /// Allocate an object, returning a pointer to its `GcBox`
+    fn alloc_box<T: Trace>(alloc: &mut Allocator, value: T) -> NonNull<GcBox<T>> {
+        let handle = alloc.alloc(value, None);
+        unsafe { handle.as_ref() }.box_ptr().unwrap().as_typed()
+    }
+
     #[test]
     fn runs_dtor_on_free() {
         let mut alloc = Allocator::new();
         let counter = DtorCounter::new();
-        let ptr = alloc.alloc(counter.incr());
+        let ptr = alloc_box(&mut alloc, counter.incr());
+        alloc.free(ptr.as_untyped());
+        assert_eq!(counter.count(), 1);
+    }
+
+    #[test]
+    fn cyclic_handle_is_dead_until_written() {
+        let mut alloc = Allocator::new();
+        let handle = alloc.alloc_cyclic(
+            |handle| {
+                assert!(!unsafe { handle.as_ref() }.is_alive());
+                5
+            },
+            None,
+        );
+        let handle_ref = unsafe { handle.as_ref() };
+        assert!(handle_ref.is_alive());
+        let val: i32 = unsafe { *handle_ref.box_ptr().unwrap().as_typed().as_ref().borrow() };
+        assert_eq!(val, 5);
+        assert_eq!(alloc.items.len(), 1);
+    }
+
+    #[test]
+    fn cyclic_handle_released_on_panic() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let mut alloc = Allocator::new();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            alloc.alloc_cyclic(|_| -> i32 { panic!("no value") }, None)
+        }));
+        assert!(result.is_err());
+        assert!(alloc.items.is_empty());
+        assert_eq!(alloc.dead_handles.len(), 1);
+        alloc.release_unused_handles();
+        assert!(alloc.dead_handles.is_empty());
+    }
+
+    #[test]
+    fn lookup_survives_removal() {
+        let mut alloc = Allocator::new();
+        let ptrs: Vec<_> = (0..5)
+            .map(|i| alloc_box(&mut alloc, i).as_untyped())
+            .collect();
+        alloc.free(ptrs[1]);
+        let val: i32 = unsafe { alloc.remove(ptrs[3]) };
+        assert_eq!(val, 3);
+
+        assert!(alloc.info_for_ptr(ptrs[1].as_ptr()).is_none());
+        assert!(alloc.info_for_ptr(ptrs[3].as_ptr()).is_none());
+        for &i in &[0, 2, 4] {
+            let info = alloc.info_for_ptr(ptrs[i].as_ptr()).unwrap();
+            assert_eq!(info.ptr, ptrs[i]);
+        }
+    }
+
+    #[test]
+    fn lookup_ignores_objects_of_other_allocators() {
+        let mut alloc = Allocator::new();
+        let mut other = Allocator::new();
+        let ours = alloc.alloc(0, None);
+        let theirs = other.alloc(0, None);
+        assert!(alloc.info_for_handle(ours).is_some());
+        assert!(alloc.info_for_handle(theirs).is_none());
+        assert!(other.info_for_handle(theirs).is_some());
+
+        // A dead handle keeps pointing at the slot its object had
+        unsafe { ours.as_ref() }.incr_weak();
+        alloc.free(unsafe { ours.as_ref() }.box_ptr().unwrap());
+        let _reused = alloc.alloc(1, None);
+        assert!(alloc.info_for_handle(ours).is_none());
+        unsafe { ours.as_ref() }.decr_weak();
+    }
+
+    #[test]
+    fn shrinks_after_big_free() {
+        let mut alloc = Allocator::new();
+        let ptrs: Vec<_> = (0..1000)
+            .map(|i| alloc_box(&mut alloc, i).as_untyped())
+            .collect();
+        assert!(!alloc.should_shrink_items());
+        for &ptr in &ptrs[10..] {
+            alloc.free(ptr);
+        }
+
+        assert!(alloc.should_shrink_items());
+        alloc.shrink_items();
+        assert!(alloc.items.capacity() <= MIN_SHRINK_CAPACITY);
+        assert!(!alloc.should_shrink_items());
+        for &ptr in &ptrs[..10] {
+            assert!(alloc.info_for_ptr(ptr.as_ptr()).is_some());
+        }
+    }
+
+    #[test]
+    fn dead_handles_wait_for_last_pointer() {
+        let mut alloc = Allocator::new();
+        let handle = alloc.alloc(0, None);
+        let handle_ref = unsafe { handle.as_ref() };
+        handle_ref.incr_weak();
+        alloc.free(handle_ref.box_ptr().unwrap());
+        assert!(!handle_ref.is_alive());
+
+        alloc.release_unused_handles();
+        assert_eq!(alloc.dead_handles.len(), 1);
+        handle_ref.decr_weak();
+        alloc.release_unused_handles();
+        assert!(alloc.dead_handles.is_empty());
+    }
+
+    #[cfg(feature = "quarantine")]
+    #[test]
+    fn freed_storage_is_poisoned() {
+        let mut alloc = Allocator::new();
+        let counter = DtorCounter::new();
+        let ptr = alloc_box(&mut alloc, counter.incr());
         alloc.free(ptr.as_untyped());
         assert_eq!(counter.count(), 1);
+        assert_eq!(alloc.quarantined(), 1);
+
+        let size = size_of::<GcBox<CounterIncrementer>>();
+        let bytes = unsafe { ::std::slice::from_raw_parts(ptr.as_ptr() as *const u8, size) };
+        assert!(bytes.iter().all(|&byte| byte == POISON_BYTE));
+    }
+
+    #[cfg(feature = "quarantine")]
+    #[test]
+    #[should_panic(expected = "use after free")]
+    fn borrow_after_free_panics() {
+        let mut alloc = Allocator::new();
+        let ptr = alloc_box(&mut alloc, 5);
+        alloc.free(ptr.as_untyped());
+        unsafe { ptr.as_ref().borrow() };
+    }
+
+    #[cfg(feature = "quarantine")]
+    #[test]
+    fn quarantine_is_bounded() {
+        let mut alloc = Allocator::new();
+        for i in 0..QUARANTINE_LEN + 10 {
+            let ptr = alloc_box(&mut alloc, i);
+            alloc.free(ptr.as_untyped());
+        }
+        assert_eq!(alloc.quarantined(), QUARANTINE_LEN);
+
+        let ptr = alloc_box(&mut alloc, 0usize);
+        let val: usize = unsafe { alloc.remove(ptr.as_untyped()) };
+        assert_eq!(val, 0);
+        assert_eq!(alloc.quarantined(), QUARANTINE_LEN);
     }
 }
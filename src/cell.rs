@@ -0,0 +1,387 @@
+//! A mutable memory location that is safe to collect while it is borrowed.
+//!
+//! Contents of the gc heap are shared, so mutating them needs interior
+//! mutability. A [`RefCell`] works, but tracing has to borrow its contents,
+//! so a collection that happens while a `RefCell` in the gc heap is mutably
+//! borrowed panics.
+//!
+//! A [`GcCell`] knows when it is being written to. While a mutable borrow is
+//! active, tracing defers the scan of its contents: every object the cell
+//! points to is treated as if it were referenced from outside the gc heap and
+//! kept alive. The contents are scanned normally once the borrow ends, so any
+//! garbage they hold is collected by the next collection.
+//!
+//! Every mutable borrow also records a write barrier: the cell is marked dirty,
+//! meaning the pointers it holds may have changed since the collector last
+//! looked at them. The mark is cleared when a collection scans the contents
+//! again, and can be checked with [`GcCell::is_dirty`].
+//!
+//! # Compacting
+//!
+//! A [compacting] collection can move the object that holds the cell, which
+//! leaves an active borrow dangling. While compacting, the object that owns
+//! the cell must be [pinned] for as long as a borrow is held across an
+//! allocation or a collection.
+//!
+//! # Examples
+//!
+//! ```
+//! use ters_gc::trace::{Trace, Tracer};
+//! use ters_gc::{Collector, Gc, GcCell};
+//!
+//! struct Node<'a> {
+//!     children: GcCell<Vec<Gc<'a, Node<'a>>>>,
+//! }
+//!
+//! impl<'a> Trace for Node<'a> {
+//!     fn trace(&self, tracer: &mut Tracer) {
+//!         tracer.add_target(&self.children);
+//!     }
+//! }
+//!
+//! let mut col = Collector::new();
+//! let mut proxy = col.proxy();
+//!
+//! let root = proxy.alloc(Node { children: GcCell::new(vec![]) });
+//! {
+//!     let mut children = root.children.borrow_mut();
+//!     // Allocating may collect while `root` is mutably borrowed. This is
+//!     // fine since the collector isn't compacting, so `root` can't move.
+//!     children.push(proxy.alloc(Node { children: GcCell::new(vec![]) }));
+//!     proxy.run();
+//! }
+//! assert_eq!(proxy.num_tracked(), 2);
+//! ```
+//!
+//! [`GcCell`]: struct.GcCell.html
+//! [`GcCell::is_dirty`]: struct.GcCell.html#method.is_dirty
+//! [compacting]: ../struct.Proxy.html#method.set_compacting
+//! [pinned]: ../struct.Proxy.html#method.pin
+//! [`RefCell`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
+
+use heap_size::HeapSize;
+use std::cell::{BorrowError, BorrowMutError, Cell, Ref, RefCell, RefMut};
+use std::ops::{Deref, DerefMut};
+use trace::{Trace, Tracer};
+
+/// A mutable memory location for objects in the gc heap.
+///
+/// Works like a [`RefCell`], but a collection that happens while it is
+/// mutably borrowed doesn't panic. See the [module documentation] for
+/// details.
+///
+/// [`RefCell`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
+/// [module documentation]: index.html
+#[derive(Debug, Default)]
+pub struct GcCell<T: ?Sized> {
+    // Set by every mutable borrow, cleared once tracing has scanned the
+    // contents again
+    dirty: Cell<bool>,
+    cell: RefCell<T>,
+}
+
+impl<T> GcCell<T> {
+    /// Creates a new `GcCell` containing `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::GcCell;
+    ///
+    /// let cell = GcCell::new(5);
+    /// ```
+    pub fn new(value: T) -> GcCell<T> {
+        GcCell {
+            dirty: Cell::new(false),
+            cell: RefCell::new(value),
+        }
+    }
+
+    /// Consumes the `GcCell`, returning the wrapped value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::GcCell;
+    ///
+    /// let cell = GcCell::new(5);
+    /// assert_eq!(cell.into_inner(), 5);
+    /// ```
+    pub fn into_inner(self) -> T {
+        self.cell.into_inner()
+    }
+}
+
+impl<T: ?Sized> GcCell<T> {
+    /// Immutably borrows the wrapped value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::GcCell;
+    ///
+    /// let cell = GcCell::new(5);
+    /// assert_eq!(*cell.borrow(), 5);
+    /// ```
+    pub fn borrow(&self) -> GcCellRef<'_, T> {
+        GcCellRef {
+            inner: self.cell.borrow(),
+        }
+    }
+
+    /// Immutably borrows the wrapped value, returning an error if the value is
+    /// currently mutably borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::GcCell;
+    ///
+    /// let cell = GcCell::new(5);
+    /// {
+    ///     let _writer = cell.borrow_mut();
+    ///     assert!(cell.try_borrow().is_err());
+    /// }
+    /// assert!(cell.try_borrow().is_ok());
+    /// ```
+    pub fn try_borrow(&self) -> Result<GcCellRef<'_, T>, BorrowError> {
+        self.cell.try_borrow().map(|inner| GcCellRef { inner })
+    }
+
+    /// Mutably borrows the wrapped value.
+    ///
+    /// Marks the cell dirty. Until the returned guard is dropped, collections
+    /// defer scanning the contents and keep everything the cell points to
+    /// alive.
+    ///
+    /// While [compacting], a collection can move the object that holds the
+    /// cell and leave the guard dangling. [Pin] the object before holding the
+    /// guard across an allocation or a collection.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::GcCell;
+    ///
+    /// let cell = GcCell::new(5);
+    /// *cell.borrow_mut() += 1;
+    /// assert_eq!(*cell.borrow(), 6);
+    /// ```
+    ///
+    /// [compacting]: ../struct.Proxy.html#method.set_compacting
+    /// [Pin]: ../struct.Proxy.html#method.pin
+    pub fn borrow_mut(&self) -> GcCellRefMut<'_, T> {
+        let inner = self.cell.borrow_mut();
+        self.dirty.set(true);
+        GcCellRefMut { inner }
+    }
+
+    /// Mutably borrows the wrapped value, returning an error if the value is
+    /// currently borrowed. Marks the cell dirty if the borrow succeeds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::GcCell;
+    ///
+    /// let cell = GcCell::new(5);
+    /// {
+    ///     let _reader = cell.borrow();
+    ///     assert!(cell.try_borrow_mut().is_err());
+    /// }
+    /// assert!(cell.try_borrow_mut().is_ok());
+    /// ```
+    pub fn try_borrow_mut(&self) -> Result<GcCellRefMut<'_, T>, BorrowMutError> {
+        let inner = self.cell.try_borrow_mut()?;
+        self.dirty.set(true);
+        Ok(GcCellRefMut { inner })
+    }
+
+    /// Returns a mutable reference to the wrapped value.
+    ///
+    /// No borrow tracking is needed since this borrows the `GcCell` mutably,
+    /// but the cell is still marked dirty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::GcCell;
+    ///
+    /// let mut cell = GcCell::new(5);
+    /// *cell.get_mut() += 1;
+    /// assert_eq!(*cell.borrow(), 6);
+    /// ```
+    pub fn get_mut(&mut self) -> &mut T {
+        *self.dirty.get_mut() = true;
+        self.cell.get_mut()
+    }
+
+    /// Returns `true` if the cell has been mutably borrowed since a collection
+    /// last scanned its contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::{Collector, GcCell};
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let cell = proxy.alloc(GcCell::new(5));
+    /// assert!(!cell.is_dirty());
+    /// *cell.borrow_mut() += 1;
+    /// assert!(cell.is_dirty());
+    ///
+    /// proxy.run();
+    /// assert!(!cell.is_dirty());
+    /// ```
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+}
+
+impl<T: Trace + ?Sized> Trace for GcCell<T> {
+    /// Traces the inner object, unless it is mutably borrowed
+    fn trace(&self, tracer: &mut Tracer) {
+        // A writer may be halfway through changing the contents, so don't
+        // look at them. Not reporting the children makes the collector treat
+        // them as externally referenced, which keeps them alive.
+        if let Ok(contents) = self.cell.try_borrow() {
+            tracer.add_target(&*contents);
+            self.dirty.set(false);
+        }
+    }
+}
+
+impl<T: HeapSize + ?Sized> HeapSize for GcCell<T> {
+    /// Heap size of the inner value, or zero if it is mutably borrowed
+    fn heap_size(&self) -> usize {
+        self.cell.heap_size()
+    }
+}
+
+/// An immutable borrow of the contents of a [`GcCell`].
+///
+/// [`GcCell`]: struct.GcCell.html
+#[derive(Debug)]
+pub struct GcCellRef<'b, T: ?Sized + 'b> {
+    inner: Ref<'b, T>,
+}
+
+impl<'b, T: ?Sized> Deref for GcCellRef<'b, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+/// A mutable borrow of the contents of a [`GcCell`].
+///
+/// Creating one marks the cell dirty, recording a write barrier. While it is
+/// alive, collections don't scan the cell's contents and keep everything they
+/// point to alive.
+///
+/// [`GcCell`]: struct.GcCell.html
+#[derive(Debug)]
+pub struct GcCellRefMut<'b, T: ?Sized + 'b> {
+    inner: RefMut<'b, T>,
+}
+
+impl<'b, T: ?Sized> Deref for GcCellRefMut<'b, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<'b, T: ?Sized> DerefMut for GcCellRefMut<'b, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Collector, Gc};
+
+    struct Node<'a> {
+        next: GcCell<Option<Gc<'a, Node<'a>>>>,
+    }
+    impl<'a> Trace for Node<'a> {
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer.add_target(&self.next);
+        }
+    }
+    fn node<'a>() -> Node<'a> {
+        Node {
+            next: GcCell::new(None),
+        }
+    }
+
+    #[test]
+    fn collect_while_mutably_borrowed() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        let head = proxy.alloc(node());
+        {
+            let mut next = head.next.borrow_mut();
+            *next = Some(proxy.alloc(node()));
+            proxy.run();
+            assert_eq!(proxy.num_tracked(), 2);
+        }
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 2);
+    }
+
+    #[test]
+    fn borrowed_children_collected_after_release() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        {
+            let a = proxy.alloc(node());
+            let b = proxy.alloc(node());
+            *a.next.borrow_mut() = Some(b.clone());
+            let mut b_next = b.next.borrow_mut();
+            *b_next = Some(a.clone());
+            drop(a);
+
+            // Tracing `b` would have panicked with a `RefCell`
+            proxy.run();
+            assert_eq!(proxy.num_tracked(), 2);
+        }
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 0);
+    }
+
+    #[test]
+    fn stays_dirty_until_rescanned() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        let head = proxy.alloc(node());
+        assert!(!head.next.is_dirty());
+        {
+            let mut next = head.next.borrow_mut();
+            *next = Some(proxy.alloc(node()));
+            proxy.run();
+            assert!(head.next.is_dirty());
+        }
+        assert!(head.next.is_dirty());
+        proxy.run();
+        assert!(!head.next.is_dirty());
+
+        assert!(head.next.try_borrow_mut().is_ok());
+        assert!(head.next.is_dirty());
+    }
+}
@@ -0,0 +1,165 @@
+//! Closures that can capture `Gc`s.
+//!
+//! A Rust closure can't implement [`Trace`], so the collector can't see the
+//! [`Gc`]s it captures. A [`GcFn`] keeps its captures as a separate value
+//! next to a plain function pointer, so the captures can be traced. It is
+//! stored with [`Proxy::alloc_closure`].
+//!
+//! # Examples
+//!
+//! ```
+//! use ters_gc::Collector;
+//!
+//! let mut col = Collector::new();
+//! let mut proxy = col.proxy();
+//!
+//! let clicks = proxy.alloc(std::cell::Cell::new(0));
+//! let on_click = proxy.alloc_closure(clicks.clone(), |clicks, (times,): (u32,)| {
+//!     clicks.set(clicks.get() + times);
+//! });
+//!
+//! on_click.call((2,));
+//! assert_eq!(clicks.get(), 2);
+//! ```
+//!
+//! [`GcFn`]: struct.GcFn.html
+//! [`Gc`]: ../ptr/struct.Gc.html
+//! [`Trace`]: ../trace/trait.Trace.html
+//! [`Proxy::alloc_closure`]: ../struct.Proxy.html#method.alloc_closure
+
+use std::fmt;
+use trace::{Trace, Tracer};
+
+/// A function together with the values it captured, callable with arguments
+/// of type `Args`, which is a tuple of all of the arguments.
+///
+/// Used behind a [`Gc`], as returned by [`Proxy::alloc_closure`].
+///
+/// [`Gc`]: ../ptr/struct.Gc.html
+/// [`Proxy::alloc_closure`]: ../struct.Proxy.html#method.alloc_closure
+#[repr(transparent)]
+pub struct GcFn<'a, Args, Ret> {
+    closure: dyn Closure<Args, Ret> + 'a,
+}
+
+impl<'a, Args: 'a, Ret: 'a> GcFn<'a, Args, Ret> {
+    pub(crate) fn new_boxed<C: Trace + 'a>(
+        captures: C,
+        function: fn(&C, Args) -> Ret,
+    ) -> Box<GcFn<'a, Args, Ret>> {
+        let closure: Box<dyn Closure<Args, Ret> + 'a> = Box::new(Captured { captures, function });
+        // Unsafe is fine since `GcFn` is a transparent wrapper around the
+        // trait object, so they have the same layout and metadata
+        unsafe { Box::from_raw(Box::into_raw(closure) as *mut GcFn<'a, Args, Ret>) }
+    }
+}
+
+impl<'a, Args, Ret> GcFn<'a, Args, Ret> {
+    /// Calls the function with the captures and `args`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let base = proxy.alloc(10);
+    /// let add = proxy.alloc_closure(base, |base, (a, b): (i32, i32)| **base + a + b);
+    /// assert_eq!(add.call((1, 2)), 13);
+    /// ```
+    pub fn call(&self, args: Args) -> Ret {
+        self.closure.call(args)
+    }
+}
+
+impl<'a, Args, Ret> Trace for GcFn<'a, Args, Ret> {
+    /// Traces the captures
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.add_target(&self.closure);
+    }
+}
+
+impl<'a, Args, Ret> fmt::Debug for GcFn<'a, Args, Ret> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("GcFn { .. }")
+    }
+}
+
+/// Calls a function with its captures, without naming their type
+trait Closure<Args, Ret>: Trace {
+    fn call(&self, args: Args) -> Ret;
+}
+
+struct Captured<C, Args, Ret> {
+    captures: C,
+    function: fn(&C, Args) -> Ret,
+}
+
+impl<C: Trace, Args, Ret> Closure<Args, Ret> for Captured<C, Args, Ret> {
+    fn call(&self, args: Args) -> Ret {
+        (self.function)(&self.captures, args)
+    }
+}
+
+impl<C: Trace, Args, Ret> Trace for Captured<C, Args, Ret> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.add_target(&self.captures);
+    }
+    fn needs_trace() -> bool {
+        C::needs_trace()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Collector, Gc, GcCell};
+
+    struct Node<'a> {
+        handler: GcCell<Option<Gc<'a, GcFn<'a, (), usize>>>>,
+    }
+    impl<'a> Trace for Node<'a> {
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer.add_target(&self.handler);
+        }
+    }
+
+    #[test]
+    fn captures_are_traced() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        {
+            let node = proxy.alloc(Node {
+                handler: GcCell::new(None),
+            });
+            let handler = proxy.alloc_closure(node.clone(), |node, ()| Gc::strong_count(node));
+            *node.handler.borrow_mut() = Some(handler.clone());
+            assert_eq!(handler.call(()), 2);
+
+            proxy.run();
+            assert_eq!(proxy.num_tracked(), 2);
+        }
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 0);
+    }
+
+    #[test]
+    fn captures_are_dropped() {
+        use std::rc::Rc;
+
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        let shared = Rc::new(5);
+        {
+            let get = proxy.alloc_closure(shared.clone(), |shared, ()| **shared);
+            assert_eq!(get.call(()), 5);
+            assert_eq!(Rc::strong_count(&shared), 2);
+        }
+        proxy.run();
+        assert_eq!(Rc::strong_count(&shared), 1);
+    }
+}
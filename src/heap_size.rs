@@ -0,0 +1,691 @@
+//! Measuring the memory that values own outside of themselves.
+//!
+//! [`size_of`] only counts the bytes a value takes up inline. A `Vec` or a
+//! `String` also owns a buffer somewhere else on the heap. [`HeapSize`]
+//! reports those bytes.
+//!
+//! Objects stored with [`Proxy::alloc_measured`] have their heap size counted
+//! as [memory pressure] and shown in the [`heap_histogram`]. Deep sizes are
+//! opt-in: objects stored with [`Proxy::alloc`] never contribute, even if
+//! they implement [`HeapSize`], and only count the bytes of their gc box.
+//!
+//! # Standard Library Types
+//!
+//! [`HeapSize`] is implemented for the same standard library types as
+//! [`Trace`]. The sizes of hash tables, B-trees and linked lists are estimates
+//! that don't count the bookkeeping those collections store alongside their
+//! elements.
+//!
+//! ## Types of Note
+//!
+//! * [`Rc`] and [`Arc`]
+//!
+//! The shared allocation is split evenly between the strong pointers to it,
+//! so that it adds up to its full size once every pointer is counted. A
+//! pointer reached again while its allocation is being measured is part of a
+//! cycle, and counts as `0`.
+//!
+//! * [`RefCell`]
+//!
+//! Contents that are mutably borrowed while being measured count as `0`.
+//!
+//! * `Mutex` and `RwLock`
+//!
+//! Contents that are locked for writing while being measured count as `0`.
+//!
+//! * [`Gc`] and [`Weak`]
+//!
+//! Objects in the gc heap are counted separately, so pointers to them count
+//! as `0`.
+//!
+//! # Examples
+//!
+//! You can `#[derive(HeapSize)]`. Fields marked with `#[ignore_heap_size]`
+//! aren't counted, and don't have to implement [`HeapSize`].
+//!
+//! ```
+//! extern crate ters_gc;
+//! #[macro_use] extern crate ters_gc_derive;
+//!
+//! use ters_gc::heap_size::HeapSize;
+//!
+//! struct Opaque;
+//!
+//! #[derive(HeapSize)]
+//! struct Mesh {
+//!     name: String,
+//!     vertices: Vec<[f32; 3]>,
+//!     #[ignore_heap_size]
+//!     _gpu_buffer: Opaque,
+//! }
+//!
+//! let mesh = Mesh {
+//!     name: String::with_capacity(8),
+//!     vertices: Vec::with_capacity(100),
+//!     _gpu_buffer: Opaque,
+//! };
+//! assert_eq!(mesh.heap_size(), 8 + 100 * 12);
+//! ```
+//!
+//! Or implement it manually.
+//!
+//! ```
+//! use ters_gc::heap_size::HeapSize;
+//!
+//! struct Mesh {
+//!     name: String,
+//!     vertices: Vec<[f32; 3]>,
+//! }
+//!
+//! impl HeapSize for Mesh {
+//!     fn heap_size(&self) -> usize {
+//!         self.name.heap_size() + self.vertices.heap_size()
+//!     }
+//! }
+//! ```
+//!
+//! [`HeapSize`]: trait.HeapSize.html
+//! [`Trace`]: ../trace/trait.Trace.html
+//! [`Gc`]: ../ptr/struct.Gc.html
+//! [`Weak`]: ../ptr/struct.Weak.html
+//! [`Proxy::alloc`]: ../struct.Proxy.html#method.alloc
+//! [`Proxy::alloc_measured`]: ../struct.Proxy.html#method.alloc_measured
+//! [memory pressure]: ../struct.Proxy.html#method.add_memory_pressure
+//! [`heap_histogram`]: ../struct.Proxy.html#method.heap_histogram
+//! [`size_of`]: https://doc.rust-lang.org/std/mem/fn.size_of.html
+//! [`Rc`]: https://doc.rust-lang.org/std/rc/struct.Rc.html
+//! [`Arc`]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+//! [`RefCell`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
+
+use ptr::{Gc, GcAny, Weak};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::mem::size_of_val;
+
+/// Trait for types that can report how much heap memory they own.
+pub trait HeapSize {
+    /// Returns the number of bytes of heap memory owned by `self`, not
+    /// counting the bytes of `self` itself.
+    fn heap_size(&self) -> usize;
+}
+
+/// Returns the number of bytes `val` takes up, including the heap memory it
+/// owns.
+///
+/// # Examples
+///
+/// ```
+/// use ters_gc::heap_size::deep_size_of;
+/// use std::mem::size_of;
+///
+/// let nums: Vec<u32> = Vec::with_capacity(4);
+/// assert_eq!(deep_size_of(&nums), size_of::<Vec<u32>>() + 16);
+/// ```
+pub fn deep_size_of<T: HeapSize + ?Sized>(val: &T) -> usize {
+    size_of_val(val) + val.heap_size()
+}
+
+thread_local! {
+    /// Shared allocations that are being measured further up the stack
+    static MEASURING: RefCell<HashSet<*const ()>> = RefCell::new(HashSet::new());
+}
+
+/// Stops measuring the allocation once it's dropped, even on panic
+struct Measuring(*const ());
+
+impl Drop for Measuring {
+    fn drop(&mut self) {
+        MEASURING.with(|measuring| measuring.borrow_mut().remove(&self.0));
+    }
+}
+
+/// Measures the shared allocation at `ptr` with `measure`, unless it's
+/// already being measured, in which case it's part of a cycle and counts as 0
+fn measure_shared<F: FnOnce() -> usize>(ptr: *const (), measure: F) -> usize {
+    if !MEASURING.with(|measuring| measuring.borrow_mut().insert(ptr)) {
+        return 0;
+    }
+    let _measuring = Measuring(ptr);
+    measure()
+}
+
+impl<'a, T: ?Sized> HeapSize for Gc<'a, T> {
+    /// Zero, the object is counted separately
+    #[inline]
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+impl<'a> HeapSize for GcAny<'a> {
+    /// Zero, the object is counted separately
+    #[inline]
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+impl<'a, T: ?Sized> HeapSize for Weak<'a, T> {
+    /// Zero, the object is counted separately
+    #[inline]
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+mod heap_size_impls {
+    use super::{deep_size_of, measure_shared, HeapSize};
+    use std;
+    use std::cmp::Ord;
+    use std::hash::BuildHasher;
+    use std::mem::size_of;
+    use std::sync::TryLockError;
+
+    macro_rules! zero_impls {
+        ($($T:ty)+) => {
+            $(
+                impl HeapSize for $T {
+                    /// Zero
+                    #[inline]
+                    fn heap_size(&self) -> usize {
+                        0
+                    }
+                }
+             )+
+        }
+    }
+    zero_impls! {
+        ()
+        bool
+        i8 i16 i32 i64 i128
+        u8 u16 u32 u64 u128
+        isize usize
+        f32 f64
+        char str
+        std::cmp::Ordering
+        std::ffi::CStr
+        std::ffi::OsStr
+        std::fs::DirEntry std::fs::File
+        std::fs::FileType std::fs::Metadata
+        std::fs::OpenOptions std::fs::Permissions
+        std::io::Repeat std::io::Sink
+        std::io::Stderr std::io::Stdin
+        std::io::Stdout std::io::ErrorKind
+        std::net::Ipv4Addr std::net::Ipv6Addr
+        std::net::SocketAddrV4 std::net::SocketAddrV6
+        std::net::TcpStream std::net::UdpSocket
+        std::net::IpAddr std::net::SocketAddr
+        std::num::NonZeroI8 std::num::NonZeroI16
+        std::num::NonZeroI32 std::num::NonZeroI64
+        std::num::NonZeroI128 std::num::NonZeroIsize
+        std::num::NonZeroU8 std::num::NonZeroU16
+        std::num::NonZeroU32 std::num::NonZeroU64
+        std::num::NonZeroU128 std::num::NonZeroUsize
+        std::ops::RangeFull
+        std::path::Path
+        std::sync::Condvar
+        std::time::Duration std::time::Instant
+        std::time::SystemTime
+    }
+    impl HeapSize for String {
+        /// The capacity of the buffer
+        fn heap_size(&self) -> usize {
+            self.capacity()
+        }
+    }
+    impl HeapSize for std::ffi::CString {
+        /// The length of the string, including the nul terminator
+        fn heap_size(&self) -> usize {
+            self.as_bytes_with_nul().len()
+        }
+    }
+    impl HeapSize for std::ffi::OsString {
+        /// The capacity of the buffer
+        fn heap_size(&self) -> usize {
+            self.capacity()
+        }
+    }
+    impl HeapSize for std::path::PathBuf {
+        /// The capacity of the buffer
+        fn heap_size(&self) -> usize {
+            self.capacity()
+        }
+    }
+    impl<T: ?Sized> HeapSize for &T {
+        /// Zero, borrowed data isn't owned
+        #[inline]
+        fn heap_size(&self) -> usize {
+            0
+        }
+    }
+    macro_rules! zero_fn_impl {
+        ($($T:tt)*) => {
+            impl<$($T,)* R> HeapSize for fn($($T),*) -> R {
+                /// Zero
+                #[inline]
+                fn heap_size(&self) -> usize {
+                    0
+                }
+            }
+        }
+    }
+    zero_fn_impl!();
+    zero_fn_impl!(Q);
+    zero_fn_impl!(Q W);
+    zero_fn_impl!(Q W E);
+    zero_fn_impl!(Q W E T);
+    impl<T: ?Sized> HeapSize for *const T {
+        /// Zero, pointed-to data isn't owned
+        #[inline]
+        fn heap_size(&self) -> usize {
+            0
+        }
+    }
+    impl<T: ?Sized> HeapSize for *mut T {
+        /// Zero, pointed-to data isn't owned
+        #[inline]
+        fn heap_size(&self) -> usize {
+            0
+        }
+    }
+
+    impl<T: HeapSize> HeapSize for [T] {
+        /// Sum of the elements' heap sizes
+        #[inline]
+        fn heap_size(&self) -> usize {
+            self.iter().map(HeapSize::heap_size).sum()
+        }
+    }
+    impl<T: HeapSize, const N: usize> HeapSize for [T; N] {
+        /// Sum of the elements' heap sizes
+        fn heap_size(&self) -> usize {
+            self[..].heap_size()
+        }
+    }
+    macro_rules! tuple_impls {
+        ($($T:ident)+) => {
+            impl<$($T: HeapSize),+> HeapSize for ($($T,)+) {
+                /// Sum of the elements' heap sizes
+                #[allow(non_snake_case)]
+                fn heap_size(&self) -> usize {
+                    let ($(ref $T,)+) = *self;
+                    0 $(+ $T.heap_size())+
+                }
+            }
+        }
+    }
+    tuple_impls!(A);
+    tuple_impls!(A B);
+    tuple_impls!(A B C);
+    tuple_impls!(A B C D);
+    tuple_impls!(A B C D E);
+    tuple_impls!(A B C D E F);
+    tuple_impls!(A B C D E F G);
+    tuple_impls!(A B C D E F G H);
+    tuple_impls!(A B C D E F G H I);
+    tuple_impls!(A B C D E F G H I J);
+    tuple_impls!(A B C D E F G H I J K);
+    tuple_impls!(A B C D E F G H I J K L);
+    impl<T: ?Sized> HeapSize for std::marker::PhantomData<T> {
+        /// Zero
+        #[inline]
+        fn heap_size(&self) -> usize {
+            0
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::ops::Range<T> {
+        /// Heap sizes of both bounds
+        fn heap_size(&self) -> usize {
+            self.start.heap_size() + self.end.heap_size()
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::ops::RangeInclusive<T> {
+        /// Heap sizes of both bounds
+        fn heap_size(&self) -> usize {
+            self.start().heap_size() + self.end().heap_size()
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::ops::RangeFrom<T> {
+        /// Heap size of the start bound
+        fn heap_size(&self) -> usize {
+            self.start.heap_size()
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::ops::RangeTo<T> {
+        /// Heap size of the end bound
+        fn heap_size(&self) -> usize {
+            self.end.heap_size()
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::ops::RangeToInclusive<T> {
+        /// Heap size of the end bound
+        fn heap_size(&self) -> usize {
+            self.end.heap_size()
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::cmp::Reverse<T> {
+        /// Heap size of the inner value
+        fn heap_size(&self) -> usize {
+            self.0.heap_size()
+        }
+    }
+
+    impl<T: HeapSize> HeapSize for Option<T> {
+        /// Heap size of the inner value if `Some`
+        fn heap_size(&self) -> usize {
+            self.as_ref().map_or(0, HeapSize::heap_size)
+        }
+    }
+    impl<T: HeapSize, E: HeapSize> HeapSize for Result<T, E> {
+        /// Heap size of whichever value is present
+        fn heap_size(&self) -> usize {
+            match self {
+                Ok(val) => val.heap_size(),
+                Err(err) => err.heap_size(),
+            }
+        }
+    }
+    impl<T: HeapSize + ?Sized> HeapSize for std::pin::Pin<Box<T>> {
+        /// The boxed value, including its own heap memory
+        fn heap_size(&self) -> usize {
+            deep_size_of::<T>(self)
+        }
+    }
+    impl<T: HeapSize + ?Sized> HeapSize for Box<T> {
+        /// The boxed value, including its own heap memory
+        fn heap_size(&self) -> usize {
+            deep_size_of::<T>(self)
+        }
+    }
+    impl<'a, T: ToOwned + ?Sized> HeapSize for std::borrow::Cow<'a, T>
+    where
+        T::Owned: HeapSize,
+    {
+        /// Heap size of the owned value, or zero if borrowed
+        fn heap_size(&self) -> usize {
+            match self {
+                std::borrow::Cow::Borrowed(_) => 0,
+                std::borrow::Cow::Owned(owned) => owned.heap_size(),
+            }
+        }
+    }
+    impl<T: HeapSize> HeapSize for Vec<T> {
+        /// The buffer plus the elements' heap sizes
+        fn heap_size(&self) -> usize {
+            self.capacity() * size_of::<T>() + self[..].heap_size()
+        }
+    }
+    impl<T: HeapSize + ?Sized> HeapSize for std::rc::Rc<T> {
+        /// An even share of the allocation holding the reference counts and
+        /// the value
+        fn heap_size(&self) -> usize {
+            measure_shared(std::rc::Rc::as_ptr(self) as *const (), || {
+                let shared = 2 * size_of::<usize>() + deep_size_of::<T>(self);
+                shared / std::rc::Rc::strong_count(self)
+            })
+        }
+    }
+    impl<T: ?Sized> HeapSize for std::rc::Weak<T> {
+        /// Zero
+        #[inline]
+        fn heap_size(&self) -> usize {
+            0
+        }
+    }
+    impl<T: HeapSize + ?Sized> HeapSize for std::sync::Arc<T> {
+        /// An even share of the allocation holding the reference counts and
+        /// the value
+        fn heap_size(&self) -> usize {
+            measure_shared(std::sync::Arc::as_ptr(self) as *const (), || {
+                let shared = 2 * size_of::<usize>() + deep_size_of::<T>(self);
+                shared / std::sync::Arc::strong_count(self)
+            })
+        }
+    }
+    impl<T: ?Sized> HeapSize for std::sync::Weak<T> {
+        /// Zero
+        #[inline]
+        fn heap_size(&self) -> usize {
+            0
+        }
+    }
+    impl<T: HeapSize + Default> HeapSize for std::cell::Cell<T> {
+        /// Heap size of the inner value, which is briefly taken out of the cell
+        fn heap_size(&self) -> usize {
+            let contents = self.take();
+            let size = contents.heap_size();
+            self.set(contents);
+            size
+        }
+    }
+    impl<T: HeapSize + ?Sized> HeapSize for std::sync::Mutex<T> {
+        /// Heap size of the inner value, or zero if it is locked
+        fn heap_size(&self) -> usize {
+            match self.try_lock() {
+                Ok(contents) => contents.heap_size(),
+                Err(TryLockError::Poisoned(err)) => err.into_inner().heap_size(),
+                Err(TryLockError::WouldBlock) => 0,
+            }
+        }
+    }
+    impl<T: HeapSize + ?Sized> HeapSize for std::sync::RwLock<T> {
+        /// Heap size of the inner value, or zero if it is locked for writing
+        fn heap_size(&self) -> usize {
+            match self.try_read() {
+                Ok(contents) => contents.heap_size(),
+                Err(TryLockError::Poisoned(err)) => err.into_inner().heap_size(),
+                Err(TryLockError::WouldBlock) => 0,
+            }
+        }
+    }
+    impl<T: HeapSize + ?Sized> HeapSize for std::cell::RefCell<T> {
+        /// Heap size of the inner value, or zero if it is mutably borrowed
+        fn heap_size(&self) -> usize {
+            self.try_borrow().map_or(0, |val| val.heap_size())
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::collections::VecDeque<T> {
+        /// The buffer plus the elements' heap sizes
+        fn heap_size(&self) -> usize {
+            let elems: usize = self.iter().map(HeapSize::heap_size).sum();
+            self.capacity() * size_of::<T>() + elems
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::collections::LinkedList<T> {
+        /// The nodes plus the elements' heap sizes
+        fn heap_size(&self) -> usize {
+            let node_size = size_of::<T>() + 2 * size_of::<usize>();
+            let elems: usize = self.iter().map(HeapSize::heap_size).sum();
+            self.len() * node_size + elems
+        }
+    }
+    impl<K: HeapSize, T: HeapSize, S: BuildHasher> HeapSize for std::collections::HashMap<K, T, S> {
+        /// The table plus the keys' and values' heap sizes
+        fn heap_size(&self) -> usize {
+            let entries: usize = self
+                .iter()
+                .map(|(key, val)| key.heap_size() + val.heap_size())
+                .sum();
+            self.capacity() * size_of::<(K, T)>() + entries
+        }
+    }
+    impl<K: HeapSize, T: HeapSize> HeapSize for std::collections::BTreeMap<K, T> {
+        /// The entries plus the keys' and values' heap sizes
+        fn heap_size(&self) -> usize {
+            let entries: usize = self
+                .iter()
+                .map(|(key, val)| key.heap_size() + val.heap_size())
+                .sum();
+            self.len() * size_of::<(K, T)>() + entries
+        }
+    }
+    impl<T: HeapSize, S: BuildHasher> HeapSize for std::collections::HashSet<T, S> {
+        /// The table plus the values' heap sizes
+        fn heap_size(&self) -> usize {
+            let elems: usize = self.iter().map(HeapSize::heap_size).sum();
+            self.capacity() * size_of::<T>() + elems
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::collections::BTreeSet<T> {
+        /// The values plus their heap sizes
+        fn heap_size(&self) -> usize {
+            let elems: usize = self.iter().map(HeapSize::heap_size).sum();
+            self.len() * size_of::<T>() + elems
+        }
+    }
+    impl<T: HeapSize + Ord> HeapSize for std::collections::BinaryHeap<T> {
+        /// The buffer plus the values' heap sizes
+        fn heap_size(&self) -> usize {
+            let elems: usize = self.iter().map(HeapSize::heap_size).sum();
+            self.capacity() * size_of::<T>() + elems
+        }
+    }
+    impl<T: HeapSize, U: HeapSize> HeapSize for std::io::Chain<T, U> {
+        /// Heap sizes of both readers
+        fn heap_size(&self) -> usize {
+            let (first, second) = self.get_ref();
+            first.heap_size() + second.heap_size()
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::io::Cursor<T> {
+        /// Heap size of the inner value
+        fn heap_size(&self) -> usize {
+            self.get_ref().heap_size()
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::io::Take<T> {
+        /// Heap size of the inner reader
+        fn heap_size(&self) -> usize {
+            self.get_ref().heap_size()
+        }
+    }
+    impl<T: HeapSize> HeapSize for std::num::Wrapping<T> {
+        /// Heap size of the inner value
+        fn heap_size(&self) -> usize {
+            self.0.heap_size()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::mem::size_of;
+    use std::rc::Rc;
+
+    #[test]
+    fn counts_buffers() {
+        let name = String::with_capacity(10);
+        assert_eq!(name.heap_size(), 10);
+
+        let nums: Vec<u64> = Vec::with_capacity(3);
+        assert_eq!(nums.heap_size(), 24);
+
+        let names = vec![String::with_capacity(5), String::with_capacity(7)];
+        let buffer = names.capacity() * size_of::<String>();
+        assert_eq!(names.heap_size(), buffer + 12);
+    }
+
+    #[test]
+    fn box_counts_contents() {
+        let boxed = Box::new(String::with_capacity(4));
+        assert_eq!(boxed.heap_size(), size_of::<String>() + 4);
+
+        let unsized_box: Box<[u16]> = vec![1, 2, 3].into_boxed_slice();
+        assert_eq!(unsized_box.heap_size(), 6);
+    }
+
+    #[test]
+    fn rc_is_split_between_owners() {
+        let rc = Rc::new(0u64);
+        let whole = 2 * size_of::<usize>() + 8;
+        assert_eq!(rc.heap_size(), whole);
+
+        let rc2 = rc.clone();
+        assert_eq!(rc.heap_size() + rc2.heap_size(), whole);
+        assert_eq!(Rc::downgrade(&rc).heap_size(), 0);
+    }
+
+    #[test]
+    fn rc_cycle_is_counted_once() {
+        struct Link(RefCell<Option<Rc<Link>>>);
+        impl HeapSize for Link {
+            fn heap_size(&self) -> usize {
+                self.0.heap_size()
+            }
+        }
+
+        let first = Rc::new(Link(RefCell::new(None)));
+        let second = Rc::new(Link(RefCell::new(Some(first.clone()))));
+        *first.0.borrow_mut() = Some(second.clone());
+
+        // Each allocation has two owners. The one measured first counts its
+        // share of the other, which counts `0` for the way back.
+        let whole = 2 * size_of::<usize>() + size_of::<Link>();
+        assert_eq!(first.heap_size(), (whole + whole / 2) / 2);
+        assert_eq!(second.heap_size(), (whole + whole / 2) / 2);
+        *first.0.borrow_mut() = None;
+    }
+
+    #[test]
+    fn borrowed_refcell_counts_nothing() {
+        let cell = RefCell::new(String::with_capacity(16));
+        assert_eq!(cell.heap_size(), 16);
+        let _borrow = cell.borrow_mut();
+        assert_eq!(cell.heap_size(), 0);
+    }
+
+    #[test]
+    fn locked_mutex_counts_nothing() {
+        use std::sync::Mutex;
+
+        let mutex = Mutex::new(String::with_capacity(16));
+        assert_eq!(mutex.heap_size(), 16);
+        let _lock = mutex.lock().unwrap();
+        assert_eq!(mutex.heap_size(), 0);
+    }
+
+    #[test]
+    fn tuples_and_large_arrays_sum_elements() {
+        let pair = (String::with_capacity(3), Vec::<u8>::with_capacity(5));
+        assert_eq!(pair.heap_size(), 8);
+
+        let array: [String; 50] = std::array::from_fn(|_| String::with_capacity(2));
+        assert_eq!(array.heap_size(), 100);
+    }
+
+    #[test]
+    fn maps_count_entries() {
+        let mut map = HashMap::new();
+        map.insert(1u32, String::with_capacity(3));
+        let table = map.capacity() * size_of::<(u32, String)>();
+        assert_eq!(map.heap_size(), table + 3);
+    }
+
+    #[test]
+    fn maps_with_other_hashers_and_unhashable_keys() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::{BTreeMap, BTreeSet};
+        use std::hash::BuildHasherDefault;
+
+        // Ordered, but not hashable
+        #[derive(PartialEq, Eq, PartialOrd, Ord)]
+        struct Rank(u8);
+        impl HeapSize for Rank {
+            fn heap_size(&self) -> usize {
+                0
+            }
+        }
+
+        let mut map: HashMap<u8, String, BuildHasherDefault<DefaultHasher>> = HashMap::default();
+        map.insert(1, String::with_capacity(3));
+        let table = map.capacity() * size_of::<(u8, String)>();
+        assert_eq!(map.heap_size(), table + 3);
+
+        let mut ranks = BTreeMap::new();
+        ranks.insert(Rank(1), String::with_capacity(4));
+        assert_eq!(ranks.heap_size(), size_of::<(Rank, String)>() + 4);
+        let set: BTreeSet<Rank> = vec![Rank(1), Rank(2)].into_iter().collect();
+        assert_eq!(set.heap_size(), 2 * size_of::<Rank>());
+    }
+}
@@ -48,7 +48,9 @@
 //!
 //! Collection of unreachable memory only happens when either you call
 //! [`Proxy::run`], or you store something in the gc heap and the heap is above
-//! a size threshold.
+//! a size threshold. Memory that objects keep alive outside of the gc heap can
+//! be counted towards that with [`Proxy::add_memory_pressure`]. In
+//! [stress mode] every allocation collects first.
 //!
 //! The primary smart pointer type is [`Gc`]. It keeps the allocated memory alive
 //! and dereferences to a shared reference. Its API surface is meant to mimick
@@ -68,7 +70,14 @@
 //!
 //! To make it easy, you can `#[derive(Trace)]`.
 //!
-//! [`Trace`] is implemented for many of the types in `std`.
+//! [`Trace`] is implemented for many of the types in `std`. Map keys and
+//! [`Result`] errors are the exception: they are never traced, so a [`Gc`]
+//! used as a map key or as an error **leaks** if it's part of a cycle. Wrap
+//! the map or result in a [`TraceAll`] to trace them.
+//!
+//! Types that own memory outside of themselves, like a `Vec`, can also
+//! implement [`HeapSize`] (or `#[derive(HeapSize)]`) and be stored with
+//! [`Proxy::alloc_measured`], so that the collector knows their real size.
 //!
 //! Check the [`trace module`] documentation for more information.
 //!
@@ -136,6 +145,17 @@
 //! Now that we know which objects are reachable and which are not we can free
 //! objects the client is no longer using.
 //!
+//! # Cargo Features
+//!
+//! * `debug-checks`: Adds [`Proxy::verify_heap`], which checks the gc heap
+//!   for broken invariants. Meant for use in tests.
+//! * `quarantine`: Overwrites freed objects with a poison pattern and holds
+//!   onto their memory for a while before releasing it. Accessing a freed
+//!   object through the gc heap then panics instead of reading reused memory.
+//! * `arrayvec`, `hashbrown`, `im`, `indexmap` and `smallvec`: Implement
+//!   [`Trace`] for the collections of the crate of the same name. Their maps
+//!   trace only values, like the `std` maps, unless wrapped in a [`TraceAll`].
+//!
 //! # Limitations
 //!
 //! ## You cannot dereference a [`Gc`] inside of a [`Drop::drop`] implementation
@@ -180,9 +200,14 @@
 //!
 //! None of the pointer types, nor [`Proxy`] should be [`Sync`] or [`Send`].
 //!
-//! ## Supports [`Sized`] type only
+//! ## Unsized values can't be coerced directly
 //!
-//! There are plans for unsized type support, but it is not yet implemented
+//! A `Gc<[T]>`, `Gc<str>` or `Gc<dyn Trait>` works like any other [`Gc`], but
+//! unlike a [`Box`] a [`Gc`] to a sized value doesn't coerce to one on stable
+//! Rust. Slices and strings can be stored with [`Proxy::alloc_slice`] and
+//! [`Proxy::alloc_str`], anything boxed with [`Proxy::alloc_boxed`], and an
+//! existing [`Gc`] can be turned into a trait object with the [`unsize!`]
+//! macro.
 //!
 //!
 //! [`Collector`]: struct.Collector.html
@@ -194,6 +219,16 @@
 //! [`trace module`]: trace/index.html
 //! [`Tracer`]: trace/struct.Tracer.html
 //! [`Proxy::run`]: struct.Proxy.html#method.run
+//! [`Proxy::add_memory_pressure`]: struct.Proxy.html#method.add_memory_pressure
+//! [`Proxy::alloc_measured`]: struct.Proxy.html#method.alloc_measured
+//! [`Proxy::alloc_slice`]: struct.Proxy.html#method.alloc_slice
+//! [`Proxy::alloc_str`]: struct.Proxy.html#method.alloc_str
+//! [`Proxy::alloc_boxed`]: struct.Proxy.html#method.alloc_boxed
+//! [`unsize!`]: macro.unsize.html
+//! [`HeapSize`]: heap_size/trait.HeapSize.html
+//! [`TraceAll`]: trace/struct.TraceAll.html
+//! [`Proxy::verify_heap`]: struct.Proxy.html#method.verify_heap
+//! [stress mode]: struct.Collector.html#method.set_stress
 //! [`Gc::is_alive`]: ptr/struct.Gc.html#method.is_alive
 //! [`Gc::get`]: ptr/struct.Gc.html#method.get
 //! [`upgrade`]: ptr/struct.Weak.html#method.upgrade
@@ -204,12 +239,10 @@
 //! [`Send`]: https://doc.rust-lang.org/std/marker/trait.Send.html
 //! [`Box`]: https://doc.rust-lang.org/std/boxed/struct.Box.html
 //! [`Vec`]: https://doc.rust-lang.org/std/vec/struct.Vec.html
+//! [`Result`]: https://doc.rust-lang.org/std/result/enum.Result.html
 //! [`Rc`]: https://doc.rust-lang.org/std/rc/struct.Rc.html
-//! [`Sized`]: https://doc.rust-lang.org/std/marker/trait.Sized.html
 //! [`Tiny Garbage Collector`]: https://github.com/orangeduck/tgc
 
-// #![feature(unsize, coerce_unsized)]
-
 // Keep the version number in sync with crate version
 #![doc(html_root_url = "https://docs.rs/ters_gc/0.1.0")]
 #![deny(
@@ -223,16 +256,82 @@
     unused_qualifications
 )]
 
+#[cfg(feature = "arrayvec")]
+extern crate arrayvec;
+#[cfg(feature = "hashbrown")]
+extern crate hashbrown;
+#[cfg(feature = "im")]
+extern crate im;
+#[cfg(feature = "indexmap")]
+extern crate indexmap;
+#[cfg(feature = "smallvec")]
+extern crate smallvec;
+
+/// Turns a [`Gc`] into one to an unsized type the value coerces to, like a
+/// trait object or a slice. The type to turn it into comes after the `Gc`.
+///
+/// Works like the unsizing coercion of a `Box` or an `Rc`, which `Gc` can't
+/// take part in on stable Rust.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate ters_gc;
+///
+/// use std::fmt::Debug;
+/// use ters_gc::{Collector, Gc};
+///
+/// # fn main() {
+/// let mut col = Collector::new();
+/// let mut proxy = col.proxy();
+///
+/// let nums: Gc<[u32]> = unsize!(proxy.alloc([1, 2, 3]), [u32]);
+/// assert_eq!(nums.len(), 3);
+///
+/// let debug = unsize!(proxy.alloc("hi"), dyn Debug);
+/// assert_eq!(format!("{:?}", debug), "Gc { value: \"hi\" }");
+/// # }
+/// ```
+///
+/// [`Gc`]: ptr/struct.Gc.html
+#[macro_export]
+macro_rules! unsize {
+    ($gc:expr, $target:ty) => {
+        match $gc {
+            // Safe since only an unsizing coercion can turn the pointer into a
+            // `*const $target`
+            gc => unsafe { $crate::Gc::unsize(gc, |ptr| -> *const $target { ptr }) },
+        }
+    };
+}
+
+pub mod cell;
+pub use cell::GcCell;
+pub mod closure;
+pub use closure::GcFn;
+pub mod heap_size;
 pub mod ptr;
-pub use ptr::Gc;
+pub use ptr::{Gc, GcAny};
 mod allocator;
+mod slab;
+pub mod stats;
 pub mod trace;
+#[cfg(feature = "debug-checks")]
+pub mod verify;
 
 use allocator::AllocInfo;
 use allocator::Allocator;
-use ptr::GcBox;
+use heap_size::HeapSize;
+use ptr::{GcBox, Handle, Weak};
+use stats::{AllocSite, HistogramBuilder, HistogramEntry, LeakSuspect, SlabStats, TraceSuspect};
+use std::alloc::GlobalAlloc;
+use std::backtrace::Backtrace;
+use std::collections::HashSet;
 use std::marker::PhantomData;
+use std::panic::Location;
 use std::ptr::NonNull;
+use std::rc::Rc;
 use trace::Trace;
 
 /// Used for type-erasure
@@ -245,11 +344,16 @@ pub(crate) enum UntypedGcBox {}
 /// Made as a trait to add a little type safety and readability
 trait AsTyped {
     fn as_typed<T>(&self) -> NonNull<GcBox<T>>;
+    /// Takes the metadata of `T` from `template`
+    fn as_typed_like<T: ?Sized>(&self, template: *const GcBox<T>) -> NonNull<GcBox<T>>;
 }
 impl AsTyped for NonNull<UntypedGcBox> {
     fn as_typed<T>(&self) -> NonNull<GcBox<T>> {
         self.cast()
     }
+    fn as_typed_like<T: ?Sized>(&self, template: *const GcBox<T>) -> NonNull<GcBox<T>> {
+        ptr::with_metadata_of(*self, template)
+    }
 }
 /// Cast a NonNull pointer to a Gc allocation into a type-erased version
 /// for storage.
@@ -258,12 +362,16 @@ impl AsTyped for NonNull<UntypedGcBox> {
 trait AsUntyped {
     fn as_untyped(&self) -> NonNull<UntypedGcBox>;
 }
-impl<T> AsUntyped for NonNull<GcBox<T>> {
+impl<T: ?Sized> AsUntyped for NonNull<GcBox<T>> {
     fn as_untyped(&self) -> NonNull<UntypedGcBox> {
         self.cast()
     }
 }
 
+/// Bytes of memory pressure needed to trigger collection on top of the
+/// pressure left after the last collection
+const MIN_PRESSURE_THRESHOLD: usize = 1 << 20;
+
 /// State container for grabage collection.
 /// Access to gc API must go through a [`Proxy`].
 ///
@@ -277,11 +385,26 @@ pub struct Collector {
     load_factor: f64,
     sweep_factor: f64,
     paused: bool,
+    track_alloc_sites: bool,
+    backtrace_sample_rate: usize,
+    sites_since_backtrace: usize,
+    audit_runs: usize,
+    untraced_holders: Vec<TraceSuspect>, // Types that audits saw drop untraced references
+    stress: bool,
+    compacting: bool,
+    memory_pressure: usize, // Pressure not associated with an object
+    object_pressure: usize, // Sum of the pressure associated with tracked objects
+    pressure_threshold: usize,
+    sweep_batch: usize, // # of objects each allocation frees. 0 to free them during collection
+    pending_frees: Vec<NonNull<UntypedGcBox>>, // Unreachable objects that haven't been freed yet
 }
 
 impl Collector {
     /// Constructs a new `Collector`
     ///
+    /// Starts in [stress mode] if the `TERS_GC_STRESS` environment variable is
+    /// set to anything other than `0` or an empty string.
+    ///
     /// # Examples
     ///
     /// ```
@@ -289,6 +412,8 @@ impl Collector {
     ///
     /// let mut col = Collector::new();
     /// ```
+    ///
+    /// [stress mode]: #method.set_stress
     pub fn new() -> Collector {
         Collector {
             allocator: Allocator::new(),
@@ -296,9 +421,114 @@ impl Collector {
             load_factor: 0.9,
             sweep_factor: 0.5,
             paused: false,
+            track_alloc_sites: false,
+            backtrace_sample_rate: 0,
+            sites_since_backtrace: 0,
+            audit_runs: 0,
+            untraced_holders: Vec::new(),
+            stress: stress_env_enabled(),
+            compacting: false,
+            memory_pressure: 0,
+            object_pressure: 0,
+            pressure_threshold: MIN_PRESSURE_THRESHOLD,
+            sweep_batch: 0,
+            pending_frees: Vec::new(),
+        }
+    }
+
+    /// Constructs a new `Collector` that gets the memory for objects from
+    /// `allocator` instead of the global allocator.
+    ///
+    /// Small objects are packed into pages that are requested from `allocator`
+    /// as needed, and larger objects are allocated from it directly. All of it
+    /// is given back to `allocator` when the `Collector` is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::alloc::{GlobalAlloc, Layout, System};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use ters_gc::Collector;
+    ///
+    /// static IN_USE: AtomicUsize = AtomicUsize::new(0);
+    ///
+    /// struct Counting;
+    /// unsafe impl GlobalAlloc for Counting {
+    ///     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ///         IN_USE.fetch_add(layout.size(), Ordering::SeqCst);
+    ///         System.alloc(layout)
+    ///     }
+    ///     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    ///         IN_USE.fetch_sub(layout.size(), Ordering::SeqCst);
+    ///         System.dealloc(ptr, layout)
+    ///     }
+    /// }
+    ///
+    /// let mut col = Collector::with_allocator(Counting);
+    /// {
+    ///     let mut proxy = col.proxy();
+    ///     let _num = proxy.alloc(5);
+    ///     assert!(IN_USE.load(Ordering::SeqCst) > 0);
+    /// }
+    /// drop(col);
+    /// assert_eq!(IN_USE.load(Ordering::SeqCst), 0);
+    /// ```
+    pub fn with_allocator<A: GlobalAlloc + 'static>(allocator: A) -> Collector {
+        Collector {
+            allocator: Allocator::with_backing(Box::new(allocator)),
+            ..Collector::new()
         }
     }
 
+    /// Sets whether to run a full collection before every allocation.
+    ///
+    /// Stress mode makes the program very slow, but it is the worst case
+    /// schedule for bugs that only show up when a collection happens at an
+    /// unexpected time, like a `RefCell` that is still borrowed or a destructor
+    /// that dereferences a dead [`Gc`]. Collections still don't happen
+    /// automatically while the collector is [paused].
+    ///
+    /// Can also be enabled for every new collector with the `TERS_GC_STRESS`
+    /// environment variable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// col.set_stress(true);
+    ///
+    /// let mut proxy = col.proxy();
+    /// drop(proxy.alloc(1));
+    /// let _two = proxy.alloc(2);
+    ///
+    /// assert_eq!(proxy.num_tracked(), 1);
+    /// ```
+    ///
+    /// [`Gc`]: struct.Gc.html
+    /// [paused]: struct.Proxy.html#method.paused
+    pub fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    /// Returns whether the collector is in [stress mode].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// col.set_stress(true);
+    /// assert!(col.stress());
+    /// ```
+    ///
+    /// [stress mode]: #method.set_stress
+    pub fn stress(&self) -> bool {
+        self.stress
+    }
+
     /// Create a new [`Proxy`](struct.Proxy.html) for this collector.
     ///
     /// # Examples
@@ -312,67 +542,150 @@ impl Collector {
     /// ```
     // While allocator is active, all pointers to Collector are valid (since the arena
     // can't be moved while there is a reference to it)
-    pub fn proxy(&mut self) -> Proxy {
+    pub fn proxy(&mut self) -> Proxy<'_> {
         Proxy { collector: self }
     }
 
-    fn alloc<T: Trace>(&mut self, val: T) -> NonNull<GcBox<T>> {
+    fn alloc<T: Trace>(&mut self, val: T, location: &'static Location<'static>) -> NonNull<Handle> {
+        self.before_alloc();
+        let site = self.alloc_site(location);
+        self.allocator.alloc(val, site)
+    }
+
+    fn alloc_cyclic<T: Trace, F>(
+        &mut self,
+        make: F,
+        location: &'static Location<'static>,
+    ) -> NonNull<Handle>
+    where
+        F: FnOnce(NonNull<Handle>) -> T,
+    {
+        self.before_alloc();
+        let site = self.alloc_site(location);
+        self.allocator.alloc_cyclic(make, site)
+    }
+
+    fn alloc_boxed<T: Trace + ?Sized>(
+        &mut self,
+        val: Box<T>,
+        needs_trace: bool,
+        location: &'static Location<'static>,
+    ) -> NonNull<Handle> {
+        self.before_alloc();
+        let site = self.alloc_site(location);
+        self.allocator.alloc_boxed(val, needs_trace, site)
+    }
+
+    fn alloc_measured<T: Trace + HeapSize>(
+        &mut self,
+        val: T,
+        location: &'static Location<'static>,
+    ) -> NonNull<Handle> {
+        self.before_alloc();
+        let site = self.alloc_site(location);
+        let handle = self.allocator.alloc_measured(val, site);
+        let heap_bytes = self.allocator.items.last().map_or(0, AllocInfo::heap_bytes);
+        self.object_pressure = self.object_pressure.saturating_add(heap_bytes);
+        handle
+    }
+
+    fn before_alloc(&mut self) {
+        self.sweep_pending(self.sweep_batch);
         if self.should_collect() {
             self.run();
         }
-        self.allocator.alloc(val)
+    }
+
+    /// Record where an allocation happened, if we are tracking that
+    fn alloc_site(&mut self, location: &'static Location<'static>) -> Option<AllocSite> {
+        if !self.track_alloc_sites {
+            return None;
+        }
+
+        let mut backtrace = None;
+        if self.backtrace_sample_rate > 0 {
+            self.sites_since_backtrace += 1;
+            if self.sites_since_backtrace >= self.backtrace_sample_rate {
+                self.sites_since_backtrace = 0;
+                backtrace = Some(Rc::new(Backtrace::force_capture()));
+            }
+        }
+
+        Some(AllocSite {
+            location,
+            backtrace,
+        })
     }
 
     fn run(&mut self) {
+        // Marking expects every tracked object to be alive
+        self.finish_sweep();
         // Find the tracked objects that the client can still use
-        self.mark();
+        if !self.mark() {
+            // Something couldn't be traced yet, like a locked `Mutex`. Without
+            // a complete trace anything could be reachable, so free nothing.
+            // Back off as if everything survived a sweep, so allocating while
+            // the lock is held doesn't trace the whole heap every time.
+            for info in self.allocator.items.iter() {
+                info.unmark();
+            }
+            self.update_collection_threshold();
+            return;
+        }
+        // Look for cycles that are only alive because of a bad `Trace` impl.
+        // Freed objects with a bad `Trace` impl are caught by the sweep.
+        if self.audit_runs > 0 {
+            self.audit_leaks();
+        }
         // Remove the objects that the client can't
         self.sweep();
     }
 
-    fn mark(&self) {
+    /// Returns `false` if a `Trace` impl postponed the collection
+    fn mark(&self) -> bool {
         // Count number of references to each other objects in the gc heap hold
-        for info in self.allocator.items.values() {
-            self.mark_inter_connections(info.ptr);
+        // Leaves don't point to anything, so they can be skipped
+        let mut tracer = trace::Tracer::new();
+        for info in self.allocator.items.iter().filter(|info| !info.is_leaf()) {
+            self.mark_inter_connections(info, &mut tracer);
+        }
+        if tracer.is_postponed() {
+            return false;
         }
 
         // Anything that is reachable must be a root
         let roots = self
             .allocator
             .items
-            .values()
+            .iter()
             .filter(|info| Collector::is_object_reachable(info));
 
         // Mark roots reachable and mark all their children reachable
+        let mut tracer = trace::Tracer::new();
         for info in roots {
             info.mark_reachable();
-            self.mark_children_reachable(info.ptr);
+            self.mark_children_reachable(info, &mut tracer);
         }
+        !tracer.is_postponed()
     }
 
     /// Increment an object's counter for each reference to it this object holds
-    fn mark_inter_connections(&self, ptr: NonNull<UntypedGcBox>) {
-        // assert!(self.allocator.is_ptr_in_range(ptr));
-
-        if let Some(info) = self.allocator.info_for_ptr(ptr.as_ptr()) {
-            for val in info.children() {
-                if let Some(child) = self.allocator.info_for_ptr(val.as_ptr()) {
-                    child.mark_inter_ref();
-                }
+    fn mark_inter_connections(&self, info: &AllocInfo, tracer: &mut trace::Tracer) {
+        for val in info.children(tracer) {
+            if let Some(child) = self.allocator.info_for_handle(val) {
+                child.mark_inter_ref();
             }
         }
     }
 
     /// Recusively mark all children as reachable
-    fn mark_children_reachable(&self, ptr: NonNull<UntypedGcBox>) {
-        // assert!(self.allocator.is_ptr_in_range(ptr));
-
-        if let Some(info) = self.allocator.info_for_ptr(ptr.as_ptr()) {
-            for val in info.children() {
-                if let Some(child) = self.allocator.info_for_ptr(val.as_ptr()) {
-                    if !child.is_marked_reachable() {
-                        child.mark_reachable();
-                        self.mark_children_reachable(val);
+    fn mark_children_reachable(&self, info: &AllocInfo, tracer: &mut trace::Tracer) {
+        for val in info.children(tracer) {
+            if let Some(child) = self.allocator.info_for_handle(val) {
+                if !child.is_marked_reachable() {
+                    child.mark_reachable();
+                    if !child.is_leaf() {
+                        self.mark_children_reachable(child, tracer);
                     }
                 }
             }
@@ -391,19 +704,187 @@ impl Collector {
         total_refs > inter_refs || info.is_marked_reachable()
     }
 
+    /// Record which surviving objects are only kept alive by external
+    /// references that no root explains. Must run between marking and
+    /// sweeping.
+    ///
+    /// Leaves are skipped, since a cycle that leaks through an untraced field
+    /// is made of objects that can hold a `Gc`.
+    fn audit_leaks(&self) {
+        // Objects that a surviving object's `Trace` impl reported
+        let mut has_live_parent = HashSet::new();
+        let mut tracer = trace::Tracer::new();
+        for info in self.allocator.items.iter() {
+            if !Collector::is_object_reachable(info) || info.is_leaf() {
+                continue;
+            }
+            for child in info.children(&mut tracer) {
+                if let Some(child) = self.allocator.info_for_handle(child) {
+                    if child.ptr != info.ptr {
+                        has_live_parent.insert(child.ptr);
+                    }
+                }
+            }
+        }
+
+        for info in self.allocator.items.iter() {
+            let suspicious = Collector::is_object_reachable(info)
+                && !info.is_leaf()
+                && info.external_refs() > 0
+                && !has_live_parent.contains(&info.ptr);
+            info.record_audit(suspicious);
+        }
+    }
+
+    /// Free unreachable objects a group at a time, grouped by type and
+    /// allocation site. After each group, any object that lost more
+    /// references than the group's `Trace` impls reported was pointed to by
+    /// a `Gc` that one of them didn't trace.
+    fn free_audited(&mut self, unreachable_objects: Vec<NonNull<UntypedGcBox>>) {
+        let mut groups: Vec<(TraceSuspect, Vec<NonNull<UntypedGcBox>>)> = vec![];
+        for ptr in unreachable_objects {
+            let info = self
+                .allocator
+                .info_for_ptr(ptr.as_ptr())
+                .expect("unreachable object isn't tracked");
+            let location = info.site.as_ref().map(|site| site.location);
+            let key = (info.type_name, location);
+            match groups
+                .iter_mut()
+                .find(|(holder, _)| (holder.type_name, holder.location) == key)
+            {
+                Some((_, ptrs)) => ptrs.push(ptr),
+                None => groups.push((
+                    TraceSuspect {
+                        type_name: info.type_name,
+                        location,
+                        untraced_refs: 0,
+                        runs: 1,
+                    },
+                    vec![ptr],
+                )),
+            }
+        }
+
+        for info in self.allocator.items.iter() {
+            info.begin_audit();
+        }
+        for (mut holder, ptrs) in groups {
+            for &ptr in &ptrs {
+                let info = self.allocator.info_for_ptr(ptr.as_ptr()).unwrap();
+                // Each object on its own, so an `Rc` shared by two of them
+                // counts for both
+                let mut tracer = trace::Tracer::new();
+                for child in info.children(&mut tracer) {
+                    if let Some(child) = self.allocator.info_for_handle(child) {
+                        child.expect_dropped_ref();
+                    }
+                }
+            }
+            for ptr in ptrs {
+                self.allocator.free(ptr);
+            }
+            holder.untraced_refs = self
+                .allocator
+                .items
+                .iter()
+                .map(|info| info.finish_audit())
+                .sum();
+            if holder.untraced_refs > 0 {
+                self.record_untraced_holder(holder);
+            }
+        }
+    }
+
+    fn record_untraced_holder(&mut self, holder: TraceSuspect) {
+        let same = |other: &&mut TraceSuspect| {
+            (other.type_name, other.location) == (holder.type_name, holder.location)
+        };
+        match self.untraced_holders.iter_mut().find(same) {
+            Some(known) => {
+                known.untraced_refs = known.untraced_refs.saturating_add(holder.untraced_refs);
+                known.runs += 1;
+            }
+            None => self.untraced_holders.push(holder),
+        }
+    }
+
+    fn trace_suspects(&self) -> Vec<TraceSuspect> {
+        if self.audit_runs == 0 {
+            return vec![];
+        }
+        let mut suspects = self.untraced_holders.clone();
+        suspects.sort_by(|a, b| {
+            b.untraced_refs
+                .cmp(&a.untraced_refs)
+                .then_with(|| a.type_name.cmp(b.type_name))
+                .then_with(|| a.location.cmp(&b.location))
+        });
+        suspects
+    }
+
+    fn leak_suspects(&self) -> Vec<LeakSuspect> {
+        if self.audit_runs == 0 {
+            return vec![];
+        }
+        let mut suspects: Vec<_> = self
+            .allocator
+            .items
+            .iter()
+            .filter(|info| info.audit_stable_runs() >= self.audit_runs)
+            .map(|info| LeakSuspect {
+                type_name: info.type_name,
+                location: info.site.as_ref().map(|site| site.location),
+                external_refs: info.audit_external(),
+                runs: info.audit_stable_runs(),
+            })
+            .collect();
+        suspects.sort_by(|a, b| {
+            b.runs
+                .cmp(&a.runs)
+                .then_with(|| a.type_name.cmp(b.type_name))
+                .then_with(|| a.location.cmp(&b.location))
+        });
+        suspects
+    }
+
     /// Reclaim unreachable objects
     fn sweep(&mut self) {
+        // Compaction needs the freed slots right away, and auditing needs to
+        // see what freeing the objects drops
+        let audit = self.audit_runs > 0;
+        let lazy = self.sweep_batch > 0 && !self.compacting && !audit;
         let mut unreachable_objects = vec![];
-        for info in self.allocator.items.values() {
+        let mut freed_pressure: usize = 0;
+        for info in self.allocator.items.iter() {
             if !Collector::is_object_reachable(info) {
                 unreachable_objects.push(info.ptr);
-            } else {
-                info.unmark();
+                freed_pressure = freed_pressure.saturating_add(info.external_bytes());
+                if lazy {
+                    // So that `Weak`s can't bring it back before it is freed
+                    info.kill();
+                }
             }
+            info.unmark();
         }
+        self.object_pressure = self.object_pressure.saturating_sub(freed_pressure);
 
-        for ptr in unreachable_objects {
-            self.allocator.free(ptr);
+        if lazy {
+            self.pending_frees = unreachable_objects;
+        } else if audit {
+            self.free_audited(unreachable_objects);
+            self.allocator.release_unused_handles();
+        } else {
+            for ptr in unreachable_objects {
+                self.allocator.free(ptr);
+            }
+            self.allocator.release_unused_handles();
+        }
+
+        self.remeasure_heap_sizes();
+
+        if self.compacting {
+            self.allocator.compact();
         }
 
         // Update automatic collection threshold
@@ -414,6 +895,29 @@ impl Collector {
         }
     }
 
+    /// Free up to `count` of the objects left over from a lazy sweep
+    fn sweep_pending(&mut self, count: usize) {
+        if self.pending_frees.is_empty() {
+            return;
+        }
+        let keep = self.pending_frees.len().saturating_sub(count);
+        for ptr in self.pending_frees.split_off(keep) {
+            self.allocator.free(ptr);
+        }
+        if self.pending_frees.is_empty() {
+            self.allocator.release_unused_handles();
+        }
+    }
+
+    fn finish_sweep(&mut self) {
+        self.sweep_pending(self.pending_frees.len());
+    }
+
+    /// Number of tracked objects that aren't waiting to be freed
+    fn num_live(&self) -> usize {
+        self.num_tracked() - self.pending_frees.len()
+    }
+
     fn pause(&mut self) {
         self.paused = true;
     }
@@ -425,9 +929,32 @@ impl Collector {
         self.allocator.items.len()
     }
 
+    fn heap_histogram(&self) -> Vec<HistogramEntry> {
+        let mut builder = HistogramBuilder::default();
+        for info in self.allocator.items.iter().filter(|info| info.is_alive()) {
+            builder.add(
+                info.type_name,
+                info.layout.size(),
+                info.measure_heap(),
+                info.site.as_ref(),
+            );
+        }
+        builder.finish()
+    }
+
+    fn info_for_gc<T: ?Sized>(&self, gc: &Gc<T>) -> &AllocInfo {
+        // Dead objects can't be looked up
+        gc.nonnull_box_ptr();
+        self.allocator
+            .info_for_handle(gc.handle_ptr())
+            .expect("gc pointer belongs to another collector")
+    }
+
     pub(crate) fn try_remove<'a, T: 'a>(&mut self, gc: Gc<'a, T>) -> Result<T, Gc<'a, T>> {
         // Gc must be valid and the only strong pointer to the object
         if Gc::is_alive(&gc) && Gc::strong_count(&gc) == 1 {
+            let freed = self.info_for_gc(&gc).external_bytes();
+            self.object_pressure = self.object_pressure.saturating_sub(freed);
             let ptr = gc.nonnull_box_ptr().as_untyped();
             // This is safe because the we are taking both the `T` and the
             // pointer from the `Gc`.
@@ -468,14 +995,62 @@ impl Collector {
 
     /// Update point at which we do automatic collection
     fn update_collection_threshold(&mut self) {
-        let num_tracked = self.num_tracked();
-        let additional = (num_tracked as f64 * self.sweep_factor) as usize;
-        self.collection_threshold = num_tracked + additional + 1;
+        let num_live = self.num_live();
+        let additional = (num_live as f64 * self.sweep_factor) as usize;
+        self.collection_threshold = num_live + additional + 1;
+
+        let pressure = self.memory_pressure();
+        let additional = (pressure as f64 * self.sweep_factor) as usize;
+        self.pressure_threshold = pressure
+            .saturating_add(additional)
+            .saturating_add(MIN_PRESSURE_THRESHOLD);
     }
 
     fn should_collect(&self) -> bool {
         // !self.paused && self.ideal_size() > self.collection_threshold
-        !self.paused && self.num_tracked() >= self.collection_threshold
+        !self.paused
+            && (self.stress
+                || self.num_live() >= self.collection_threshold
+                || self.memory_pressure() >= self.pressure_threshold)
+    }
+
+    /// Update the heap sizes of measured objects, since they may have grown
+    fn remeasure_heap_sizes(&mut self) {
+        for info in self
+            .allocator
+            .items
+            .iter()
+            .filter(|info| info.is_measured() && info.is_alive())
+        {
+            let old = info.heap_bytes();
+            info.remeasure_heap();
+            self.object_pressure = self
+                .object_pressure
+                .saturating_sub(old)
+                .saturating_add(info.heap_bytes());
+        }
+    }
+
+    fn memory_pressure(&self) -> usize {
+        self.memory_pressure.saturating_add(self.object_pressure)
+    }
+
+    fn add_memory_pressure_for<T: ?Sized>(&mut self, gc: &Gc<T>, bytes: usize) {
+        self.info_for_gc(gc).add_pressure(bytes);
+        self.object_pressure = self.object_pressure.saturating_add(bytes);
+    }
+
+    fn remove_memory_pressure_for<T: ?Sized>(&mut self, gc: &Gc<T>, bytes: usize) {
+        let removed = self.info_for_gc(gc).remove_pressure(bytes);
+        self.object_pressure = self.object_pressure.saturating_sub(removed);
+    }
+}
+
+/// Whether `TERS_GC_STRESS` asks for stress mode
+fn stress_env_enabled() -> bool {
+    match ::std::env::var_os("TERS_GC_STRESS") {
+        Some(val) => !val.is_empty() && val != "0",
+        None => false,
     }
 }
 
@@ -507,58 +1082,111 @@ impl<'a> Proxy<'a> {
     /// ```
     ///
     /// [`paused`]: #method.paused
+    #[track_caller]
     pub fn alloc<T: Trace>(&mut self, payload: T) -> Gc<'a, T> {
-        let ptr = self.collector.alloc(payload);
-        Gc::from_raw_nonnull(ptr, PhantomData)
+        let handle = self.collector.alloc(payload, Location::caller());
+        Gc::from_raw_handle(handle, PhantomData)
     }
 
-    /// Runs the gc, freeing unreachable objects.
+    /// Stores the value `make` returns in the gc heap, giving `make` a [`Weak`]
+    /// pointer to the object it is making.
+    ///
+    /// Works like [`Rc::new_cyclic`], so objects can point to themselves
+    /// without interior mutability. The [`Weak`] can't be upgraded until
+    /// `make` returns.
+    ///
+    /// If not [`paused`], runs the gc if the heap got too big.
     ///
     /// # Examples
     ///
     /// ```
+    /// use ters_gc::ptr::Weak;
+    /// use ters_gc::trace::{Trace, Tracer};
     /// use ters_gc::Collector;
     ///
+    /// struct Node<'a> {
+    ///     this: Weak<'a, Node<'a>>,
+    ///     value: i32,
+    /// }
+    ///
+    /// impl<'a> Trace for Node<'a> {
+    ///     fn trace(&self, tracer: &mut Tracer) {
+    ///         tracer.add_target(&self.this);
+    ///     }
+    /// }
+    ///
     /// let mut col = Collector::new();
     /// let mut proxy = col.proxy();
     ///
-    /// {
-    ///     proxy.alloc(42);
-    /// }
-    /// assert_eq!(proxy.num_tracked(), 1);
-    /// proxy.run();
-    /// assert_eq!(proxy.num_tracked(), 0);
+    /// let node = proxy.alloc_cyclic(|this| {
+    ///     assert!(this.upgrade().is_none());
+    ///     Node { this: this.clone(), value: 5 }
+    /// });
+    /// assert_eq!(node.this.upgrade().unwrap().value, 5);
     /// ```
-    pub fn run(&mut self) {
-        self.collector.run();
+    ///
+    /// [`Weak`]: ptr/struct.Weak.html
+    /// [`Rc::new_cyclic`]: https://doc.rust-lang.org/std/rc/struct.Rc.html#method.new_cyclic
+    /// [`paused`]: #method.paused
+    #[track_caller]
+    pub fn alloc_cyclic<T: Trace, F>(&mut self, make: F) -> Gc<'a, T>
+    where
+        F: FnOnce(&Weak<'a, T>) -> T,
+    {
+        let make = |handle| make(&Weak::from_raw_handle(handle, PhantomData));
+        let handle = self.collector.alloc_cyclic(make, Location::caller());
+        Gc::from_raw_handle(handle, PhantomData)
     }
 
-    /// Returns whether or not automatic collection is paused.
+    /// Moves a boxed value into the gc heap. Unlike [`alloc`], the value can
+    /// be unsized, like a slice or a trait object.
     ///
-    /// When paused, garbage collection will only occur if started manually
-    /// via [`run`].
+    /// The value is always traced, since an unsized type can't say whether it
+    /// needs tracing. [`alloc_slice`] and [`alloc_str`] skip tracing leaves.
+    ///
+    /// If not [`paused`], runs the gc if the heap got too big.
     ///
     /// # Examples
     ///
     /// ```
-    /// use ters_gc::Collector;
+    /// use ters_gc::trace::Trace;
+    /// use ters_gc::{Collector, Gc};
     ///
     /// let mut col = Collector::new();
     /// let mut proxy = col.proxy();
     ///
-    /// assert!(!proxy.paused());
+    /// let boxed: Box<dyn Trace> = Box::new(5);
+    /// let _val: Gc<dyn Trace> = proxy.alloc_boxed(boxed);
     /// ```
     ///
-    /// [`run`]: #method.run
-    pub fn paused(&self) -> bool {
-        self.collector.paused
+    /// [`alloc`]: #method.alloc
+    /// [`alloc_slice`]: #method.alloc_slice
+    /// [`alloc_str`]: #method.alloc_str
+    /// [`paused`]: #method.paused
+    #[track_caller]
+    pub fn alloc_boxed<T: Trace + ?Sized>(&mut self, payload: Box<T>) -> Gc<'a, T> {
+        // What's in the box isn't known, so it is traced
+        self.alloc_boxed_traced(payload, true)
     }
 
-    /// Pauses automatic collection.
+    #[track_caller]
+    fn alloc_boxed_traced<T: Trace + ?Sized>(
+        &mut self,
+        payload: Box<T>,
+        needs_trace: bool,
+    ) -> Gc<'a, T> {
+        let value_ptr: *const T = &*payload;
+        let template = value_ptr as *const GcBox<T>;
+        let handle = self
+            .collector
+            .alloc_boxed(payload, needs_trace, Location::caller());
+        // Unsafe is fine since the metadata came from the value
+        unsafe { Gc::from_raw_handle_like(handle, template, PhantomData) }
+    }
+
+    /// Copies a slice into the gc heap.
     ///
-    /// Until [`resume`] is called, storing things in the gc
-    /// heap will not trigger collection. The only time collection will occur
-    /// is if it is done manually with [`run`].
+    /// If not [`paused`], runs the gc if the heap got too big.
     ///
     /// # Examples
     ///
@@ -568,19 +1196,20 @@ impl<'a> Proxy<'a> {
     /// let mut col = Collector::new();
     /// let mut proxy = col.proxy();
     ///
-    /// proxy.pause();
-    /// assert!(proxy.paused());
+    /// let nums = proxy.alloc_slice(&[1, 2, 3]);
+    /// assert_eq!(nums.len(), 3);
+    /// assert_eq!(nums[1], 2);
     /// ```
     ///
-    /// [`resume`]: #method.resume
-    /// [`run`]: #method.run
-    pub fn pause(&mut self) {
-        self.collector.pause();
+    /// [`paused`]: #method.paused
+    #[track_caller]
+    pub fn alloc_slice<T: Trace + Clone>(&mut self, values: &[T]) -> Gc<'a, [T]> {
+        self.alloc_boxed_traced(values.into(), T::needs_trace())
     }
 
-    /// Resume automatic collection.
+    /// Copies a string into the gc heap.
     ///
-    /// When storing something, it will run collection if the gc heap is too big.
+    /// If not [`paused`], runs the gc if the heap got too big.
     ///
     /// # Examples
     ///
@@ -590,17 +1219,24 @@ impl<'a> Proxy<'a> {
     /// let mut col = Collector::new();
     /// let mut proxy = col.proxy();
     ///
-    /// proxy.pause();
-    /// assert!(proxy.paused());
-    ///
-    /// proxy.resume();
-    /// assert!(!proxy.paused());
+    /// let name = proxy.alloc_str("Ferris");
+    /// assert_eq!(&*name, "Ferris");
     /// ```
-    pub fn resume(&mut self) {
-        self.collector.resume();
+    ///
+    /// [`paused`]: #method.paused
+    #[track_caller]
+    pub fn alloc_str(&mut self, string: &str) -> Gc<'a, str> {
+        self.alloc_boxed_traced(string.into(), false)
     }
 
-    /// Returns the number of objects in the gc heap.
+    /// Stores a function together with the values it captures, so that the
+    /// captures are traced like any other object's fields.
+    ///
+    /// `function` is called with a reference to `captures` and the arguments,
+    /// which are passed as a tuple. A closure that doesn't capture anything
+    /// works as `function`.
+    ///
+    /// If not [`paused`], runs the gc if the heap got too big.
     ///
     /// # Examples
     ///
@@ -610,25 +1246,38 @@ impl<'a> Proxy<'a> {
     /// let mut col = Collector::new();
     /// let mut proxy = col.proxy();
     ///
-    /// assert_eq!(proxy.num_tracked(), 0);
-    ///
-    /// let _ = proxy.alloc(());
-    /// assert_eq!(proxy.num_tracked(), 1);
-    ///
-    /// let _ = proxy.alloc(());
-    /// assert_eq!(proxy.num_tracked(), 2);
+    /// let greeting = proxy.alloc(String::from("Hello"));
+    /// let greet = proxy.alloc_closure(greeting, |greeting, (name,): (&str,)| {
+    ///     format!("{}, {}!", greeting, name)
+    /// });
+    /// assert_eq!(greet.call(("world",)), "Hello, world!");
     /// ```
-    pub fn num_tracked(&self) -> usize {
-        self.collector.num_tracked()
+    ///
+    /// [`paused`]: #method.paused
+    #[track_caller]
+    pub fn alloc_closure<C, Args, Ret>(
+        &mut self,
+        captures: C,
+        function: fn(&C, Args) -> Ret,
+    ) -> Gc<'a, GcFn<'a, Args, Ret>>
+    where
+        C: Trace + 'a,
+        Args: 'a,
+        Ret: 'a,
+    {
+        // Only the captures are traced
+        self.alloc_boxed_traced(GcFn::new_boxed(captures, function), C::needs_trace())
     }
 
-    /// Sets how much the threshold to run the gc when storing things grows.
+    /// Stores something in the gc heap, keeping track of how much heap memory
+    /// it owns.
     ///
-    /// The higher the value the more objects you can store before storing triggers
-    /// automatic collection.
+    /// The object's [`HeapSize`] counts towards the [`memory_pressure`] and
+    /// shows up in the [`heap_histogram`]. It is measured again during every
+    /// collection, so the pressure follows the object as it grows or shrinks.
+    /// Objects stored with [`alloc`] are never measured.
     ///
-    /// The automatic collection threshold will not be updated until collection
-    /// is performed.
+    /// If not [`paused`], runs the gc if the heap got too big.
     ///
     /// # Examples
     ///
@@ -638,318 +1287,1934 @@ impl<'a> Proxy<'a> {
     /// let mut col = Collector::new();
     /// let mut proxy = col.proxy();
     ///
-    /// proxy.set_threshold_growth(0.75);
+    /// let buffer = proxy.alloc_measured(Vec::<u8>::with_capacity(1024));
+    /// assert_eq!(proxy.memory_pressure(), 1024);
+    ///
+    /// drop(buffer);
+    /// proxy.run();
+    /// assert_eq!(proxy.memory_pressure(), 0);
     /// ```
-    pub fn set_threshold_growth(&mut self, factor: f64) {
-        self.collector.sweep_factor = factor;
+    ///
+    /// [`HeapSize`]: heap_size/trait.HeapSize.html
+    /// [`alloc`]: #method.alloc
+    /// [`memory_pressure`]: #method.memory_pressure
+    /// [`heap_histogram`]: #method.heap_histogram
+    /// [`paused`]: #method.paused
+    #[track_caller]
+    pub fn alloc_measured<T: Trace + HeapSize>(&mut self, payload: T) -> Gc<'a, T> {
+        let handle = self.collector.alloc_measured(payload, Location::caller());
+        Gc::from_raw_handle(handle, PhantomData)
     }
 
-    /// Returns the number of objects that can be stored in the gc heap
-    /// before collection is automatically run.
+    /// Runs the gc, freeing unreachable objects.
     ///
-    /// Changes every time collection is performed.
+    /// With [lazy sweeping] enabled, unreachable objects are only marked as
+    /// dead, and are freed by later allocations.
+    ///
+    /// Nothing is freed if an object in the gc heap can't be traced right
+    /// now, like a `Mutex` that is locked. The next allocation tries again.
     ///
     /// # Examples
     ///
     /// ```
     /// use ters_gc::Collector;
     ///
-    ///
     /// let mut col = Collector::new();
     /// let mut proxy = col.proxy();
     ///
-    /// let init_thresh = proxy.threshold();
-    ///
-    /// for _ in 0..(init_thresh + 1) {
-    ///     proxy.alloc(());
+    /// {
+    ///     proxy.alloc(42);
     /// }
+    /// assert_eq!(proxy.num_tracked(), 1);
+    /// proxy.run();
+    /// assert_eq!(proxy.num_tracked(), 0);
+    /// ```
+    pub fn run(&mut self) {
+        self.collector.run();
+    }
+
+    /// Sets how many unreachable objects each allocation frees after a
+    /// collection. A value of `0` frees all of them during the collection,
+    /// which is the default.
+    ///
+    /// Freeing runs destructors and gives memory back, so a collection that
+    /// finds lots of garbage can take a while. Lazy sweeping spreads that work
+    /// over the following calls to [`alloc`]. Marking is still done all at
+    /// once, and the unreachable objects are dead as soon as the collection
+    /// finishes, so [`Weak`] pointers to them can't be upgraded.
+    ///
+    /// Objects that are waiting to be freed still count towards
+    /// [`num_tracked`]. Use [`finish_sweep`] to free all of them right away.
+    /// Collecting again also finishes the previous sweep first.
+    ///
+    /// [Compacting] collections always free everything during the collection.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// col.set_stress(false);
+    /// let mut proxy = col.proxy();
+    /// proxy.set_lazy_sweep(2);
+    ///
+    /// for i in 0..5 {
+    ///     proxy.alloc(i);
+    /// }
+    /// proxy.run();
+    /// assert_eq!(proxy.pending_sweep(), 5);
+    ///
+    /// let _num = proxy.alloc(5);
+    /// assert_eq!(proxy.pending_sweep(), 3);
+    /// ```
+    ///
+    /// [lazy sweeping]: #method.set_lazy_sweep
+    /// [`alloc`]: #method.alloc
+    /// [`Weak`]: ptr/struct.Weak.html
+    /// [`num_tracked`]: #method.num_tracked
+    /// [`finish_sweep`]: #method.finish_sweep
+    /// [Compacting]: #method.set_compacting
+    pub fn set_lazy_sweep(&mut self, batch: usize) {
+        self.collector.sweep_batch = batch;
+    }
+
+    /// Returns how many unreachable objects each allocation frees. `0` if
+    /// lazy sweeping is disabled.
+    ///
+    /// See [`set_lazy_sweep`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// assert_eq!(proxy.lazy_sweep(), 0);
+    /// proxy.set_lazy_sweep(16);
+    /// assert_eq!(proxy.lazy_sweep(), 16);
+    /// ```
+    ///
+    /// [`set_lazy_sweep`]: #method.set_lazy_sweep
+    pub fn lazy_sweep(&self) -> usize {
+        self.collector.sweep_batch
+    }
+
+    /// Frees every object that a [lazy sweep] left for later allocations.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    /// proxy.set_lazy_sweep(1);
+    ///
+    /// {
+    ///     proxy.alloc(42);
+    /// }
+    /// proxy.run();
+    /// assert_eq!(proxy.num_tracked(), 1);
+    /// proxy.finish_sweep();
+    /// assert_eq!(proxy.num_tracked(), 0);
+    /// ```
+    ///
+    /// [lazy sweep]: #method.set_lazy_sweep
+    pub fn finish_sweep(&mut self) {
+        self.collector.finish_sweep();
+    }
+
+    /// Returns the number of unreachable objects that are waiting to be freed
+    /// by a [lazy sweep].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// assert_eq!(proxy.pending_sweep(), 0);
+    /// ```
+    ///
+    /// [lazy sweep]: #method.set_lazy_sweep
+    pub fn pending_sweep(&self) -> usize {
+        self.collector.pending_frees.len()
+    }
+
+    /// Returns whether or not automatic collection is paused.
+    ///
+    /// When paused, garbage collection will only occur if started manually
+    /// via [`run`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// assert!(!proxy.paused());
+    /// ```
+    ///
+    /// [`run`]: #method.run
+    pub fn paused(&self) -> bool {
+        self.collector.paused
+    }
+
+    /// Pauses automatic collection.
+    ///
+    /// Until [`resume`] is called, storing things in the gc
+    /// heap will not trigger collection. The only time collection will occur
+    /// is if it is done manually with [`run`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// proxy.pause();
+    /// assert!(proxy.paused());
+    /// ```
+    ///
+    /// [`resume`]: #method.resume
+    /// [`run`]: #method.run
+    pub fn pause(&mut self) {
+        self.collector.pause();
+    }
+
+    /// Resume automatic collection.
+    ///
+    /// When storing something, it will run collection if the gc heap is too big.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// proxy.pause();
+    /// assert!(proxy.paused());
+    ///
+    /// proxy.resume();
+    /// assert!(!proxy.paused());
+    /// ```
+    pub fn resume(&mut self) {
+        self.collector.resume();
+    }
+
+    /// Returns the number of objects in the gc heap.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// assert_eq!(proxy.num_tracked(), 0);
+    ///
+    /// let _a = proxy.alloc(());
+    /// assert_eq!(proxy.num_tracked(), 1);
+    ///
+    /// let _b = proxy.alloc(());
+    /// assert_eq!(proxy.num_tracked(), 2);
+    /// ```
+    pub fn num_tracked(&self) -> usize {
+        self.collector.num_tracked()
+    }
+
+    /// Sets how much the threshold to run the gc when storing things grows.
+    ///
+    /// The higher the value the more objects you can store before storing triggers
+    /// automatic collection.
+    ///
+    /// The automatic collection threshold will not be updated until collection
+    /// is performed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// proxy.set_threshold_growth(0.75);
+    /// ```
+    pub fn set_threshold_growth(&mut self, factor: f64) {
+        self.collector.sweep_factor = factor;
+    }
+
+    /// Returns the number of objects that can be stored in the gc heap
+    /// before collection is automatically run.
+    ///
+    /// Changes every time collection is performed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let init_thresh = proxy.threshold();
+    ///
+    /// for _ in 0..(init_thresh + 1) {
+    ///     proxy.alloc(());
+    /// }
+    ///
+    /// let new_thresh = proxy.threshold();
     ///
-    /// let new_thresh = proxy.threshold();
-    ///
     /// assert!(init_thresh != new_thresh);
     /// ```
     ///
     pub fn threshold(&self) -> usize {
         self.collector.collection_threshold
     }
-}
 
-impl<'a> Drop for Proxy<'a> {
-    fn drop(&mut self) {
-        self.collector.allocator.items.clear();
+    /// Tells the collector that the gc heap keeps `bytes` of memory alive that
+    /// it can't see, like buffers owned by a library or mapped files.
+    ///
+    /// Once the total memory pressure reaches the [`pressure_threshold`], the
+    /// next allocation runs the collector, even if the number of objects is
+    /// still below the [`threshold`]. Call [`remove_memory_pressure`] when the
+    /// memory is released.
+    ///
+    /// Use [`add_memory_pressure_for`] instead if the memory belongs to a
+    /// single object, so that the pressure goes away when it is reclaimed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// proxy.add_memory_pressure(4096);
+    /// assert_eq!(proxy.memory_pressure(), 4096);
+    /// proxy.remove_memory_pressure(4096);
+    /// assert_eq!(proxy.memory_pressure(), 0);
+    /// ```
+    ///
+    /// [`pressure_threshold`]: #method.pressure_threshold
+    /// [`threshold`]: #method.threshold
+    /// [`remove_memory_pressure`]: #method.remove_memory_pressure
+    /// [`add_memory_pressure_for`]: #method.add_memory_pressure_for
+    pub fn add_memory_pressure(&mut self, bytes: usize) {
+        self.collector.memory_pressure = self.collector.memory_pressure.saturating_add(bytes);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Undoes a call to [`add_memory_pressure`].
+    ///
+    /// Only removes pressure that isn't associated with an object. Removing
+    /// more than was added leaves no unassociated pressure.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// proxy.add_memory_pressure(100);
+    /// proxy.remove_memory_pressure(150);
+    /// assert_eq!(proxy.memory_pressure(), 0);
+    /// ```
+    ///
+    /// [`add_memory_pressure`]: #method.add_memory_pressure
+    pub fn remove_memory_pressure(&mut self, bytes: usize) {
+        self.collector.memory_pressure = self.collector.memory_pressure.saturating_sub(bytes);
+    }
 
-    struct LinkedList<'a> {
-        next: Option<Gc<'a, LinkedList<'a>>>,
+    /// Tells the collector that the object `gc` points to keeps `bytes` of
+    /// memory alive that it can't see.
+    ///
+    /// Works like [`add_memory_pressure`], except that the pressure is
+    /// removed automatically once the object is reclaimed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object is dead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let texture = proxy.alloc(7u32); // Handle to a texture on the GPU
+    /// proxy.add_memory_pressure_for(&texture, 1 << 20);
+    /// assert_eq!(proxy.memory_pressure(), 1 << 20);
+    ///
+    /// drop(texture);
+    /// proxy.run();
+    /// assert_eq!(proxy.memory_pressure(), 0);
+    /// ```
+    ///
+    /// [`add_memory_pressure`]: #method.add_memory_pressure
+    pub fn add_memory_pressure_for<T: ?Sized>(&mut self, gc: &Gc<'a, T>, bytes: usize) {
+        self.collector.add_memory_pressure_for(gc, bytes);
     }
-    impl<'a> Trace for LinkedList<'a> {
-        fn trace(&self, tracer: &mut trace::Tracer) {
+
+    /// Undoes a call to [`add_memory_pressure_for`], for when an object
+    /// releases its memory before it is reclaimed.
+    ///
+    /// Removing more than was added leaves the object with no pressure.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object is dead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let file = proxy.alloc(3i32); // File descriptor of a mapped file
+    /// proxy.add_memory_pressure_for(&file, 8192);
+    /// proxy.remove_memory_pressure_for(&file, 8192);
+    /// assert_eq!(proxy.memory_pressure(), 0);
+    /// ```
+    ///
+    /// [`add_memory_pressure_for`]: #method.add_memory_pressure_for
+    pub fn remove_memory_pressure_for<T: ?Sized>(&mut self, gc: &Gc<'a, T>, bytes: usize) {
+        self.collector.remove_memory_pressure_for(gc, bytes);
+    }
+
+    /// Returns the number of bytes of memory pressure, both from
+    /// [`add_memory_pressure`] and from objects that are still tracked. That
+    /// includes the heap size of objects stored with [`alloc_measured`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let buffer = proxy.alloc(());
+    /// proxy.add_memory_pressure(10);
+    /// proxy.add_memory_pressure_for(&buffer, 20);
+    /// assert_eq!(proxy.memory_pressure(), 30);
+    /// ```
+    ///
+    /// [`add_memory_pressure`]: #method.add_memory_pressure
+    /// [`alloc_measured`]: #method.alloc_measured
+    pub fn memory_pressure(&self) -> usize {
+        self.collector.memory_pressure()
+    }
+
+    /// Returns how many bytes of memory pressure there can be before
+    /// allocating automatically runs the collector.
+    ///
+    /// Changes every time collection is performed, growing along with the
+    /// pressure that is left, the same way the [`threshold`] does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let buffer = proxy.alloc(());
+    /// proxy.add_memory_pressure_for(&buffer, proxy.pressure_threshold());
+    ///
+    /// // Collects before allocating, reclaiming `buffer`
+    /// drop(buffer);
+    /// let _next = proxy.alloc(());
+    /// assert_eq!(proxy.memory_pressure(), 0);
+    /// ```
+    ///
+    /// [`threshold`]: #method.threshold
+    pub fn pressure_threshold(&self) -> usize {
+        self.collector.pressure_threshold
+    }
+
+    /// Sets whether the source location of each allocation is recorded.
+    ///
+    /// Recorded locations show up in the [`heap_histogram`]. Only objects
+    /// allocated while tracking is enabled have a location.
+    ///
+    /// Disabled by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// proxy.set_track_alloc_sites(true);
+    /// let _num = proxy.alloc(8);
+    ///
+    /// let histogram = proxy.heap_histogram();
+    /// assert_eq!(histogram[0].location.unwrap().file(), file!());
+    /// ```
+    ///
+    /// [`heap_histogram`]: #method.heap_histogram
+    pub fn set_track_alloc_sites(&mut self, track: bool) {
+        self.collector.track_alloc_sites = track;
+    }
+
+    /// Sets how often a backtrace is captured for a tracked allocation site.
+    ///
+    /// A backtrace is captured for one out of every `rate` allocations made
+    /// while [`set_track_alloc_sites`] is enabled. A rate of `0` never captures
+    /// backtraces, which is the default.
+    ///
+    /// Capturing a backtrace is slow, so keep the rate high in large programs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// proxy.set_track_alloc_sites(true);
+    /// proxy.set_backtrace_sample_rate(1);
+    /// let _num = proxy.alloc(8);
+    ///
+    /// let histogram = proxy.heap_histogram();
+    /// assert!(histogram[0].backtrace.is_some());
+    /// ```
+    ///
+    /// [`set_track_alloc_sites`]: #method.set_track_alloc_sites
+    pub fn set_backtrace_sample_rate(&mut self, rate: usize) {
+        self.collector.backtrace_sample_rate = rate;
+        self.collector.sites_since_backtrace = 0;
+    }
+
+    /// Returns a summary of the objects in the gc heap.
+    ///
+    /// Objects are grouped by type and allocation site, with the largest groups
+    /// first. See the [`stats module`] for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let _a = proxy.alloc(1u8);
+    /// let _b = proxy.alloc(2u8);
+    /// let _c = proxy.alloc('c');
+    ///
+    /// let histogram = proxy.heap_histogram();
+    /// assert_eq!(histogram.len(), 2);
+    /// assert_eq!(histogram[0].type_name, "u8");
+    /// assert_eq!(histogram[0].count, 2);
+    /// ```
+    ///
+    /// [`stats module`]: stats/index.html
+    pub fn heap_histogram(&self) -> Vec<HistogramEntry> {
+        self.collector.heap_histogram()
+    }
+
+    /// Returns how much memory the allocator has reserved for objects, and how
+    /// much of it is unused.
+    ///
+    /// Freed objects leave behind slots that are reused by later objects of a
+    /// similar size, so a large [`fragmentation`] after a big collection is
+    /// expected and doesn't mean memory is leaking. With the `quarantine`
+    /// feature, freed objects keep their slots until they leave quarantine.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let _nums: Vec<_> = (0..100u64).map(|i| proxy.alloc(i)).collect();
+    ///
+    /// let stats = proxy.slab_stats();
+    /// assert!(stats.requested_bytes() > 100 * 8);
+    /// assert!(stats.reserved_bytes() >= stats.requested_bytes());
+    /// assert_eq!(stats.large_objects, 0);
+    /// ```
+    ///
+    /// [`fragmentation`]: stats/struct.SlabStats.html#method.fragmentation
+    pub fn slab_stats(&self) -> SlabStats {
+        self.collector.allocator.slab_stats()
+    }
+
+    /// Sets whether collections move objects to reduce fragmentation.
+    ///
+    /// When compacting, each collection moves surviving objects into the
+    /// lowest free slots of their size class, then gives pages that end up
+    /// empty back to the allocator. Slots held by the `quarantine` feature
+    /// aren't free, so they limit how much can be compacted. [`Gc`]s and
+    /// [`Weak`]s keep working, since they find their object through a handle
+    /// that never moves.
+    ///
+    /// Disabled by default.
+    ///
+    /// # Safety
+    ///
+    /// References into an object, such as the ones returned by
+    /// [`Gc::get`] or by dereferencing a [`Gc`], are invalidated if the object
+    /// moves. While compacting, the client must not hold such a reference
+    /// across a collection unless the object is [pinned]. Remember that
+    /// collections also happen automatically when allocating.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// // Safe since we don't keep references into the gc heap
+    /// unsafe { proxy.set_compacting(true) };
+    ///
+    /// let nums: Vec<_> = (0..1000u64).map(|i| proxy.alloc(i)).collect();
+    /// let reserved = proxy.slab_stats().reserved_bytes();
+    ///
+    /// // Keep every 100th number
+    /// let kept: Vec<_> = nums.into_iter().step_by(100).collect();
+    /// proxy.run();
+    ///
+    /// // The survivors were packed together, so some pages could be freed
+    /// assert!(proxy.slab_stats().reserved_bytes() <= reserved);
+    /// assert_eq!(*kept[3], 300);
+    /// ```
+    ///
+    /// [`Gc`]: ptr/struct.Gc.html
+    /// [`Weak`]: ptr/struct.Weak.html
+    /// [`Gc::get`]: ptr/struct.Gc.html#method.get
+    /// [pinned]: #method.pin
+    pub unsafe fn set_compacting(&mut self, compacting: bool) {
+        self.collector.compacting = compacting;
+    }
+
+    /// Returns whether collections move objects.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// assert!(!proxy.compacting());
+    /// ```
+    pub fn compacting(&self) -> bool {
+        self.collector.compacting
+    }
+
+    /// Stops an object from being moved by [compacting] collections, so that
+    /// references into it stay valid.
+    ///
+    /// Pins are counted, so the object can move again once [`unpin`] has been
+    /// called as many times as `pin`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object is dead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::{Collector, Gc};
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    /// unsafe { proxy.set_compacting(true) };
+    ///
+    /// let _garbage = proxy.alloc(0u64);
+    /// let num = proxy.alloc(1u64);
+    /// drop(_garbage);
+    ///
+    /// proxy.pin(&num);
+    /// let num_ref: *const u64 = Gc::get(&num).unwrap();
+    /// proxy.run();
+    /// assert_eq!(unsafe { *num_ref }, 1);
+    /// proxy.unpin(&num);
+    /// ```
+    ///
+    /// [compacting]: #method.set_compacting
+    /// [`unpin`]: #method.unpin
+    pub fn pin<T: ?Sized>(&mut self, gc: &Gc<'a, T>) {
+        self.collector.info_for_gc(gc).pin();
+    }
+
+    /// Undoes a call to [`pin`], letting the object move again once it isn't
+    /// pinned anymore.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object is dead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let num = proxy.alloc(1);
+    /// proxy.pin(&num);
+    /// proxy.unpin(&num);
+    /// ```
+    ///
+    /// [`pin`]: #method.pin
+    pub fn unpin<T: ?Sized>(&mut self, gc: &Gc<'a, T>) {
+        self.collector.info_for_gc(gc).unpin();
+    }
+
+    /// Turns on auditing of [`Trace`] impls, which looks for `Gc`s that a
+    /// `Trace` impl forgot to report. A value of `0` disables auditing, which
+    /// is the default.
+    ///
+    /// While auditing, every collection frees the unreachable objects one
+    /// type at a time and checks what that drops. If the objects drop a
+    /// reference that their `Trace` impls never reported, their type has a
+    /// bad `Trace` impl and is reported by [`trace_suspects`]. This names the
+    /// faulty impl, and objects the client holds onto are never reported.
+    ///
+    /// A bad impl can also leak a whole cycle that is never freed, and so is
+    /// never checked that way. As a separate hint, every collection also looks
+    /// for surviving objects that have references from outside of the gc
+    /// heap, but that no surviving object's `Trace` impl reported as a child.
+    /// An object whose number of such references doesn't change for `runs`
+    /// collections in a row is reported by [`leak_suspects`]. Such a leaked
+    /// cycle looks exactly like this, but so does an object the client holds
+    /// onto without touching its `Gc`s. Cloning or dropping a `Gc` to an
+    /// object starts its count over, and objects that can't hold a `Gc` are
+    /// never reported.
+    ///
+    /// Auditing makes each collection slower, and turns off lazy sweeping.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// proxy.set_trace_audit(3);
+    /// ```
+    ///
+    /// [`trace_suspects`]: #method.trace_suspects
+    /// [`leak_suspects`]: #method.leak_suspects
+    /// [`Trace`]: trace/trait.Trace.html
+    pub fn set_trace_audit(&mut self, runs: usize) {
+        self.collector.audit_runs = runs;
+    }
+
+    /// Returns the types whose objects were found dropping references that
+    /// their [`Trace`] impls didn't report, while [auditing].
+    ///
+    /// Suspects that were allocated while [`set_track_alloc_sites`] was
+    /// enabled include their allocation site.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::{Collector, Gc};
+    /// use ters_gc::trace::{Trace, Tracer};
+    ///
+    /// struct Event<'a> {
+    ///     target: Gc<'a, String>,
+    /// }
+    /// impl<'a> Trace for Event<'a> {
+    ///     fn trace(&self, _tracer: &mut Tracer) {
+    ///         // Forgot to trace `target`
+    ///     }
+    /// }
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    /// proxy.set_trace_audit(1);
+    ///
+    /// let button = proxy.alloc(String::from("button"));
+    /// proxy.alloc(Event { target: button.clone() });
+    /// proxy.run();
+    ///
+    /// let suspects = proxy.trace_suspects();
+    /// assert_eq!(suspects.len(), 1);
+    /// assert!(suspects[0].type_name.contains("Event"));
+    /// ```
+    ///
+    /// [`Trace`]: trace/trait.Trace.html
+    /// [auditing]: #method.set_trace_audit
+    /// [`set_track_alloc_sites`]: #method.set_track_alloc_sites
+    pub fn trace_suspects(&self) -> Vec<TraceSuspect> {
+        self.collector.trace_suspects()
+    }
+
+    /// Returns the objects that have looked like part of a leaked cycle for at
+    /// least as many collections as were passed to [`set_trace_audit`].
+    ///
+    /// These are hints: objects the client holds onto can look the same. Use
+    /// [`trace_suspects`] to find a bad [`Trace`] impl whose objects do get
+    /// freed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::cell::RefCell;
+    /// use ters_gc::{Collector, Gc};
+    /// use ters_gc::trace::{Trace, Tracer};
+    ///
+    /// struct Node<'a> {
+    ///     next: RefCell<Option<Gc<'a, Node<'a>>>>,
+    /// }
+    /// impl<'a> Trace for Node<'a> {
+    ///     fn trace(&self, _tracer: &mut Tracer) {
+    ///         // Forgot to trace `next`
+    ///     }
+    /// }
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    /// proxy.set_trace_audit(2);
+    ///
+    /// let node = proxy.alloc(Node { next: RefCell::new(None) });
+    /// *node.next.borrow_mut() = Some(node.clone());
+    /// drop(node);
+    ///
+    /// proxy.run();
+    /// assert!(proxy.leak_suspects().is_empty());
+    /// proxy.run();
+    ///
+    /// let suspects = proxy.leak_suspects();
+    /// assert_eq!(suspects.len(), 1);
+    /// assert!(suspects[0].type_name.contains("Node"));
+    /// ```
+    ///
+    /// [`set_trace_audit`]: #method.set_trace_audit
+    /// [`trace_suspects`]: #method.trace_suspects
+    /// [`Trace`]: trace/trait.Trace.html
+    pub fn leak_suspects(&self) -> Vec<LeakSuspect> {
+        self.collector.leak_suspects()
+    }
+
+    /// Checks the gc heap for broken invariants.
+    ///
+    /// Returns every violation that was found, so an empty list means the heap
+    /// is consistent. See the [`verify module`] for the list of checks.
+    ///
+    /// Only available with the `debug-checks` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::Collector;
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let _num = proxy.alloc(21);
+    /// proxy.run();
+    ///
+    /// assert_eq!(proxy.verify_heap(), vec![]);
+    /// ```
+    ///
+    /// [`verify module`]: verify/index.html
+    #[cfg(feature = "debug-checks")]
+    pub fn verify_heap(&self) -> Vec<verify::HeapViolation> {
+        verify::verify(&self.collector.allocator)
+    }
+}
+
+impl<'a> Drop for Proxy<'a> {
+    fn drop(&mut self) {
+        self.collector.pending_frees.clear();
+        self.collector.allocator.clear();
+        self.collector.object_pressure = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct LinkedList<'a> {
+        next: Option<Gc<'a, LinkedList<'a>>>,
+    }
+    impl<'a> Trace for LinkedList<'a> {
+        fn trace(&self, tracer: &mut trace::Tracer) {
             tracer.add_target(&self.next);
         }
     }
 
-    fn num_tracked_objs(proxy: &Proxy) -> usize {
-        proxy.num_tracked()
+    fn num_tracked_objs(proxy: &Proxy) -> usize {
+        proxy.num_tracked()
+    }
+
+    #[test]
+    fn collect_while_in_stack_after_drop() {
+        use std::mem::drop;
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        for i in 0..60 {
+            let num = proxy.alloc(i);
+            assert_eq!(*num, i);
+        }
+        let num = proxy.alloc(-1);
+        assert_eq!(*num, -1);
+        assert!(proxy.num_tracked() > 0);
+        proxy.run();
+        assert!(proxy.num_tracked() > 0);
+        drop(num);
+        proxy.run();
+        assert_eq!(0, proxy.num_tracked());
+    }
+
+    #[test]
+    fn msc_allocs_sanity_check() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        {
+            let _num1 = proxy.alloc(42);
+            assert_eq!(num_tracked_objs(&proxy), 1);
+            proxy.run();
+            assert_eq!(num_tracked_objs(&proxy), 1);
+        }
+        proxy.run();
+        assert_eq!(num_tracked_objs(&proxy), 0);
+    }
+
+    #[test]
+    fn collects_after_reaching_threshold() {
+        let mut col = Collector::new();
+        col.set_stress(false);
+        let threshold = col.collection_threshold;
+        let num_useful = 13;
+        let num_wasted = threshold - num_useful;
+        assert!(threshold > num_useful);
+
+        let mut proxy = col.proxy();
+
+        let mut head = LinkedList { next: None };
+        macro_rules! prepend_ll {
+            () => {{
+                let boxed = proxy.alloc(head);
+                LinkedList { next: Some(boxed) }
+            }};
+        }
+        for _ in 0..num_useful {
+            head = prepend_ll!(); //(&mut proxy, head);
+        }
+        {
+            for _ in 0..num_wasted {
+                proxy.alloc(22);
+            }
+        }
+        assert_eq!(num_tracked_objs(&proxy), threshold);
+        head = prepend_ll!(); //(&mut proxy, head);
+        assert_eq!(num_tracked_objs(&proxy), num_useful + 1);
+        assert!(head.next.is_some());
+    }
+
+    #[test]
+    fn pause_works() {
+        let mut col = Collector::new();
+        col.set_stress(false);
+        let threshold = col.collection_threshold;
+        let num_useful = 13;
+        let num_wasted = threshold - num_useful;
+        assert!(threshold > num_useful);
+
+        let mut proxy = col.proxy();
+
+        let mut head = LinkedList { next: None };
+        macro_rules! prepend_ll {
+            () => {{
+                let boxed = proxy.alloc(head);
+                LinkedList { next: Some(boxed) }
+            }};
+        }
+        for _ in 0..num_useful {
+            head = prepend_ll!(); //(&mut proxy, head);
+        }
+        {
+            for _ in 0..num_wasted {
+                proxy.alloc(22);
+            }
+        }
+        assert_eq!(num_tracked_objs(&proxy), threshold);
+        proxy.pause();
+        prepend_ll!(); //(&mut proxy, head);
+        assert_eq!(num_tracked_objs(&proxy), threshold + 1);
+    }
+
+    #[test]
+    fn resume_also_works() {
+        let mut col = Collector::new();
+        col.set_stress(false);
+        let threshold = col.collection_threshold;
+        let num_useful = 13;
+        let num_wasted = threshold - num_useful;
+        assert!(threshold > num_useful);
+
+        let mut proxy = col.proxy();
+        let mut head = LinkedList { next: None };
+        macro_rules! prepend_ll {
+            () => {{
+                let boxed = proxy.alloc(head);
+                LinkedList { next: Some(boxed) }
+            }};
+        }
+        for _ in 0..num_useful {
+            head = prepend_ll!(); //(&mut proxy, head);
+        }
+        for _ in 0..num_wasted {
+            proxy.alloc(22);
+        }
+        assert_eq!(num_tracked_objs(&proxy), threshold);
+        proxy.pause();
+        proxy.resume();
+        prepend_ll!(); //(&mut proxy, head);
+        assert_eq!(num_tracked_objs(&proxy), num_useful + 1);
+    }
+
+    #[test]
+    fn self_ref_cycle() {
+        use std::cell::RefCell;
+        struct SelfRef<'a> {
+            self_ptr: RefCell<Option<Gc<'a, SelfRef<'a>>>>,
+        }
+        impl<'a> Trace for SelfRef<'a> {
+            fn trace(&self, tracer: &mut trace::Tracer) {
+                tracer.add_target(&self.self_ptr);
+            }
+        }
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        {
+            let ptr = proxy.alloc(SelfRef {
+                self_ptr: RefCell::new(None),
+            });
+            *ptr.self_ptr.borrow_mut() = Some(ptr.clone());
+
+            proxy.run();
+        }
+
+        proxy.run();
+        assert_eq!(num_tracked_objs(&proxy), 0);
+    }
+
+    #[test]
+    fn pointed_to_by_heap_root_arent_freed() {
+        struct List<'a> {
+            ptr: Option<Gc<'a, List<'a>>>,
+        }
+        impl<'a> Trace for List<'a> {
+            fn trace(&self, tracer: &mut trace::Tracer) {
+                tracer.add_target(&self.ptr);
+            }
+        }
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        let _root = {
+            let leaf = proxy.alloc(List { ptr: None });
+            let root = proxy.alloc(List { ptr: Some(leaf) });
+            Box::new(root)
+        };
+
+        proxy.run();
+        assert_eq!(num_tracked_objs(&proxy), 2);
+    }
+
+    #[test]
+    // A.K.A. Crate doc test
+    fn min_cycle() {
+        use std::cell::RefCell;
+
+        // A struct that can hold references to itself
+        struct CyclicStruct<'a>(RefCell<Option<Gc<'a, CyclicStruct<'a>>>>);
+
+        // All things in the gc heap need to impl `Trace`
+        impl<'a> Trace for CyclicStruct<'a> {
+            fn trace(&self, tracer: &mut trace::Tracer) {
+                // Tell the tracer where to find our gc pointer
+                tracer.add_target(&self.0);
+            }
+        }
+
+        // Make a new collector to keep the gc state
+        let mut col = Collector::new();
+
+        // Make a Proxy to access the API
+        let mut proxy = col.proxy();
+
+        // Do some computations that are best expressed with a cyclic data structure
+        {
+            let thing1 = proxy.alloc(CyclicStruct(RefCell::new(None)));
+            let thing2 = proxy.alloc(CyclicStruct(RefCell::new(Some(thing1.clone()))));
+            *thing1.0.borrow_mut() = Some(thing2.clone());
+        }
+
+        // Collect garbage
+        proxy.run();
+
+        // And we've successfully cleaned up the unused cyclic data
+        assert_eq!(proxy.num_tracked(), 0);
+    }
+
+    #[test]
+    fn get_current_threshold() {
+        let mut col = Collector::new();
+        col.set_stress(false);
+        let mut proxy = col.proxy();
+        let threshold = proxy.threshold();
+        assert_eq!(proxy.collector.collection_threshold, threshold);
+
+        let num_useful = 13;
+        let num_wasted = threshold - num_useful;
+        assert!(threshold > num_useful);
+
+        let mut head = LinkedList { next: None };
+        macro_rules! prepend_ll {
+            () => {{
+                let boxed = proxy.alloc(head);
+                LinkedList { next: Some(boxed) }
+            }};
+        }
+        for _ in 0..num_useful {
+            head = prepend_ll!(); //(&mut proxy, head);
+        }
+        for _ in 0..num_wasted {
+            proxy.alloc(22);
+        }
+        assert_eq!(proxy.num_tracked(), threshold);
+        head = prepend_ll!(); //(&mut proxy, head);
+        assert_eq!(proxy.num_tracked(), num_useful + 1);
+        assert!(head.next.is_some());
+
+        let after_thresh = proxy.threshold();
+        assert_eq!(20, after_thresh);
+    }
+
+    #[test]
+    fn set_sweep_factor() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        proxy.set_threshold_growth(0.1);
+        let factor1 = proxy.collector.sweep_factor;
+        assert_eq!(factor1, 0.1);
+        proxy.set_threshold_growth(0.9);
+        let factor2 = proxy.collector.sweep_factor;
+        assert_eq!(factor2, 0.9);
+    }
+    #[test]
+    fn histogram_groups_by_site() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        proxy.set_track_alloc_sites(true);
+
+        let mut nums = vec![];
+        for i in 0..3 {
+            nums.push(proxy.alloc(i));
+        }
+        let other_site = proxy.alloc(3);
+        let line = line!() - 1;
+
+        let histogram = proxy.heap_histogram();
+        assert_eq!(histogram.len(), 2);
+        assert_eq!(histogram[0].count, 3);
+        assert_eq!(histogram[1].count, 1);
+        assert_eq!(histogram[1].location.unwrap().line(), line);
+        assert!(histogram.iter().all(|entry| entry.type_name == "i32"));
+        assert!(histogram.iter().all(|entry| entry.backtrace.is_none()));
+
+        drop(nums);
+        drop(other_site);
+        proxy.run();
+        assert!(proxy.heap_histogram().is_empty());
+    }
+
+    #[test]
+    fn untracked_allocs_have_no_site() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        let _untracked = proxy.alloc(0);
+        proxy.set_track_alloc_sites(true);
+        let _tracked = proxy.alloc(0);
+
+        let histogram = proxy.heap_histogram();
+        assert_eq!(histogram.len(), 2);
+        assert_eq!(
+            histogram
+                .iter()
+                .filter(|entry| entry.location.is_none())
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn backtraces_are_sampled() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        proxy.set_track_alloc_sites(true);
+        proxy.set_backtrace_sample_rate(3);
+        proxy.pause();
+
+        for _ in 0..7 {
+            proxy.alloc(());
+        }
+
+        let num_sampled = proxy
+            .collector
+            .allocator
+            .items
+            .iter()
+            .filter(|info| info.site.as_ref().unwrap().backtrace.is_some())
+            .count();
+        assert_eq!(num_sampled, 2);
+    }
+
+    struct Pair<'a> {
+        other: ::std::cell::RefCell<Option<Gc<'a, Pair<'a>>>>,
+        trace_other: bool,
+    }
+    impl<'a> Trace for Pair<'a> {
+        fn trace(&self, tracer: &mut trace::Tracer) {
+            if self.trace_other {
+                tracer.add_target(&self.other);
+            }
+        }
+    }
+
+    #[test]
+    fn audit_reports_untraced_refs_from_freed_objects() {
+        let mut col = Collector::new();
+        // Counts the exact number of collections
+        col.set_stress(false);
+        let mut proxy = col.proxy();
+        proxy.set_trace_audit(3);
+        proxy.set_track_alloc_sites(true);
+
+        let target = proxy.alloc(Pair {
+            other: Default::default(),
+            trace_other: true,
+        });
+        for run in 0..3 {
+            // Garbage whose edge to `target` is never traced
+            let untraced = proxy.alloc(Pair {
+                other: Default::default(),
+                trace_other: false,
+            });
+            *untraced.other.borrow_mut() = Some(target.clone());
+            // A traced edge to `target` is expected to be dropped
+            let traced = proxy.alloc(vec![target.clone()]);
+            drop((untraced, traced));
+
+            proxy.run();
+            assert_eq!(num_tracked_objs(&proxy), 1, "run {}", run);
+        }
+
+        // Names the type that didn't trace, not the one it pointed to
+        let suspects = proxy.trace_suspects();
+        assert_eq!(suspects.len(), 1);
+        assert!(suspects[0].type_name.contains("Pair"));
+        assert_eq!(suspects[0].untraced_refs, 3);
+        assert_eq!(suspects[0].runs, 3);
+        assert_eq!(suspects[0].location.unwrap().file(), file!());
+
+        proxy.set_trace_audit(0);
+        assert!(proxy.trace_suspects().is_empty());
+    }
+
+    #[test]
+    fn audit_reports_untraced_cycle_as_leak() {
+        let mut col = Collector::new();
+        // Counts the exact number of collections
+        col.set_stress(false);
+        let mut proxy = col.proxy();
+        proxy.set_trace_audit(3);
+        proxy.set_track_alloc_sites(true);
+
+        let pair = || Pair {
+            other: Default::default(),
+            trace_other: false,
+        };
+        let a = proxy.alloc(pair());
+        let b = proxy.alloc(pair());
+        // Neither edge is ever traced
+        *a.other.borrow_mut() = Some(b.clone());
+        *b.other.borrow_mut() = Some(a.clone());
+        drop((a, b));
+
+        for run in 0..3 {
+            assert!(proxy.leak_suspects().is_empty(), "run {}", run);
+            proxy.run();
+            assert_eq!(num_tracked_objs(&proxy), 2);
+        }
+
+        // Never freed, so only the leak heuristic can see it
+        assert!(proxy.trace_suspects().is_empty());
+        let suspects = proxy.leak_suspects();
+        assert_eq!(suspects.len(), 2);
+        for suspect in &suspects {
+            assert!(suspect.type_name.contains("Pair"));
+            assert_eq!(suspect.external_refs, 1);
+            assert_eq!(suspect.runs, 3);
+            assert_eq!(suspect.location.unwrap().file(), file!());
+        }
+
+        proxy.set_trace_audit(0);
+        assert!(proxy.leak_suspects().is_empty());
+    }
+
+    #[test]
+    fn audit_restarts_when_external_refs_change() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        proxy.set_trace_audit(2);
+
+        let root = proxy.alloc(Pair {
+            other: Default::default(),
+            trace_other: false,
+        });
+        let mut clones = vec![];
+        for _ in 0..5 {
+            // The client is still handing out references to it
+            clones.push(root.clone());
+            proxy.run();
+            assert!(proxy.leak_suspects().is_empty());
+        }
+    }
+
+    #[test]
+    fn audit_ignores_leaves() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        proxy.set_trace_audit(1);
+
+        let root = proxy.alloc(String::from("root"));
+        let _clone = root.clone();
+        for _ in 0..5 {
+            let _garbage = proxy.alloc(1);
+            proxy.run();
+        }
+        assert!(proxy.trace_suspects().is_empty());
+        assert!(proxy.leak_suspects().is_empty());
+    }
+
+    #[test]
+    fn audit_ignores_held_traced_roots() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        proxy.set_trace_audit(3);
+
+        let tail = proxy.alloc(Pair {
+            other: Default::default(),
+            trace_other: true,
+        });
+        let head = proxy.alloc(Pair {
+            other: ::std::cell::RefCell::new(Some(tail)),
+            trace_other: true,
+        });
+        for _ in 0..5 {
+            let garbage = proxy.alloc(Pair {
+                other: ::std::cell::RefCell::new(Some(head.clone())),
+                trace_other: true,
+            });
+            drop(garbage);
+            proxy.run();
+        }
+        assert_eq!(num_tracked_objs(&proxy), 2);
+        assert!(proxy.trace_suspects().is_empty());
+    }
+
+    #[test]
+    fn objects_use_custom_allocator() {
+        use std::alloc::{Layout, System};
+        use std::cell::Cell;
+
+        struct Counting(Rc<Cell<usize>>, Rc<Cell<usize>>);
+        unsafe impl GlobalAlloc for Counting {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                self.0.set(self.0.get() + 1);
+                System.alloc(layout)
+            }
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                self.1.set(self.1.get() + 1);
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        let allocs = Rc::new(Cell::new(0));
+        let deallocs = Rc::new(Cell::new(0));
+        let mut col = Collector::with_allocator(Counting(allocs.clone(), deallocs.clone()));
+        {
+            let mut proxy = col.proxy();
+            let _small = proxy.alloc(1u8);
+            let _large = proxy.alloc([[0u64; 32]; 4]);
+            // Pages for the small object and the handles, and the large object
+            assert_eq!(allocs.get(), 3);
+            assert_eq!(deallocs.get(), 0);
+        }
+        drop(col);
+        assert_eq!(deallocs.get(), 3);
+    }
+
+    // Quarantined slots can't be moved into
+    #[cfg(not(feature = "quarantine"))]
+    #[test]
+    fn compaction_keeps_pointers_valid() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        unsafe { proxy.set_compacting(true) };
+        proxy.pause();
+
+        // Interleave a linked list with garbage
+        let mut head = proxy.alloc(LinkedList { next: None });
+        let mut weaks = vec![Gc::downgrade(&head)];
+        for _ in 0..500 {
+            let _garbage = proxy.alloc(LinkedList { next: None });
+            head = proxy.alloc(LinkedList { next: Some(head) });
+            weaks.push(Gc::downgrade(&head));
+        }
+        let before = proxy.slab_stats();
+        let old_addr = head.nonnull_box_ptr();
+
+        proxy.run();
+        assert_eq!(num_tracked_objs(&proxy), 501);
+        assert!(proxy.slab_stats().reserved_bytes() < before.reserved_bytes());
+        assert_ne!(head.nonnull_box_ptr(), old_addr);
+
+        let mut len = 1;
+        let mut node = head.clone();
+        while let Some(next) = node.next.clone() {
+            node = next;
+            len += 1;
+        }
+        assert_eq!(len, 501);
+        assert!(weaks.iter().all(|weak| weak.upgrade().is_some()));
+
+        drop(head);
+        drop(node);
+        proxy.run();
+        assert_eq!(num_tracked_objs(&proxy), 0);
+        assert!(weaks.iter().all(|weak| !weak.is_alive()));
+    }
+
+    #[cfg(not(feature = "quarantine"))]
+    #[test]
+    fn pinned_objects_dont_move() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        unsafe { proxy.set_compacting(true) };
+
+        let garbage: Vec<_> = (0..10u64).map(|i| proxy.alloc(i)).collect();
+        let pinned = proxy.alloc(10u64);
+        let unpinned = proxy.alloc(11u64);
+        drop(garbage);
+
+        proxy.pin(&pinned);
+        proxy.pin(&pinned);
+        proxy.unpin(&pinned);
+        let pinned_addr = pinned.nonnull_box_ptr();
+        let unpinned_addr = unpinned.nonnull_box_ptr();
+        proxy.run();
+
+        assert_eq!(pinned.nonnull_box_ptr(), pinned_addr);
+        assert_ne!(unpinned.nonnull_box_ptr(), unpinned_addr);
+        assert_eq!((*pinned, *unpinned), (10, 11));
+    }
+
+    // Quarantine keeps freed slots from being reused
+    #[cfg(not(feature = "quarantine"))]
+    #[test]
+    fn sweep_makes_slots_reusable() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        let nums: Vec<_> = (0..1000u64).map(|i| proxy.alloc(i)).collect();
+        let full = proxy.slab_stats();
+        drop(nums);
+        proxy.run();
+
+        let swept = proxy.slab_stats();
+        assert_eq!(swept.reserved_bytes(), full.reserved_bytes());
+        assert_eq!(swept.requested_bytes(), 0);
+        assert_eq!(swept.fragmentation(), 1.0);
+
+        let _nums: Vec<_> = (0..1000u64).map(|i| proxy.alloc(i)).collect();
+        assert_eq!(proxy.slab_stats(), full);
+    }
+
+    #[test]
+    fn memory_pressure_triggers_collection() {
+        let mut col = Collector::new();
+        col.set_stress(false);
+        let mut proxy = col.proxy();
+
+        let _garbage = proxy.alloc(0);
+        drop(_garbage);
+        let threshold = proxy.pressure_threshold();
+        proxy.add_memory_pressure(threshold - 1);
+        let _a = proxy.alloc(1);
+        assert_eq!(num_tracked_objs(&proxy), 2);
+
+        proxy.add_memory_pressure(1);
+        let _b = proxy.alloc(2);
+        assert_eq!(num_tracked_objs(&proxy), 2);
+        // The pressure is still there, so the threshold grows past it
+        assert_eq!(proxy.memory_pressure(), threshold);
+        assert!(proxy.pressure_threshold() > threshold + MIN_PRESSURE_THRESHOLD);
+    }
+
+    #[test]
+    fn object_pressure_is_released_with_object() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        let kept = proxy.alloc(1);
+        let swept = proxy.alloc(2);
+        let unwrapped = proxy.alloc(3);
+        proxy.add_memory_pressure_for(&kept, 10);
+        proxy.add_memory_pressure_for(&swept, 20);
+        proxy.add_memory_pressure_for(&unwrapped, 40);
+        assert_eq!(proxy.memory_pressure(), 70);
+
+        drop(swept);
+        proxy.run();
+        assert_eq!(proxy.memory_pressure(), 50);
+
+        assert_eq!(Gc::try_unwrap(unwrapped, &mut proxy).unwrap(), 3);
+        assert_eq!(proxy.memory_pressure(), 10);
+
+        proxy.remove_memory_pressure_for(&kept, 100);
+        assert_eq!(proxy.memory_pressure(), 0);
     }
 
     #[test]
-    fn collect_while_in_stack_after_drop() {
-        use std::mem::drop;
+    fn saturated_object_pressure_is_released() {
         let mut col = Collector::new();
         let mut proxy = col.proxy();
 
-        for i in 0..60 {
-            let num = proxy.alloc(i);
-            assert_eq!(*num, i);
+        let kept = proxy.alloc(1);
+        proxy.add_memory_pressure_for(&kept, 10);
+        let swept = vec![proxy.alloc(2), proxy.alloc(3)];
+        for gc in &swept {
+            proxy.add_memory_pressure_for(gc, usize::MAX);
         }
-        let num = proxy.alloc(-1);
-        assert_eq!(*num, -1);
-        assert!(proxy.num_tracked() > 0);
-        proxy.run();
-        assert!(proxy.num_tracked() > 0);
-        drop(num);
+        assert_eq!(proxy.memory_pressure(), usize::MAX);
+        drop(swept);
+
         proxy.run();
-        assert_eq!(0, proxy.num_tracked());
+        assert_eq!(proxy.memory_pressure(), 0);
+        proxy.remove_memory_pressure_for(&kept, 10);
+        assert_eq!(proxy.memory_pressure(), 0);
     }
 
     #[test]
-    fn msc_allocs_sanity_check() {
+    fn measured_objects_are_remeasured_on_collection() {
+        use std::cell::RefCell;
+
         let mut col = Collector::new();
         let mut proxy = col.proxy();
+        proxy.set_track_alloc_sites(true);
+
+        let buffer = proxy.alloc_measured(RefCell::new(Vec::<u64>::with_capacity(4)));
+        let _plain = proxy.alloc(Vec::<u64>::with_capacity(4));
+        assert_eq!(proxy.memory_pressure(), 32);
+
+        buffer.borrow_mut().reserve_exact(64);
+        let histogram = proxy.heap_histogram();
+        let entry = histogram
+            .iter()
+            .find(|entry| entry.type_name.contains("RefCell"))
+            .unwrap();
+        assert_eq!(entry.heap_bytes, 64 * 8);
+        assert!(histogram.iter().any(|entry| entry.heap_bytes == 0));
+
+        // The pressure only catches up during collection
+        assert_eq!(proxy.memory_pressure(), 32);
+        proxy.run();
+        assert_eq!(proxy.memory_pressure(), 64 * 8);
+
+        assert!(Gc::try_unwrap(buffer, &mut proxy).is_ok());
+        assert_eq!(proxy.memory_pressure(), 0);
+    }
+
+    #[test]
+    fn object_pressure_is_released_with_proxy() {
+        let mut col = Collector::new();
         {
-            let _num1 = proxy.alloc(42);
-            assert_eq!(num_tracked_objs(&proxy), 1);
-            proxy.run();
-            assert_eq!(num_tracked_objs(&proxy), 1);
+            let mut proxy = col.proxy();
+            let num = proxy.alloc(1);
+            proxy.add_memory_pressure_for(&num, 10);
+            proxy.add_memory_pressure(5);
         }
-        proxy.run();
-        assert_eq!(num_tracked_objs(&proxy), 0);
+        assert_eq!(col.proxy().memory_pressure(), 5);
     }
 
     #[test]
-    fn collects_after_reaching_threshold() {
+    fn lazy_sweep_frees_in_batches() {
         let mut col = Collector::new();
-        let threshold = col.collection_threshold;
-        let num_useful = 13;
-        let num_wasted = threshold - num_useful;
-        assert!(threshold > num_useful);
+        col.set_stress(false);
+        let mut proxy = col.proxy();
+        proxy.set_lazy_sweep(3);
+
+        let kept = proxy.alloc(LinkedList { next: None });
+        let weaks: Vec<_> = (0..10)
+            .map(|_| Gc::downgrade(&proxy.alloc(LinkedList { next: None })))
+            .collect();
+        proxy.run();
+        assert_eq!(proxy.pending_sweep(), 10);
+        assert_eq!(num_tracked_objs(&proxy), 11);
+        // Unreachable objects are dead even before they are freed
+        assert!(weaks.iter().all(|weak| weak.upgrade().is_none()));
+        assert_eq!(proxy.heap_histogram()[0].count, 1);
+
+        let _a = proxy.alloc(LinkedList { next: None });
+        assert_eq!(proxy.pending_sweep(), 7);
+        assert_eq!(num_tracked_objs(&proxy), 9);
+
+        proxy.finish_sweep();
+        assert_eq!(proxy.pending_sweep(), 0);
+        assert_eq!(num_tracked_objs(&proxy), 2);
+        assert!(Gc::is_alive(&kept));
+    }
 
+    #[test]
+    fn collecting_finishes_lazy_sweep() {
+        let mut col = Collector::new();
+        col.set_stress(false);
         let mut proxy = col.proxy();
+        proxy.set_lazy_sweep(1);
 
-        let mut head = LinkedList { next: None };
-        macro_rules! prepend_ll {
-            () => {{
-                let boxed = proxy.alloc(head);
-                LinkedList { next: Some(boxed) }
-            }};
-        }
-        for _ in 0..num_useful {
-            head = prepend_ll!(); //(&mut proxy, head);
-        }
-        {
-            for _ in 0..num_wasted {
-                proxy.alloc(22);
-            }
+        for _ in 0..5 {
+            proxy.alloc(LinkedList { next: None });
         }
-        assert_eq!(num_tracked_objs(&proxy), threshold);
-        head = prepend_ll!(); //(&mut proxy, head);
-        assert_eq!(num_tracked_objs(&proxy), num_useful + 1);
-        assert!(head.next.is_some());
+        proxy.run();
+        assert_eq!(proxy.pending_sweep(), 5);
+        proxy.run();
+        assert_eq!(proxy.pending_sweep(), 0);
+        assert_eq!(num_tracked_objs(&proxy), 0);
     }
 
     #[test]
-    fn pause_works() {
-        let mut col = Collector::new();
-        let threshold = col.collection_threshold;
-        let num_useful = 13;
-        let num_wasted = threshold - num_useful;
-        assert!(threshold > num_useful);
+    fn lazy_sweep_frees_cycles() {
+        use std::cell::RefCell;
 
+        let mut col = Collector::new();
+        col.set_stress(false);
         let mut proxy = col.proxy();
+        proxy.set_lazy_sweep(1);
 
-        let mut head = LinkedList { next: None };
-        macro_rules! prepend_ll {
-            () => {{
-                let boxed = proxy.alloc(head);
-                LinkedList { next: Some(boxed) }
-            }};
-        }
-        for _ in 0..num_useful {
-            head = prepend_ll!(); //(&mut proxy, head);
+        struct Node<'a>(RefCell<Option<Gc<'a, Node<'a>>>>);
+        impl<'a> Trace for Node<'a> {
+            fn trace(&self, tracer: &mut trace::Tracer) {
+                tracer.add_target(&self.0);
+            }
         }
         {
-            for _ in 0..num_wasted {
-                proxy.alloc(22);
-            }
+            let a = proxy.alloc(Node(RefCell::new(None)));
+            let b = proxy.alloc(Node(RefCell::new(Some(a.clone()))));
+            *a.0.borrow_mut() = Some(b.clone());
         }
-        assert_eq!(num_tracked_objs(&proxy), threshold);
-        proxy.pause();
-        prepend_ll!(); //(&mut proxy, head);
-        assert_eq!(num_tracked_objs(&proxy), threshold + 1);
+        proxy.run();
+        assert_eq!(proxy.pending_sweep(), 2);
+        let _c = proxy.alloc(Node(RefCell::new(None)));
+        let _d = proxy.alloc(Node(RefCell::new(None)));
+        assert_eq!(proxy.pending_sweep(), 0);
+        assert_eq!(num_tracked_objs(&proxy), 2);
     }
 
     #[test]
-    fn resume_also_works() {
-        let mut col = Collector::new();
-        let threshold = col.collection_threshold;
-        let num_useful = 13;
-        let num_wasted = threshold - num_useful;
-        assert!(threshold > num_useful);
+    fn leaves_are_never_traced() {
+        struct NeverTrace(u32);
+        impl Trace for NeverTrace {
+            fn trace(&self, _: &mut trace::Tracer) {
+                panic!("traced a leaf");
+            }
+            fn needs_trace() -> bool {
+                false
+            }
+        }
 
+        let mut col = Collector::new();
         let mut proxy = col.proxy();
-        let mut head = LinkedList { next: None };
-        macro_rules! prepend_ll {
-            () => {{
-                let boxed = proxy.alloc(head);
-                LinkedList { next: Some(boxed) }
-            }};
-        }
-        for _ in 0..num_useful {
-            head = prepend_ll!(); //(&mut proxy, head);
-        }
-        for _ in 0..num_wasted {
-            proxy.alloc(22);
-        }
-        assert_eq!(num_tracked_objs(&proxy), threshold);
-        proxy.pause();
-        proxy.resume();
-        prepend_ll!(); //(&mut proxy, head);
-        assert_eq!(num_tracked_objs(&proxy), num_useful + 1);
+
+        let kept = proxy.alloc(NeverTrace(1));
+        proxy.alloc(vec![NeverTrace(2)]);
+        let _bytes = proxy.alloc_slice(&[1u8, 2, 3]);
+        let _name = proxy.alloc_str("leaf");
+        let _closure = proxy.alloc_closure(5u8, |num, ()| *num);
+        proxy.run();
+        assert_eq!(num_tracked_objs(&proxy), 4);
+        assert!(proxy
+            .collector
+            .allocator
+            .items
+            .iter()
+            .all(|info| info.is_leaf()));
+        assert_eq!(kept.0, 1);
     }
 
     #[test]
-    fn self_ref_cycle() {
-        use std::cell::RefCell;
-        struct SelfRef<'a> {
-            self_ptr: RefCell<Option<Gc<'a, SelfRef<'a>>>>,
-        }
-        impl<'a> Trace for SelfRef<'a> {
+    fn cell_edges_are_traced() {
+        use std::cell::Cell;
+
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        struct Node<'a>(Cell<Option<Gc<'a, Node<'a>>>>);
+        impl<'a> Trace for Node<'a> {
             fn trace(&self, tracer: &mut trace::Tracer) {
-                tracer.add_target(&self.self_ptr);
+                tracer.add_target(&self.0);
             }
         }
+        {
+            let a = proxy.alloc(Node(Cell::new(None)));
+            let b = proxy.alloc(Node(Cell::new(Some(a.clone()))));
+            a.0.set(Some(b));
+            proxy.run();
+            assert_eq!(num_tracked_objs(&proxy), 2);
+        }
+        proxy.run();
+        assert_eq!(num_tracked_objs(&proxy), 0);
+    }
+
+    #[test]
+    fn locked_mutex_postpones_collection() {
+        use std::sync::Mutex;
+
         let mut col = Collector::new();
+        col.set_stress(false);
         let mut proxy = col.proxy();
-        {
-            let ptr = proxy.alloc(SelfRef {
-                self_ptr: RefCell::new(None),
-            });
-            *ptr.self_ptr.borrow_mut() = Some(ptr.clone());
 
+        let mutex = proxy.alloc(Mutex::new(5));
+        proxy.alloc(1);
+        {
+            let _lock = mutex.lock().unwrap();
             proxy.run();
+            assert_eq!(num_tracked_objs(&proxy), 2);
         }
-
         proxy.run();
-        assert_eq!(num_tracked_objs(&proxy), 0);
+        assert_eq!(num_tracked_objs(&proxy), 1);
     }
 
     #[test]
-    fn pointed_to_by_heap_root_arent_freed() {
-        struct List<'a> {
-            ptr: Option<Gc<'a, List<'a>>>,
+    fn postponed_collection_backs_off() {
+        use std::cell::Cell;
+        use std::sync::Mutex;
+
+        struct Counted<'a> {
+            traces: &'a Cell<usize>,
+            mutex: Mutex<()>,
         }
-        impl<'a> Trace for List<'a> {
+        impl<'a> Trace for Counted<'a> {
             fn trace(&self, tracer: &mut trace::Tracer) {
-                tracer.add_target(&self.ptr);
+                self.traces.set(self.traces.get() + 1);
+                tracer.add_target(&self.mutex);
             }
         }
+
+        let traces = Cell::new(0);
         let mut col = Collector::new();
+        col.set_stress(false);
         let mut proxy = col.proxy();
-        let _root = {
-            let leaf = proxy.alloc(List { ptr: None });
-            let root = proxy.alloc(List { ptr: Some(leaf) });
-            Box::new(root)
-        };
 
-        proxy.run();
-        assert_eq!(num_tracked_objs(&proxy), 2);
+        let counted = proxy.alloc(Counted {
+            traces: &traces,
+            mutex: Mutex::new(()),
+        });
+        let _lock = counted.mutex.lock().unwrap();
+        let mut nums = vec![];
+        for i in 0..1000 {
+            nums.push(proxy.alloc(i));
+        }
+        // Each postponed collection traces `counted` once
+        assert!(traces.get() > 0);
+        assert!(traces.get() < 20, "traced {} times", traces.get());
     }
 
     #[test]
-    // A.K.A. Crate doc test
-    fn min_cycle() {
+    fn collect_through_rc_cycle() {
         use std::cell::RefCell;
+        use std::rc::Rc;
 
-        // A struct that can hold references to itself
-        struct CyclicStruct<'a>(RefCell<Option<Gc<'a, CyclicStruct<'a>>>>);
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
 
-        // All things in the gc heap need to impl `Trace`
-        impl<'a> Trace for CyclicStruct<'a> {
+        struct Node<'a> {
+            next: RefCell<Option<Rc<Node<'a>>>>,
+            num: Gc<'a, i32>,
+        }
+        impl<'a> Trace for Node<'a> {
             fn trace(&self, tracer: &mut trace::Tracer) {
-                // Tell the tracer where to find our gc pointer
-                tracer.add_target(&self.0);
+                tracer.add_target(&self.next);
+                tracer.add_target(&self.num);
             }
         }
 
-        // Make a new collector to keep the gc state
-        let mut col = Collector::new();
+        let num = proxy.alloc(1);
+        let a = Rc::new(Node {
+            next: RefCell::new(None),
+            num: num.clone(),
+        });
+        let b = Rc::new(Node {
+            next: RefCell::new(Some(a.clone())),
+            num: num.clone(),
+        });
+        *a.next.borrow_mut() = Some(b.clone());
+        let holder = proxy.alloc(vec![a.clone(), b.clone(), a.clone()]);
+        drop(num);
 
-        // Make a Proxy to access the API
+        proxy.run();
+        assert_eq!(num_tracked_objs(&proxy), 2);
+        assert_eq!(*holder[0].num, 1);
+
+        *a.next.borrow_mut() = None;
+    }
+
+    // Quarantined slots can't be moved into
+    #[cfg(not(feature = "quarantine"))]
+    #[test]
+    fn compaction_keeps_unsized_values() {
+        let mut col = Collector::new();
         let mut proxy = col.proxy();
+        unsafe { proxy.set_compacting(true) };
+        proxy.pause();
 
-        // Do some computations that are best expressed with a cyclic data structure
-        {
-            let thing1 = proxy.alloc(CyclicStruct(RefCell::new(None)));
-            let thing2 = proxy.alloc(CyclicStruct(RefCell::new(Some(thing1.clone()))));
-            *thing1.0.borrow_mut() = Some(thing2.clone());
+        let mut strings = vec![];
+        for i in 0..200 {
+            let _garbage = proxy.alloc_str("garbage");
+            strings.push(proxy.alloc_str(&i.to_string()));
         }
+        let old_addrs: Vec<_> = strings.iter().map(|s| s.nonnull_box_ptr()).collect();
 
-        // Collect garbage
         proxy.run();
-
-        // And we've successfully cleaned up the unused cyclic data
-        assert_eq!(proxy.num_tracked(), 0);
+        assert_eq!(num_tracked_objs(&proxy), 200);
+        // Pages aren't guaranteed to come back in address order, so only
+        // some of the strings have a free slot below them
+        let moved = strings.iter().zip(old_addrs).filter(|&(string, old_addr)| {
+            !std::ptr::addr_eq(string.nonnull_box_ptr().as_ptr(), old_addr.as_ptr())
+        });
+        assert!(moved.count() > 0);
+        for (i, string) in strings.iter().enumerate() {
+            assert_eq!(&**string, &*i.to_string());
+        }
     }
 
     #[test]
-    fn get_current_threshold() {
+    fn cyclic_objects_are_collected() {
         let mut col = Collector::new();
         let mut proxy = col.proxy();
-        let threshold = proxy.threshold();
-        assert_eq!(proxy.collector.collection_threshold, threshold);
-
-        let num_useful = 13;
-        let num_wasted = threshold - num_useful;
-        assert!(threshold > num_useful);
 
-        let mut head = LinkedList { next: None };
-        macro_rules! prepend_ll {
-            () => {{
-                let boxed = proxy.alloc(head);
-                LinkedList { next: Some(boxed) }
-            }};
+        struct Node<'a> {
+            this: Weak<'a, Node<'a>>,
+            num: Gc<'a, i32>,
         }
-        for _ in 0..num_useful {
-            head = prepend_ll!(); //(&mut proxy, head);
+        impl<'a> Trace for Node<'a> {
+            fn trace(&self, tracer: &mut trace::Tracer) {
+                tracer.add_target(&self.this);
+                tracer.add_target(&self.num);
+            }
         }
-        for _ in 0..num_wasted {
-            proxy.alloc(22);
+
+        let num = proxy.alloc(1);
+        let node = proxy.alloc_cyclic(|this| Node {
+            this: this.clone(),
+            num,
+        });
+        let this = node.this.upgrade().unwrap();
+        assert!(Gc::ptr_eq(&node, &this));
+        assert_eq!(*this.num, 1);
+        drop(this);
+
+        proxy.run();
+        assert_eq!(num_tracked_objs(&proxy), 2);
+        let weak = Gc::downgrade(&node);
+        drop(node);
+        proxy.run();
+        assert_eq!(num_tracked_objs(&proxy), 0);
+        assert!(!weak.is_alive());
+    }
+
+    #[test]
+    fn stress_mode_collects_on_every_alloc() {
+        let mut col = Collector::new();
+        col.set_stress(true);
+        let mut proxy = col.proxy();
+
+        for i in 0..10 {
+            let _num = proxy.alloc(i);
+            assert_eq!(num_tracked_objs(&proxy), 1);
         }
-        assert_eq!(proxy.num_tracked(), threshold);
-        head = prepend_ll!(); //(&mut proxy, head);
-        assert_eq!(proxy.num_tracked(), num_useful + 1);
-        assert!(head.next.is_some());
 
-        let after_thresh = proxy.threshold();
-        assert_eq!(20, after_thresh);
+        proxy.pause();
+        for i in 0..10 {
+            let _num = proxy.alloc(i);
+        }
+        assert_eq!(num_tracked_objs(&proxy), 11);
     }
 
     #[test]
-    fn set_sweep_factor() {
+    fn audit_ignores_traced_cycle() {
         let mut col = Collector::new();
         let mut proxy = col.proxy();
-        proxy.set_threshold_growth(0.1);
-        let factor1 = proxy.collector.sweep_factor;
-        assert_eq!(factor1, 0.1);
-        proxy.set_threshold_growth(0.9);
-        let factor2 = proxy.collector.sweep_factor;
-        assert_eq!(factor2, 0.9);
+        proxy.set_trace_audit(1);
+
+        let a = proxy.alloc(Pair {
+            other: Default::default(),
+            trace_other: true,
+        });
+        let b = proxy.alloc(Pair {
+            other: Default::default(),
+            trace_other: true,
+        });
+        *a.other.borrow_mut() = Some(b.clone());
+        *b.other.borrow_mut() = Some(a.clone());
+        drop(b);
+
+        // `a` is held on the stack, but `b` traces it, so it isn't a suspect
+        proxy.run();
+        assert!(proxy.trace_suspects().is_empty());
+        assert!(proxy.leak_suspects().is_empty());
+
+        drop(a);
+        proxy.run();
+        assert_eq!(num_tracked_objs(&proxy), 0);
+        assert!(proxy.trace_suspects().is_empty());
+        assert!(proxy.leak_suspects().is_empty());
     }
+
     //    /// # use std::error::Error;
     //    /// #
     //    /// # fn try_main() -> Result<(), Box<Error>> {
@@ -961,5 +3226,4 @@ mod tests {
     //    /// # fn main() {
     //    /// #     try_main().unwrap();
     //    /// # }
-
 }
@@ -9,33 +9,41 @@
 //! [`Weak`] pointers do not contribute to ownership of an object, but can be
 //! turned into owning [`Gc`] pointers.
 //!
+//! A [`GcAny`] owns an object of any type, and can be turned back into a
+//! [`Gc`] of the object's type.
+//!
 //! [`Gc`]: struct.Gc.html
 //! [`Weak`]: struct.Weak.html
+//! [`GcAny`]: struct.GcAny.html
 //! [`clone`]: https://doc.rust-lang.org/std/clone/trait.Clone.html#tymethod.clone
 
+#[cfg(feature = "quarantine")]
+use allocator::POISON_WORD;
+use std::alloc::{dealloc, Layout};
+use std::any::{Any, TypeId};
 use std::cell::Cell;
-use std::cell::RefCell;
+use std::fmt;
 use std::marker::PhantomData;
+use std::mem::{align_of, size_of, transmute_copy, MaybeUninit};
 use std::ops::Deref;
 use std::ptr::NonNull;
-use std::rc::Rc;
 use trace::Trace;
 use Proxy;
+use UntypedGcBox;
 
 /// Backing data of `Gc`s. The thing that is allocated and stores the user's value.
+///
+/// `repr(C)` so that the allocator can read `slot` without knowing `T`.
+#[repr(C)]
 pub(crate) struct GcBox<T: ?Sized> {
-    refs: Cell<usize>,
-    weak: Cell<usize>,
-    coroner: Coroner,
-    val: T, // TODO: Why does this fail if it is first in list when `T: ?Sized`?
+    slot: Cell<usize>, // Index of the object's info in the allocator
+    val: T,            // TODO: Why does this fail if it is first in list when `T: ?Sized`?
 }
 
 impl<T> GcBox<T> {
     pub fn new(val: T) -> GcBox<T> {
         GcBox {
-            refs: Cell::new(0),
-            weak: Cell::new(0),
-            coroner: Coroner::new(),
+            slot: Cell::new(0),
             val,
         }
     }
@@ -44,98 +52,226 @@ impl<T> GcBox<T> {
     }
 }
 impl<T: ?Sized> GcBox<T> {
-    pub fn incr_ref(&self) {
-        self.refs.set(self.refs.get() + 1);
-    }
-    pub fn decr_ref(&self) {
-        self.refs.set(self.refs.get() - 1);
-    }
-    pub fn incr_weak(&self) {
-        self.weak.set(self.weak.get() + 1);
-    }
-    pub fn decr_weak(&self) {
-        self.weak.set(self.weak.get() - 1);
-    }
-    pub fn strong_count(&self) -> usize {
-        self.refs.get()
-    }
-    pub fn weak_count(&self) -> usize {
-        self.weak.get()
+    /// Moves a boxed value, which may be unsized, into the storage at `ptr`,
+    /// freeing the box.
+    ///
+    /// Unsafe because `ptr` must point to uninitialized storage with the
+    /// layout of a `GcBox` holding the value, and carry the value's metadata.
+    pub unsafe fn write_boxed(ptr: *mut GcBox<T>, value: Box<T>) {
+        let layout = Layout::for_value(&*value);
+        let value = Box::into_raw(value);
+        ::std::ptr::addr_of_mut!((*ptr).slot).write(Cell::new(0));
+        let dest = ::std::ptr::addr_of_mut!((*ptr).val);
+        ::std::ptr::copy_nonoverlapping(value as *const u8, dest as *mut u8, layout.size());
+        // The value was moved out, so only release the memory. A box of a
+        // zero sized value never allocated any.
+        if layout.size() != 0 {
+            dealloc(value as *mut u8, layout);
+        }
     }
+
     // Should this be unsafe? Needs to not be an active mut reference when called
     pub fn borrow(&self) -> &T {
+        #[cfg(feature = "quarantine")]
+        self.check_poison();
         &self.val
     }
     /// Unsfe due to stronger requirements than `borrow`, that it should be
     /// the only active reference.
     pub unsafe fn borrow_mut(&mut self) -> &mut T {
+        #[cfg(feature = "quarantine")]
+        self.check_poison();
         &mut self.val
     }
 
-    fn tracker(&self) -> LifeTracker {
-        if !self.coroner.is_tracking() {
-            self.coroner.track();
+    /// Panics if this box's storage has been freed and poisoned
+    #[cfg(feature = "quarantine")]
+    fn check_poison(&self) {
+        // No collector tracks this many objects
+        if self.slot.get() == POISON_WORD {
+            panic!("use after free: tried to access a freed object");
         }
-        self.coroner.tracker()
     }
 }
 
-/// Reports to the LifeTracker when this is destroyed
-struct Coroner(RefCell<Option<LifeTracker>>);
-impl Drop for Coroner {
-    fn drop(&mut self) {
-        if let Some(ref tracker) = *self.0.borrow() {
-            tracker.dead();
-        }
-    }
+/// Returns a pointer to `addr` with the metadata of `template`, like the
+/// length of a slice or the vtable of a trait object.
+pub(crate) fn with_metadata_of<T: ?Sized, U: ?Sized>(
+    addr: NonNull<U>,
+    template: *const GcBox<T>,
+) -> NonNull<GcBox<T>> {
+    let addr = addr.as_ptr() as *mut u8;
+    let ptr = if size_of::<*const GcBox<T>>() == size_of::<*mut u8>() {
+        // A thin pointer has no metadata, so `addr` can be used as is
+        // Unsafe is fine since both are thin pointers
+        unsafe { transmute_copy::<*mut u8, *mut GcBox<T>>(&addr) }
+    } else {
+        template.with_addr(addr.addr()) as *mut GcBox<T>
+    };
+    // Unsafe is fine since the address came from a `NonNull`
+    unsafe { NonNull::new_unchecked(ptr) }
 }
-impl Coroner {
-    fn new() -> Coroner {
-        Coroner(RefCell::new(None))
-    }
-    fn track(&self) {
-        *self.0.borrow_mut() = Some(LifeTracker::new());
-    }
 
-    fn is_tracking(&self) -> bool {
-        self.0.borrow().is_some()
+/// A pointer to a `GcBox<T>` with `T` erased, kept for its metadata.
+///
+/// The pointer is stored whole, so nothing is assumed about how a wide
+/// pointer is laid out, only that it fits. It's never read back as anything
+/// but the pointer it was made from.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ErasedTemplate(MaybeUninit<[usize; 2]>);
+
+impl ErasedTemplate {
+    pub fn new<T: ?Sized>(template: *const GcBox<T>) -> ErasedTemplate {
+        assert!(size_of::<*const GcBox<T>>() <= size_of::<ErasedTemplate>());
+        assert!(align_of::<*const GcBox<T>>() <= align_of::<ErasedTemplate>());
+        let mut erased = ErasedTemplate(MaybeUninit::uninit());
+        // Unsafe is fine since the storage is large and aligned enough
+        unsafe {
+            erased
+                .0
+                .as_mut_ptr()
+                .cast::<*const GcBox<T>>()
+                .write(template)
+        };
+        erased
     }
 
-    fn tracker(&self) -> LifeTracker {
-        self.0.borrow().as_ref().expect("was not tracking").clone()
+    /// Unsafe because `T` must be the type the template was made with
+    pub unsafe fn typed<T: ?Sized>(&self) -> *const GcBox<T> {
+        self.0.as_ptr().cast::<*const GcBox<T>>().read()
     }
 }
 
-struct LifeTracker(Rc<Cell<bool>>);
-impl LifeTracker {
-    fn new() -> LifeTracker {
-        LifeTracker(Rc::new(Cell::new(true)))
+/// What `Gc`s and `Weak`s actually point to. Owned by the collector.
+///
+/// Handles never move, so the collector can move the `GcBox` and only has to
+/// update its handle. A handle outlives its object until no `Gc` or `Weak`
+/// points to it anymore, which is how they can tell the object was freed.
+///
+/// Tracing reports handles, so the handle also says which allocator owns the
+/// object and where its info is, like the object's header does.
+pub(crate) struct Handle {
+    ptr: Cell<Option<NonNull<UntypedGcBox>>>, // `None` once the object is freed
+    strong: Cell<usize>,
+    weak: Cell<usize>,
+    slot: Cell<usize>, // Index of the object's info in the allocator
+    owner: usize,      // Id of the allocator the object belongs to
+}
+impl Handle {
+    pub fn new(ptr: NonNull<UntypedGcBox>, owner: usize) -> Handle {
+        Handle {
+            ptr: Cell::new(Some(ptr)),
+            strong: Cell::new(0),
+            weak: Cell::new(0),
+            slot: Cell::new(0),
+            owner,
+        }
     }
-    fn is_alive(&self) -> bool {
-        self.0.get()
+    /// A handle for an object that isn't written yet, which looks dead until
+    /// it is moved to the object
+    pub fn reserved(owner: usize) -> Handle {
+        Handle {
+            ptr: Cell::new(None),
+            strong: Cell::new(0),
+            weak: Cell::new(0),
+            slot: Cell::new(0),
+            owner,
+        }
     }
-
-    fn dead(&self) {
-        self.0.set(false);
+    pub fn slot(&self) -> usize {
+        self.slot.get()
     }
-}
-impl Clone for LifeTracker {
-    fn clone(&self) -> LifeTracker {
-        LifeTracker(self.0.clone())
+    pub fn set_slot(&self, slot: usize) {
+        self.slot.set(slot);
+    }
+    pub fn owner(&self) -> usize {
+        self.owner
+    }
+    /// Current address of the object, if it is still alive
+    pub fn box_ptr(&self) -> Option<NonNull<UntypedGcBox>> {
+        self.ptr.get()
+    }
+    pub fn moved_to(&self, ptr: NonNull<UntypedGcBox>) {
+        self.ptr.set(Some(ptr));
+    }
+    /// Mark the object as freed
+    pub fn kill(&self) {
+        self.ptr.set(None);
+    }
+    pub fn is_alive(&self) -> bool {
+        self.ptr.get().is_some()
+    }
+    /// Whether any `Gc` or `Weak` still points to this handle
+    pub fn is_referenced(&self) -> bool {
+        self.strong.get() > 0 || self.weak.get() > 0
+    }
+    pub fn incr_ref(&self) {
+        self.strong.set(self.strong.get() + 1);
+    }
+    pub fn decr_ref(&self) {
+        self.strong.set(self.strong.get() - 1);
+    }
+    pub fn incr_weak(&self) {
+        self.weak.set(self.weak.get() + 1);
+    }
+    pub fn decr_weak(&self) {
+        self.weak.set(self.weak.get() - 1);
+    }
+    pub fn strong_count(&self) -> usize {
+        self.strong.get()
+    }
+    pub fn weak_count(&self) -> usize {
+        self.weak.get()
+    }
+    #[cfg(all(test, feature = "debug-checks"))]
+    pub fn set_weak_count(&self, weak: usize) {
+        self.weak.set(weak);
     }
 }
 
-/// Lifetime-restricted pointer to a `GcBox`
+/// Lifetime-restricted pointer to an object's `Handle`.
+///
+/// Typed as a `GcBox<T>` so that it can carry the pointer metadata of `T`, but
+/// the address is always that of the handle.
 pub(crate) struct GcRef<'arena, T: 'arena + ?Sized> {
     _marker: PhantomData<&'arena T>,
     ptr: NonNull<GcBox<T>>,
 }
+impl<'a, T: 'a> GcRef<'a, T> {
+    pub(crate) fn from_raw_handle(
+        handle: NonNull<Handle>,
+        _marker: PhantomData<&'a T>,
+    ) -> GcRef<'a, T> {
+        GcRef {
+            _marker,
+            ptr: handle.cast(),
+        }
+    }
+}
 impl<'a, T: 'a + ?Sized> GcRef<'a, T> {
-    pub(crate) fn from_raw_nonnull(
-        ptr: NonNull<GcBox<T>>,
+    /// Like `from_raw_handle`, but takes the metadata of `T` from `template`.
+    ///
+    /// Unsafe because the metadata of `template` must be valid for the object.
+    pub(crate) unsafe fn from_raw_handle_like(
+        handle: NonNull<Handle>,
+        template: *const GcBox<T>,
         _marker: PhantomData<&'a T>,
     ) -> GcRef<'a, T> {
-        GcRef { _marker, ptr }
+        GcRef {
+            _marker,
+            ptr: with_metadata_of(handle, template),
+        }
+    }
+
+    fn handle(&self) -> &Handle {
+        // Unsafe is fine since a handle isn't freed while anything points to it
+        unsafe { self.ptr.cast::<Handle>().as_ref() }
+    }
+
+    /// Returns the current address of the `GcBox`, if it is still alive
+    fn box_ptr(&self) -> Option<NonNull<GcBox<T>>> {
+        let addr = self.handle().box_ptr()?;
+        Some(with_metadata_of(addr, self.ptr.as_ptr()))
     }
 
     /// Returns a reference to the inner `GcBox`
@@ -143,20 +279,24 @@ impl<'a, T: 'a + ?Sized> GcRef<'a, T> {
     /// #Safety
     ///
     /// The box must still be alive.
-    unsafe fn gc_box<'t>(&'t self) -> &'t GcBox<T> {
+    unsafe fn gc_box(&self) -> &GcBox<T> {
         // This is fine because as long as there is a Gc the pointer to the data
         // should be valid
-        self.ptr.as_ref()
+        self.box_ptr()
+            .expect("gc pointer was already dead")
+            .as_ref()
     }
     /// Returns a mutable reference to the inner `GcBox`
     ///
     /// #Safety
     ///
     /// The box must still be alive.
-    unsafe fn gc_box_mut<'t>(&'t mut self) -> &'t mut GcBox<T> {
+    unsafe fn gc_box_mut(&mut self) -> &mut GcBox<T> {
         // This is fine because as long as there is a Gc the pointer to the data
         // should be valid
-        self.ptr.as_mut()
+        self.box_ptr()
+            .expect("gc pointer was already dead")
+            .as_mut()
     }
 }
 
@@ -169,9 +309,6 @@ impl<'a, T: 'a + ?Sized> Clone for GcRef<'a, T> {
     }
 }
 
-// impl<'a, T: 'a + ?Sized + ::std::marker::Unsize<U>, U: ?Sized>
-// ::std::ops::CoerceUnsized<GcRef<'a, U>> for GcRef<'a, T> {}
-
 /// A single-threaded garbage collected pointer.
 /// 'Gc' stands for 'Garbage Collected'.
 ///
@@ -227,7 +364,6 @@ impl<'a, T: 'a + ?Sized> Clone for GcRef<'a, T> {
 // TODO Mention reference counts?
 pub struct Gc<'arena, T: 'arena + ?Sized> {
     ptr: GcRef<'arena, T>,
-    life_tracker: LifeTracker,
 }
 impl<'a, T: 'a> Gc<'a, T> {
     /// Returns the contained value, if the `Gc` is alive and has exactly one
@@ -247,7 +383,7 @@ impl<'a, T: 'a> Gc<'a, T> {
     /// let mut col = Collector::new();
     /// let mut proxy = col.proxy();
     ///
-    /// let zambia_2016_gdp = proxy.alloc(19_550_000_000); // USD
+    /// let zambia_2016_gdp = proxy.alloc(19_550_000_000u64); // USD
     ///
     /// let gdp_clone = zambia_2016_gdp.clone();
     ///
@@ -269,38 +405,39 @@ impl<'a, T: 'a> Gc<'a, T> {
 }
 impl<'a, T: 'a + ?Sized> Gc<'a, T> {
     pub(crate) fn from_raw_gcref(gc_ref: GcRef<'a, T>) -> Gc<'a, T> {
-        let gc = Gc {
-            // Unsafe is ok since we are only passed living objects
-            life_tracker: unsafe { gc_ref.gc_box().tracker() },
-            ptr: gc_ref,
-        };
-        gc.incr_ref();
+        let gc = Gc { ptr: gc_ref };
         assert!(Gc::is_alive(&gc));
+        gc.handle().incr_ref();
         gc
     }
 
-    pub(crate) fn from_raw_nonnull(
-        ptr: NonNull<GcBox<T>>,
-        _marker: PhantomData<&'a T>,
-    ) -> Gc<'a, T> {
-        Gc::from_raw_gcref(GcRef::from_raw_nonnull(ptr, _marker))
+    pub(crate) fn from_raw_handle(handle: NonNull<Handle>, _marker: PhantomData<&'a T>) -> Gc<'a, T>
+    where
+        T: Sized,
+    {
+        Gc::from_raw_gcref(GcRef::from_raw_handle(handle, _marker))
     }
 
-    fn incr_ref(&self) {
-        assert!(Gc::is_alive(self));
-        Gc::gc_box(self).incr_ref();
+    /// Unsafe because the metadata of `template` must be valid for the object.
+    pub(crate) unsafe fn from_raw_handle_like(
+        handle: NonNull<Handle>,
+        template: *const GcBox<T>,
+        _marker: PhantomData<&'a T>,
+    ) -> Gc<'a, T> {
+        Gc::from_raw_gcref(GcRef::from_raw_handle_like(handle, template, _marker))
     }
 
-    fn decr_ref(&self) {
-        assert!(Gc::is_alive(self));
-        Gc::gc_box(self).decr_ref();
+    pub(crate) fn handle(&self) -> &Handle {
+        self.ptr.handle()
     }
 
     /// Whether or not the object pointed to by this `Gc` is still valid and has
     /// not been freed.
     ///
     /// Allows you to check in destructors that the data a `Gc` point to has
-    /// not already been reclaimed.
+    /// not already been reclaimed. Keeps working after the object is freed,
+    /// since a `Gc` points to a handle owned by the collector rather than to
+    /// the object itself.
     ///
     /// # Examples
     ///
@@ -315,7 +452,7 @@ impl<'a, T: 'a + ?Sized> Gc<'a, T> {
     /// assert!(Gc::is_alive(&meaning_of_life));
     /// ```
     pub fn is_alive(this: &Self) -> bool {
-        this.life_tracker.is_alive()
+        this.handle().is_alive()
     }
 
     /// Safely obtain a reference to the inner value.
@@ -413,14 +550,11 @@ impl<'a, T: 'a + ?Sized> Gc<'a, T> {
         // this isn't called when dead).
         unsafe { self.ptr.gc_box() }
     }
-    fn gc_box_checked(&self) -> &GcBox<T> {
+    fn handle_checked(&self) -> &Handle {
         if !Gc::is_alive(self) {
             panic!("gc pointer was dead");
         }
-        // This is fine because as long as there is a Gc the pointer to the data
-        // should be valid (unless we are in the `sweep` phase, in which case
-        // this isn't called when dead).
-        unsafe { self.ptr.gc_box() }
+        self.handle()
     }
 
     /// Returns a mutable reference to the inner `GcBox`
@@ -453,12 +587,10 @@ impl<'a, T: 'a + ?Sized> Gc<'a, T> {
     ///
     /// [`Weak`]: struct.Weak.html
     pub fn downgrade(this: &Gc<'a, T>) -> Weak<'a, T> {
-        let weak = Weak {
-            life_tracker: this.life_tracker.clone(),
+        this.handle().incr_weak();
+        Weak {
             ptr: this.ptr.clone(),
-        };
-        weak.incr_weak();
-        weak
+        }
     }
 
     /// Get the number of strong (`Gc`) pointers to this value.
@@ -484,7 +616,7 @@ impl<'a, T: 'a + ?Sized> Gc<'a, T> {
     ///
     /// [`is_alive`]: #method.is_alive
     pub fn strong_count(this: &Gc<'a, T>) -> usize {
-        Gc::gc_box_checked(this).strong_count()
+        Gc::handle_checked(this).strong_count()
     }
 
     /// Gets the number of [`Weak`] pointers to this value.
@@ -511,7 +643,7 @@ impl<'a, T: 'a + ?Sized> Gc<'a, T> {
     /// [`is_alive`]: #method.is_alive
     /// [`Weak`]: struct.Weak.html
     pub fn weak_count(this: &Gc<'a, T>) -> usize {
-        Gc::gc_box_checked(this).weak_count()
+        Gc::handle_checked(this).weak_count()
     }
 
     /// Returns `true` if the two `Gc`s point to the same value
@@ -533,7 +665,55 @@ impl<'a, T: 'a + ?Sized> Gc<'a, T> {
     /// assert!(!Gc::ptr_eq(&nes_sales, &famicom_sales));
     /// ```
     pub fn ptr_eq(this: &Self, other: &Self) -> bool {
-        this.ptr.ptr == other.ptr.ptr
+        ::std::ptr::addr_eq(this.ptr.ptr.as_ptr(), other.ptr.ptr.as_ptr())
+    }
+
+    /// Turns the `Gc` into one that points to the same value as an unsized
+    /// type, like a trait object or a slice, by letting `coerce` change the
+    /// type of a pointer to the value.
+    ///
+    /// The [`unsize!`] macro does this safely for unsizing coercions.
+    ///
+    /// # Safety
+    ///
+    /// `coerce` must return a pointer to the same value, only changing its
+    /// type in a way an unsizing coercion could.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `coerce` changes the address of the pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::fmt::Display;
+    /// use ters_gc::{Collector, Gc};
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let num = proxy.alloc(5);
+    /// let shown: Gc<dyn Display> = unsafe { Gc::unsize(num, |ptr| ptr as *const dyn Display) };
+    /// assert_eq!(shown.to_string(), "5");
+    /// ```
+    ///
+    /// [`unsize!`]: ../macro.unsize.html
+    pub unsafe fn unsize<U: 'a + ?Sized>(
+        this: Self,
+        coerce: impl FnOnce(*const T) -> *const U,
+    ) -> Gc<'a, U> {
+        let handle: NonNull<Handle> = this.ptr.ptr.cast();
+        let ptr = this.ptr.ptr.as_ptr() as *const T;
+        let unsized_ptr = coerce(ptr);
+        assert!(
+            ::std::ptr::addr_eq(ptr, unsized_ptr),
+            "unsizing changed the address of a gc pointer"
+        );
+        // The new `Gc` takes over the strong reference of the old one
+        ::std::mem::forget(this);
+        Gc {
+            ptr: GcRef::from_raw_handle_like(handle, unsized_ptr as *const GcBox<U>, PhantomData),
+        }
     }
 
     /// Returns a reference to inner value
@@ -546,15 +726,16 @@ impl<'a, T: 'a + ?Sized> Gc<'a, T> {
     }
 
     pub(crate) fn get_box_ptr(&self) -> Option<NonNull<GcBox<T>>> {
-        if Gc::is_alive(self) {
-            Some(self.ptr.ptr)
-        } else {
-            None
-        }
+        self.ptr.box_ptr()
     }
 
+    pub(crate) fn handle_ptr(&self) -> NonNull<Handle> {
+        self.ptr.ptr.cast()
+    }
+
+    /// Panics if the object is dead, since its handle may have been reused
     pub(crate) fn nonnull_box_ptr(&self) -> NonNull<GcBox<T>> {
-        self.ptr.ptr
+        self.get_box_ptr().expect("gc pointer was already dead")
     }
 }
 impl<'a, T: 'a + Clone + Trace> Gc<'a, T> {
@@ -599,6 +780,7 @@ impl<'a, T: 'a + Clone + Trace> Gc<'a, T> {
     /// [`get_mut`]: #method.get_mut
     /// [`Weak`]: struct.Weak.html
     /// [`clone`]: https://doc.rust-lang.org/std/clone/trait.Clone.html#tymethod.clone
+    #[track_caller]
     pub fn make_mut<'g>(this: &'g mut Self, proxy: &mut Proxy<'a>) -> &'g mut T {
         if !Gc::is_alive(this) {
             panic!("gc pointer was already dead");
@@ -658,12 +840,11 @@ impl<'a, T: 'a + ?Sized> Drop for Gc<'a, T> {
     /// proxy.run(); // Prints "dropped!"
     /// ```
     fn drop(&mut self) {
-        if Gc::is_alive(self) {
-            self.decr_ref();
-        }
+        // The handle outlives the object, so this is counted even when dead
+        self.handle().decr_ref();
     }
 }
-impl<'a, T: 'a> Deref for Gc<'a, T> {
+impl<'a, T: 'a + ?Sized> Deref for Gc<'a, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -671,7 +852,7 @@ impl<'a, T: 'a> Deref for Gc<'a, T> {
     }
 }
 
-impl<'a, T: 'a> Clone for Gc<'a, T> {
+impl<'a, T: 'a + ?Sized> Clone for Gc<'a, T> {
     /// Makes a clone of the `Gc` pointer.
     ///
     /// This creates another pointer to the same inner value, increasing the
@@ -701,10 +882,9 @@ impl<'a, T: 'a> Clone for Gc<'a, T> {
         if !Gc::is_alive(self) {
             panic!("gc pointer was already dead");
         }
-        self.incr_ref();
+        self.handle().incr_ref();
         Gc {
             ptr: self.ptr.clone(),
-            life_tracker: self.life_tracker.clone(),
         }
     }
 }
@@ -718,10 +898,10 @@ mod gc_impls {
     use std::fmt;
     use std::hash::{Hash, Hasher};
 
-    impl<'a, T: 'a + fmt::Debug> fmt::Debug for Gc<'a, T> {
+    impl<'a, T: 'a + fmt::Debug + ?Sized> fmt::Debug for Gc<'a, T> {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             match Gc::get(self) {
-                Some(value) => f.debug_struct("Gc").field("value", value).finish(),
+                Some(value) => f.debug_struct("Gc").field("value", &value).finish(),
                 None => {
                     struct DeadPlaceholder;
 
@@ -739,39 +919,39 @@ mod gc_impls {
         }
     }
 
-    impl<'a, T: 'a> AsRef<T> for Gc<'a, T> {
+    impl<'a, T: 'a + ?Sized> AsRef<T> for Gc<'a, T> {
         fn as_ref(&self) -> &T {
-            &**self
+            self
         }
     }
-    impl<'a, T: 'a + fmt::Display> fmt::Display for Gc<'a, T> {
+    impl<'a, T: 'a + fmt::Display + ?Sized> fmt::Display for Gc<'a, T> {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             fmt::Display::fmt(&**self, f)
         }
     }
-    impl<'a, T: 'a> fmt::Pointer for Gc<'a, T> {
+    impl<'a, T: 'a + ?Sized> fmt::Pointer for Gc<'a, T> {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             fmt::Pointer::fmt(&(&**self), f)
         }
     }
-    impl<'a, T: 'a + Hash> Hash for Gc<'a, T> {
+    impl<'a, T: 'a + Hash + ?Sized> Hash for Gc<'a, T> {
         fn hash<H: Hasher>(&self, state: &mut H) {
             (**self).hash(state)
         }
     }
-    impl<'a, T: 'a> borrow::Borrow<T> for Gc<'a, T> {
+    impl<'a, T: 'a + ?Sized> borrow::Borrow<T> for Gc<'a, T> {
         fn borrow(&self) -> &T {
-            &**self
+            self
         }
     }
-    impl<'a, T: 'a + PartialEq> PartialEq for Gc<'a, T> {
+    impl<'a, T: 'a + PartialEq + ?Sized> PartialEq for Gc<'a, T> {
         #[inline(always)]
         fn eq(&self, other: &Gc<'a, T>) -> bool {
             **self == **other
         }
     }
-    impl<'a, T: 'a + Eq> Eq for Gc<'a, T> {}
-    impl<'a, T: 'a + PartialOrd> PartialOrd for Gc<'a, T> {
+    impl<'a, T: 'a + Eq + ?Sized> Eq for Gc<'a, T> {}
+    impl<'a, T: 'a + PartialOrd + ?Sized> PartialOrd for Gc<'a, T> {
         #[inline(always)]
         fn partial_cmp(&self, other: &Gc<'a, T>) -> Option<Ordering> {
             (*self.get_borrow()).partial_cmp(other.get_borrow())
@@ -793,13 +973,12 @@ mod gc_impls {
             *self.get_borrow() >= *other.get_borrow()
         }
     }
-    impl<'a, T: 'a + Ord> Ord for Gc<'a, T> {
+    impl<'a, T: 'a + Ord + ?Sized> Ord for Gc<'a, T> {
         #[inline]
         fn cmp(&self, other: &Gc<'a, T>) -> Ordering {
             (*self.get_borrow()).cmp(other.get_borrow())
         }
     }
-
 }
 
 /// `Weak` is a version of [`Gc`] that holds a non-owning reference to the managed
@@ -825,10 +1004,19 @@ mod gc_impls {
 /// [`Option`]: https://doc.rust-lang.org/std/option/enum.Option.html
 /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
 pub struct Weak<'arena, T: 'arena + ?Sized> {
-    life_tracker: LifeTracker,
     ptr: GcRef<'arena, T>,
 }
 
+impl<'a, T: 'a> Weak<'a, T> {
+    pub(crate) fn from_raw_handle(
+        handle: NonNull<Handle>,
+        _marker: PhantomData<&'a T>,
+    ) -> Weak<'a, T> {
+        let ptr = GcRef::from_raw_handle(handle, _marker);
+        ptr.handle().incr_weak();
+        Weak { ptr }
+    }
+}
 impl<'a, T: 'a + ?Sized> Weak<'a, T> {
     /// Returns whether the inner object has been reclaimed and freed.
     ///
@@ -854,7 +1042,7 @@ impl<'a, T: 'a + ?Sized> Weak<'a, T> {
     /// assert!(!weak_hd.is_alive());
     /// ```
     pub fn is_alive(&self) -> bool {
-        self.life_tracker.is_alive()
+        self.ptr.handle().is_alive()
     }
 
     /// Attempts to upgrade the `Weak` pointer to a [`Gc`], preventing the inner
@@ -908,27 +1096,8 @@ impl<'a, T: 'a + ?Sized> Weak<'a, T> {
     fn get_borrow(&self) -> &T {
         self.get().expect("weak pointer was already dead")
     }
-
-    fn get_gc_box(&self) -> Option<&GcBox<T>> {
-        if self.is_alive() {
-            // Unsfe is ok since we checked that we won't be accessing freed memory
-            Some(unsafe { self.ptr.gc_box() })
-        } else {
-            None
-        }
-    }
-    fn incr_weak(&self) {
-        if let Some(gc_box) = self.get_gc_box() {
-            gc_box.incr_weak();
-        }
-    }
-    fn decr_weak(&self) {
-        if let Some(gc_box) = self.get_gc_box() {
-            gc_box.decr_weak();
-        }
-    }
 }
-impl<'a, T: 'a> Clone for Weak<'a, T> {
+impl<'a, T: 'a + ?Sized> Clone for Weak<'a, T> {
     /// Makes a clone of the `Weak` pointer that points to the same value.
     ///
     /// # Examples
@@ -944,9 +1113,8 @@ impl<'a, T: 'a> Clone for Weak<'a, T> {
     /// weak_five.clone();
     /// ```
     fn clone(&self) -> Self {
-        self.incr_weak();
+        self.ptr.handle().incr_weak();
         Weak {
-            life_tracker: self.life_tracker.clone(),
             ptr: self.ptr.clone(),
         }
     }
@@ -954,7 +1122,7 @@ impl<'a, T: 'a> Clone for Weak<'a, T> {
 impl<'a, T: 'a + ?Sized> Drop for Weak<'a, T> {
     /// Drops the `Weak` pointer.
     fn drop(&mut self) {
-        self.decr_weak();
+        self.ptr.handle().decr_weak();
     }
 }
 
@@ -965,10 +1133,10 @@ mod weak_impls {
     use std::cmp::Ordering;
     use std::fmt;
 
-    impl<'a, T: 'a + fmt::Debug> fmt::Debug for Weak<'a, T> {
+    impl<'a, T: 'a + fmt::Debug + ?Sized> fmt::Debug for Weak<'a, T> {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             match self.get() {
-                Some(value) => f.debug_struct("Weak").field("value", value).finish(),
+                Some(value) => f.debug_struct("Weak").field("value", &value).finish(),
                 None => {
                     struct DeadPlaceholder;
 
@@ -985,14 +1153,14 @@ mod weak_impls {
             }
         }
     }
-    impl<'a, T: 'a + PartialEq> PartialEq for Weak<'a, T> {
+    impl<'a, T: 'a + PartialEq + ?Sized> PartialEq for Weak<'a, T> {
         #[inline(always)]
         fn eq(&self, other: &Weak<'a, T>) -> bool {
             *self.get_borrow() == *other.get_borrow()
         }
     }
-    impl<'a, T: 'a + Eq> Eq for Weak<'a, T> {}
-    impl<'a, T: 'a + PartialOrd> PartialOrd for Weak<'a, T> {
+    impl<'a, T: 'a + Eq + ?Sized> Eq for Weak<'a, T> {}
+    impl<'a, T: 'a + PartialOrd + ?Sized> PartialOrd for Weak<'a, T> {
         #[inline(always)]
         fn partial_cmp(&self, other: &Weak<'a, T>) -> Option<Ordering> {
             (*self.get_borrow()).partial_cmp(other.get_borrow())
@@ -1014,7 +1182,7 @@ mod weak_impls {
             *self.get_borrow() >= *other.get_borrow()
         }
     }
-    impl<'a, T: 'a + Ord> Ord for Weak<'a, T> {
+    impl<'a, T: 'a + Ord + ?Sized> Ord for Weak<'a, T> {
         #[inline]
         fn cmp(&self, other: &Weak<'a, T>) -> Ordering {
             (*self.get_borrow()).cmp(other.get_borrow())
@@ -1022,10 +1190,179 @@ mod weak_impls {
     }
 }
 
+/// A [`Gc`] to a value of any type, which can be turned back into a [`Gc`] of
+/// the original type.
+///
+/// Only remembers the type of the value, so storing one doesn't need a
+/// vtable. The collector frees and traces the value the same as it would
+/// through a [`Gc`].
+///
+/// # Examples
+///
+/// ```
+/// use ters_gc::{Collector, Gc, GcAny};
+///
+/// let mut col = Collector::new();
+/// let mut proxy = col.proxy();
+///
+/// let values = vec![GcAny::from(proxy.alloc(5)), GcAny::from(proxy.alloc("five"))];
+///
+/// let num: Gc<i32> = values[0].clone().downcast().unwrap();
+/// assert_eq!(*num, 5);
+/// assert!(values[1].is::<&str>());
+/// ```
+///
+/// [`Gc`]: struct.Gc.html
+pub struct GcAny<'arena> {
+    ptr: GcRef<'arena, UntypedGcBox>,
+    type_id: TypeId,
+}
+
+impl<'a> GcAny<'a> {
+    /// Returns `true` if the value is of type `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::{Collector, GcAny};
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let any = GcAny::from(proxy.alloc(5u8));
+    /// assert!(any.is::<u8>());
+    /// assert!(!any.is::<u16>());
+    /// ```
+    pub fn is<T: Any>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
+    /// Turns the `GcAny` back into a [`Gc`] to the value, if the value is of
+    /// type `T`.
+    ///
+    /// Otherwise, an [`Err`] is returned with the same `GcAny` that was passed
+    /// in.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::{Collector, Gc, GcAny};
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let any = GcAny::from(proxy.alloc(5u8));
+    /// let any = any.downcast::<u16>().unwrap_err();
+    /// let num: Gc<u8> = any.downcast().unwrap();
+    /// assert_eq!(*num, 5);
+    /// ```
+    ///
+    /// [`Gc`]: struct.Gc.html
+    /// [`Err`]: https://doc.rust-lang.org/std/result/enum.Result.html
+    pub fn downcast<T: Any>(self) -> Result<Gc<'a, T>, GcAny<'a>> {
+        if !self.is::<T>() {
+            return Err(self);
+        }
+        let handle: NonNull<Handle> = self.ptr.ptr.cast();
+        // The `Gc` takes over the strong reference
+        ::std::mem::forget(self);
+        Ok(Gc {
+            ptr: GcRef::from_raw_handle(handle, PhantomData),
+        })
+    }
+
+    /// Whether or not the value is still valid and has not been freed.
+    ///
+    /// See [`Gc::is_alive`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::{Collector, GcAny};
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let any = GcAny::from(proxy.alloc(5));
+    /// assert!(any.is_alive());
+    /// ```
+    ///
+    /// [`Gc::is_alive`]: struct.Gc.html#method.is_alive
+    pub fn is_alive(&self) -> bool {
+        self.ptr.handle().is_alive()
+    }
+
+    pub(crate) fn get_box_ptr(&self) -> Option<NonNull<GcBox<UntypedGcBox>>> {
+        self.ptr.box_ptr()
+    }
+
+    pub(crate) fn handle_ptr(&self) -> NonNull<Handle> {
+        self.ptr.ptr.cast()
+    }
+}
+
+impl<'a, T: Any + Trace> From<Gc<'a, T>> for GcAny<'a> {
+    /// Erases the type of the value, keeping the strong reference.
+    fn from(gc: Gc<'a, T>) -> GcAny<'a> {
+        let handle: NonNull<Handle> = gc.ptr.ptr.cast();
+        // The `GcAny` takes over the strong reference
+        ::std::mem::forget(gc);
+        GcAny {
+            ptr: GcRef::from_raw_handle(handle, PhantomData),
+            type_id: TypeId::of::<T>(),
+        }
+    }
+}
+
+impl<'a> Clone for GcAny<'a> {
+    /// Makes a clone of the `GcAny` that points to the same value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is dead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::{Collector, GcAny};
+    ///
+    /// let mut col = Collector::new();
+    /// let mut proxy = col.proxy();
+    ///
+    /// let any = GcAny::from(proxy.alloc(5));
+    /// let _other = any.clone();
+    /// ```
+    fn clone(&self) -> Self {
+        if !self.is_alive() {
+            panic!("gc pointer was already dead");
+        }
+        self.ptr.handle().incr_ref();
+        GcAny {
+            ptr: self.ptr.clone(),
+            type_id: self.type_id,
+        }
+    }
+}
+
+impl<'a> Drop for GcAny<'a> {
+    fn drop(&mut self) {
+        // The handle outlives the object, so this is counted even when dead
+        self.ptr.handle().decr_ref();
+    }
+}
+
+impl<'a> fmt::Debug for GcAny<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("GcAny { .. }")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use Collector;
+    use std::rc::Rc;
+    use trace::Tracer;
+    use {Collector, GcCell};
 
     #[test]
     fn strong_count_works() {
@@ -1033,7 +1370,7 @@ mod tests {
         let mut col = Collector::new();
         let mut proxy = col.proxy();
         fn get_ref_num<'a, T>(gc: &Gc<'a, T>) -> usize {
-            Gc::gc_box(gc).refs.clone().take()
+            gc.handle().strong_count()
         }
         let num = proxy.alloc(42);
         assert_eq!(get_ref_num(&num), 1);
@@ -1043,6 +1380,14 @@ mod tests {
         assert_eq!(get_ref_num(&num2), 1);
     }
 
+    #[test]
+    fn gc_is_a_single_pointer() {
+        use std::mem::size_of;
+        assert_eq!(size_of::<Gc<i32>>(), size_of::<usize>());
+        assert_eq!(size_of::<Option<Gc<i32>>>(), size_of::<usize>());
+        assert_eq!(size_of::<Weak<i32>>(), size_of::<usize>());
+    }
+
     #[test]
     fn casting_weak() {
         use trace::{Trace, Tracer};
@@ -1086,15 +1431,16 @@ mod tests {
     fn gc_knows_when_dangling() {
         let mut col = Collector::new();
         let mut proxy = col.proxy();
-        let num_safe = {
-            let num = proxy.alloc(0);
-            Gc::gc_box(&num).decr_ref();
-            num
-        };
+        let num_safe = proxy.alloc(0);
+        // Keeps the handle around while its strong count is off
+        let _num_weak = Gc::downgrade(&num_safe);
+        num_safe.handle().decr_ref();
 
         proxy.run();
         assert_eq!(proxy.num_tracked(), 0);
         assert!(Gc::get(&num_safe).is_none());
+        assert!(!Gc::is_alive(&num_safe));
+        num_safe.handle().incr_ref();
     }
 
     #[test]
@@ -1103,10 +1449,12 @@ mod tests {
         let mut col = Collector::new();
         let mut proxy = col.proxy();
         let num = proxy.alloc(0);
-        Gc::gc_box(&num).decr_ref();
+        let _num_weak = Gc::downgrade(&num);
+        num.handle().decr_ref();
 
         proxy.run();
         assert_eq!(proxy.num_tracked(), 0);
+        num.handle().incr_ref();
         let _ = *num;
     }
 
@@ -1398,31 +1746,145 @@ mod tests {
         }
     }
 
-    //     #[test]
-    //     fn store_unsized_types() {
-    //         // TODO work on this and ?Sized support
-    //         use std::rc::Rc;
-    //         let val = 42;
-    //
-    //         let _r: Rc<ToString> = Rc::new(val);
-    //
-    //         let gc_box: GcBox<i32> = GcBox::new(val);
-    //         let boxed_box: Box<GcBox<ToString>> = Box::new(gc_box);
-    //         let nonnull_box = unsafe { NonNull::new_unchecked(Box::leak(boxed_box)) };
-    //
-    //         // let gc_ref: GcRef<Trace> = GcRef::from_raw_nonnull(nonnull_box, PhantomData);
-    //         let _gc: Gc<ToString> = Gc::from_raw_nonnull(nonnull_box, PhantomData);
-    //
-    // // pub(crate) struct GcRef<'arena, T: 'arena + ?Sized> {
-    // //     _marker: PhantomData<&'arena T>,
-    // //     ptr: NonNull<GcBox<T>>,
-    // // }
-    //         let mut col = Collector::new();
-    //         let mut _proxy = col.proxy();
-    //         // let stored_gc: Gc<ToString>  = proxy.alloc(val);
-    //         // let r: GcRef<Trace> = GcRef {
-    //         //     _marker: PhantomData,
-    //         //     ptr: unimplemented!()
-    //         // };
-    //     }
+    #[test]
+    fn unsized_cycles_are_collected() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        type Children<'a> = Gc<'a, [Gc<'a, dyn Trace + 'a>]>;
+        struct Node<'a> {
+            children: GcCell<Option<Children<'a>>>,
+        }
+        impl<'a> Trace for Node<'a> {
+            fn trace(&self, tracer: &mut Tracer) {
+                tracer.add_target(&self.children);
+            }
+        }
+        {
+            let node = proxy.alloc(Node {
+                children: GcCell::new(None),
+            });
+            let name = unsize!(proxy.alloc(String::from("node")), dyn Trace);
+            let children = proxy.alloc_slice(&[unsize!(node.clone(), dyn Trace), name]);
+            *node.children.borrow_mut() = Some(children);
+            proxy.run();
+            assert_eq!(proxy.num_tracked(), 3);
+        }
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 0);
+    }
+
+    #[test]
+    fn unsized_values_are_dropped() {
+        use std::cell::Cell;
+
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        struct Counted(Rc<Cell<usize>>);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+        impl Trace for Counted {
+            fn trace(&self, _: &mut Tracer) {}
+        }
+
+        let drops = Rc::new(Cell::new(0));
+        {
+            let boxed: Box<dyn Trace> = Box::new(Counted(drops.clone()));
+            let _dyn = proxy.alloc_boxed(boxed);
+            let counted = vec![Counted(drops.clone()), Counted(drops.clone())];
+            let _slice = proxy.alloc_boxed(counted.into_boxed_slice());
+            let _empty = proxy.alloc_slice::<u64>(&[]);
+            proxy.run();
+            assert_eq!(proxy.num_tracked(), 3);
+            assert_eq!(drops.get(), 0);
+        }
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 0);
+        assert_eq!(drops.get(), 3);
+    }
+
+    #[test]
+    fn gc_any_downcasts() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        let num = proxy.alloc(5u32);
+        let any = GcAny::from(num.clone());
+        assert_eq!(Gc::strong_count(&num), 2);
+
+        let any = any.downcast::<i32>().unwrap_err();
+        assert!(any.is::<u32>());
+        let other = any.clone();
+        assert_eq!(Gc::strong_count(&num), 3);
+        drop(other);
+
+        let back = any.downcast::<u32>().unwrap();
+        assert!(Gc::ptr_eq(&num, &back));
+        assert_eq!(Gc::strong_count(&num), 2);
+    }
+
+    #[test]
+    fn gc_any_keeps_values_alive() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        let table = proxy.alloc(GcCell::new(vec![]));
+        let name = proxy.alloc(String::from("name"));
+        table.borrow_mut().push(GcAny::from(name));
+        let num = proxy.alloc(1.5f64);
+        table.borrow_mut().push(GcAny::from(num));
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 3);
+
+        let num = table.borrow_mut().pop().unwrap();
+        drop(table);
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 1);
+        assert!(num.is_alive());
+
+        drop(num);
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 0);
+    }
+
+    #[test]
+    fn store_unsized_types() {
+        use std::fmt::Display;
+
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        let nums = proxy.alloc([1, 2, 3]);
+        let sized = nums.clone();
+        let slice = unsize!(nums, [i32]);
+        assert_eq!(Gc::strong_count(&slice), 2);
+        assert_eq!(&*slice, &[1, 2, 3]);
+
+        let cloned = slice.clone();
+        let weak = Gc::downgrade(&cloned);
+        assert_eq!(Gc::strong_count(&slice), 3);
+        assert_eq!(&*weak.upgrade().unwrap(), &*sized);
+        assert!(Gc::ptr_eq(&slice, &cloned));
+
+        let shown = unsize!(proxy.alloc(42), dyn Display);
+        assert_eq!(shown.to_string(), "42");
+        assert_eq!(
+            format!("{:?}", proxy.alloc_str("hi")),
+            "Gc { value: \"hi\" }"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "unsizing changed the address")]
+    fn unsize_must_keep_address() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+
+        let nums = proxy.alloc([1u8, 2]);
+        let _moved: Gc<u8> = unsafe { Gc::unsize(nums, |ptr| (ptr as *const u8).wrapping_add(1)) };
+    }
 }
@@ -0,0 +1,404 @@
+//! Size-class allocator for the storage of `GcBox`es.
+//!
+//! Small objects are carved out of fixed size pages. Each size class keeps a
+//! free list of slots, so freeing and reallocating an object of the same size
+//! never goes through the system allocator. Objects that are too large or too
+//! aligned for any size class are given to the backing allocator directly.
+//!
+//! Pages and large objects come from a backing `GlobalAlloc`, which is the
+//! program's global allocator unless the collector was made with
+//! `Collector::with_allocator`.
+
+use stats::{SizeClassStats, SlabStats};
+use std::alloc::{alloc, dealloc, handle_alloc_error, GlobalAlloc, Layout};
+use std::fmt;
+use std::ptr::NonNull;
+
+/// Size of the pages that slots are carved out of
+const PAGE_SIZE: usize = 4096;
+
+/// Alignment of every page. Objects that need more go to the system allocator.
+const PAGE_ALIGN: usize = 16;
+
+/// Slot size of each size class. Each is a multiple of `PAGE_ALIGN`, so every
+/// slot is aligned to it.
+const SLOT_SIZES: [usize; 16] = [
+    16, 32, 48, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512,
+];
+
+/// Forwards to the program's global allocator
+struct GlobalHeap;
+
+unsafe impl GlobalAlloc for GlobalHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        dealloc(ptr, layout)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct SizeClass {
+    slot_size: usize,
+    pages: Vec<NonNull<u8>>,
+    free: Vec<NonNull<u8>>, // Unused slots
+    used: usize,            // # of slots handed out
+    requested: usize,       // Bytes asked for by the objects in used slots
+}
+
+impl SizeClass {
+    fn new(slot_size: usize) -> SizeClass {
+        SizeClass {
+            slot_size,
+            pages: vec![],
+            free: vec![],
+            used: 0,
+            requested: 0,
+        }
+    }
+
+    fn alloc(&mut self, size: usize, backing: &dyn GlobalAlloc) -> NonNull<u8> {
+        if self.free.is_empty() {
+            self.add_page(backing);
+        }
+        let slot = self.free.pop().expect("a page was just added");
+        self.used += 1;
+        self.requested += size;
+        slot
+    }
+
+    fn dealloc(&mut self, slot: NonNull<u8>, size: usize) {
+        self.free.push(slot);
+        self.used -= 1;
+        self.requested -= size;
+    }
+
+    fn add_page(&mut self, backing: &dyn GlobalAlloc) {
+        let page = page_layout();
+        // Unsafe is fine since the page layout doesn't have a size of zero
+        let base = match NonNull::new(unsafe { backing.alloc(page) }) {
+            Some(base) => base,
+            None => handle_alloc_error(page),
+        };
+        self.pages.push(base);
+
+        // Reversed so that slots are handed out in address order
+        let num_slots = PAGE_SIZE / self.slot_size;
+        for i in (0..num_slots).rev() {
+            // Unsafe is fine since every slot is inside of the page
+            let slot = unsafe { base.as_ptr().add(i * self.slot_size) };
+            self.free
+                .push(NonNull::new(slot).expect("slot inside nonnull page"));
+        }
+    }
+
+    /// Pair the highest objects with the lowest free slots below them
+    fn plan_moves(&mut self, mut objects: Vec<NonNull<u8>>) -> Vec<(NonNull<u8>, NonNull<u8>)> {
+        self.free.sort_unstable();
+        objects.sort_unstable_by(|a, b| b.cmp(a));
+
+        let moves: Vec<_> = objects
+            .into_iter()
+            .zip(self.free.iter().cloned())
+            .take_while(|&(from, to)| to < from)
+            .collect();
+        // The vacated slots replace the ones that were filled
+        self.free.drain(..moves.len());
+        self.free.extend(moves.iter().map(|&(from, _)| from));
+        moves
+    }
+
+    /// Take the pages that have no used slots out of the class
+    fn take_empty_pages(&mut self) -> Vec<NonNull<u8>> {
+        let num_slots = PAGE_SIZE / self.slot_size;
+        self.pages.sort_unstable();
+        let mut free_per_page = vec![0; self.pages.len()];
+        for &slot in &self.free {
+            free_per_page[self.page_of(slot)] += 1;
+        }
+        let is_empty: Vec<bool> = free_per_page
+            .iter()
+            .map(|&free| free == num_slots)
+            .collect();
+
+        let free: Vec<_> = self
+            .free
+            .iter()
+            .cloned()
+            .filter(|&slot| !is_empty[self.page_of(slot)])
+            .collect();
+        self.free = free;
+
+        let (empty, kept): (Vec<_>, Vec<_>) = self
+            .pages
+            .drain(..)
+            .zip(is_empty)
+            .partition(|&(_, is_empty)| is_empty);
+        self.pages = kept.into_iter().map(|(page, _)| page).collect();
+        empty.into_iter().map(|(page, _)| page).collect()
+    }
+
+    /// Index of the page `slot` is in. `pages` must be sorted.
+    fn page_of(&self, slot: NonNull<u8>) -> usize {
+        match self.pages.binary_search(&slot) {
+            Ok(idx) => idx,
+            Err(idx) => idx - 1,
+        }
+    }
+
+    fn stats(&self) -> SizeClassStats {
+        SizeClassStats {
+            slot_size: self.slot_size,
+            slots: self.pages.len() * (PAGE_SIZE / self.slot_size),
+            used: self.used,
+            requested_bytes: self.requested,
+        }
+    }
+}
+
+fn page_layout() -> Layout {
+    Layout::from_size_align(PAGE_SIZE, PAGE_ALIGN).expect("valid page layout")
+}
+
+/// Hands out storage for objects, reusing freed slots of the same size class.
+pub(crate) struct SlabAllocator {
+    classes: Vec<SizeClass>,
+    large_objects: usize,
+    large_bytes: usize,
+    backing: Box<dyn GlobalAlloc>,
+}
+
+impl Default for SlabAllocator {
+    fn default() -> SlabAllocator {
+        SlabAllocator::new()
+    }
+}
+
+impl fmt::Debug for SlabAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SlabAllocator")
+            .field("classes", &self.classes)
+            .field("large_objects", &self.large_objects)
+            .field("large_bytes", &self.large_bytes)
+            .finish()
+    }
+}
+
+impl PartialEq for SlabAllocator {
+    // The backing allocator can't be compared, so it is ignored
+    fn eq(&self, other: &SlabAllocator) -> bool {
+        self.classes == other.classes
+            && self.large_objects == other.large_objects
+            && self.large_bytes == other.large_bytes
+    }
+}
+
+impl Drop for SlabAllocator {
+    fn drop(&mut self) {
+        for class in &mut self.classes {
+            for page in class.pages.drain(..) {
+                // Unsafe is fine since every page was allocated with this layout
+                unsafe { self.backing.dealloc(page.as_ptr(), page_layout()) };
+            }
+        }
+    }
+}
+
+impl SlabAllocator {
+    pub fn new() -> SlabAllocator {
+        SlabAllocator::with_backing(Box::new(GlobalHeap))
+    }
+
+    pub fn with_backing(backing: Box<dyn GlobalAlloc>) -> SlabAllocator {
+        SlabAllocator {
+            backing,
+            classes: SLOT_SIZES
+                .iter()
+                .map(|&size| SizeClass::new(size))
+                .collect(),
+            large_objects: 0,
+            large_bytes: 0,
+        }
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.classes.len()
+    }
+
+    /// The size class an object with this layout is stored in, if any
+    pub fn class_for(layout: Layout) -> Option<usize> {
+        if layout.align() > PAGE_ALIGN {
+            return None;
+        }
+        SLOT_SIZES.iter().position(|&size| size >= layout.size())
+    }
+
+    pub fn alloc(&mut self, layout: Layout) -> NonNull<u8> {
+        match SlabAllocator::class_for(layout) {
+            Some(class) => self.classes[class].alloc(layout.size(), &*self.backing),
+            None => {
+                self.large_objects += 1;
+                self.large_bytes += layout.size();
+                // Unsafe is fine since `GcBox`es are never zero sized
+                match NonNull::new(unsafe { self.backing.alloc(layout) }) {
+                    Some(ptr) => ptr,
+                    None => handle_alloc_error(layout),
+                }
+            }
+        }
+    }
+
+    /// Unsafe because `ptr` must have been returned by `alloc` with the same
+    /// layout, and can't be used afterwards
+    pub unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        match SlabAllocator::class_for(layout) {
+            Some(class) => self.classes[class].dealloc(ptr, layout.size()),
+            None => {
+                self.large_objects -= 1;
+                self.large_bytes -= layout.size();
+                self.backing.dealloc(ptr.as_ptr(), layout);
+            }
+        }
+    }
+
+    /// Pick free slots to move the given objects of a size class into, so that
+    /// used slots end up at the lowest addresses. Returns `(from, to)` pairs.
+    ///
+    /// The slots are already swapped in the free list, so the caller must move
+    /// every object.
+    pub fn plan_moves(
+        &mut self,
+        class: usize,
+        objects: Vec<NonNull<u8>>,
+    ) -> Vec<(NonNull<u8>, NonNull<u8>)> {
+        self.classes[class].plan_moves(objects)
+    }
+
+    /// Give pages without any used slots back to the backing allocator
+    pub fn release_empty_pages(&mut self) {
+        for class in 0..self.classes.len() {
+            for page in self.classes[class].take_empty_pages() {
+                // Unsafe is fine since every page was allocated with this layout
+                unsafe { self.backing.dealloc(page.as_ptr(), page_layout()) };
+            }
+        }
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        SlabStats {
+            classes: self.classes.iter().map(SizeClass::stats).collect(),
+            large_objects: self.large_objects,
+            large_bytes: self.large_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_freed_slots() {
+        let mut slab = SlabAllocator::new();
+        let layout = Layout::new::<[u64; 3]>();
+        let first = slab.alloc(layout);
+        let second = slab.alloc(layout);
+        assert_ne!(first, second);
+
+        unsafe { slab.dealloc(first, layout) };
+        assert_eq!(slab.alloc(layout), first);
+
+        let stats = slab.stats();
+        let class = stats.classes.iter().find(|class| class.used > 0).unwrap();
+        assert_eq!(class.slot_size, 32);
+        assert_eq!(class.used, 2);
+        assert_eq!(class.requested_bytes, 48);
+        assert_eq!(class.slots, PAGE_SIZE / 32);
+        unsafe {
+            slab.dealloc(first, layout);
+            slab.dealloc(second, layout);
+        }
+    }
+
+    #[test]
+    fn large_objects_use_system_allocator() {
+        let mut slab = SlabAllocator::new();
+        let big = Layout::new::<[u8; 1000]>();
+        let aligned = Layout::from_size_align(16, 64).unwrap();
+        let big_ptr = slab.alloc(big);
+        let aligned_ptr = slab.alloc(aligned);
+        assert_eq!(aligned_ptr.as_ptr() as usize % 64, 0);
+
+        let stats = slab.stats();
+        assert_eq!(stats.large_objects, 2);
+        assert_eq!(stats.large_bytes, 1016);
+        assert!(stats.classes.iter().all(|class| class.slots == 0));
+
+        unsafe {
+            slab.dealloc(big_ptr, big);
+            slab.dealloc(aligned_ptr, aligned);
+        }
+        assert_eq!(slab.stats().large_objects, 0);
+    }
+
+    #[test]
+    fn compaction_fills_low_slots_and_frees_pages() {
+        let mut slab = SlabAllocator::new();
+        let layout = Layout::new::<[u64; 8]>();
+        let class = SlabAllocator::class_for(layout).unwrap();
+        let slots_per_page = PAGE_SIZE / 64;
+        let ptrs: Vec<_> = (0..slots_per_page * 3)
+            .map(|_| slab.alloc(layout))
+            .collect();
+
+        // Keep the last slot of each page
+        let mut kept = vec![];
+        for (i, &ptr) in ptrs.iter().enumerate() {
+            if i % slots_per_page == slots_per_page - 1 {
+                kept.push(ptr);
+            } else {
+                unsafe { slab.dealloc(ptr, layout) };
+            }
+        }
+
+        // Pages aren't guaranteed to come back in address order, so one of
+        // the kept objects may already sit below every free slot
+        let moves = slab.plan_moves(class, kept.clone());
+        assert!(moves.len() >= 2);
+        assert!(moves.iter().all(|&(from, to)| to < from));
+        let mut free: Vec<_> = ptrs.iter().filter(|p| !kept.contains(p)).collect();
+        free.sort();
+        let mut targets: Vec<_> = moves.iter().map(|&(_, to)| to).collect();
+        targets.sort();
+        assert!(targets.iter().zip(free).all(|(to, free)| to == free));
+        let unmoved = kept
+            .iter()
+            .filter(|&&ptr| moves.iter().all(|&(from, _)| from != ptr));
+        targets.extend(unmoved);
+
+        slab.release_empty_pages();
+        let stats = slab.stats();
+        assert_eq!(stats.classes[class].slots, slots_per_page);
+        assert_eq!(stats.classes[class].used, 3);
+
+        for to in targets {
+            unsafe { slab.dealloc(to, layout) };
+        }
+        slab.release_empty_pages();
+        assert_eq!(slab.stats().reserved_bytes(), 0);
+    }
+
+    #[test]
+    fn slots_are_aligned() {
+        let mut slab = SlabAllocator::new();
+        let layout = Layout::from_size_align(40, 16).unwrap();
+        let ptrs: Vec<_> = (0..200).map(|_| slab.alloc(layout)).collect();
+        for &ptr in &ptrs {
+            assert_eq!(ptr.as_ptr() as usize % 16, 0);
+        }
+        for ptr in ptrs {
+            unsafe { slab.dealloc(ptr, layout) };
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Information about what is stored in the gc heap.
+//!
+//! Use [`Proxy::heap_histogram`] to get a summary of the objects currently
+//! tracked by the collector, grouped by type and by where they were allocated.
+//!
+//! Allocation sites are only recorded while [`Proxy::set_track_alloc_sites`]
+//! is enabled, and backtraces are only captured for the fraction of allocations
+//! chosen with [`Proxy::set_backtrace_sample_rate`].
+//!
+//! # Examples
+//!
+//! ```
+//! use ters_gc::Collector;
+//!
+//! let mut col = Collector::new();
+//! let mut proxy = col.proxy();
+//!
+//! proxy.set_track_alloc_sites(true);
+//!
+//! let _nums: Vec<_> = (0..10).map(|i| proxy.alloc(i)).collect();
+//! let _name = proxy.alloc("Ferris".to_string());
+//!
+//! let histogram = proxy.heap_histogram();
+//! assert_eq!(histogram[0].count, 10);
+//! assert_eq!(histogram[0].type_name, "i32");
+//! assert!(histogram[0].location.is_some());
+//! ```
+//!
+//! [`Proxy::slab_stats`] describes the memory the collector has reserved for
+//! objects, and how much of it is wasted.
+//!
+//! [`Proxy::heap_histogram`]: ../struct.Proxy.html#method.heap_histogram
+//! [`Proxy::slab_stats`]: ../struct.Proxy.html#method.slab_stats
+//! [`Proxy::set_track_alloc_sites`]: ../struct.Proxy.html#method.set_track_alloc_sites
+//! [`Proxy::set_backtrace_sample_rate`]: ../struct.Proxy.html#method.set_backtrace_sample_rate
+
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::panic::Location;
+use std::rc::Rc;
+
+/// Where in the client program an object was allocated.
+#[derive(Debug)]
+pub(crate) struct AllocSite {
+    pub location: &'static Location<'static>,
+    pub backtrace: Option<Rc<Backtrace>>,
+}
+
+/// A group of tracked objects that share a type and allocation site.
+#[derive(Debug)]
+pub struct HistogramEntry {
+    /// Name of the type of the objects, as given by [`type_name`].
+    ///
+    /// [`type_name`]: https://doc.rust-lang.org/std/any/fn.type_name.html
+    pub type_name: &'static str,
+    /// Source location of the [`Proxy::alloc`] call that created the objects.
+    ///
+    /// `None` if the objects were allocated while site tracking was disabled.
+    ///
+    /// [`Proxy::alloc`]: ../struct.Proxy.html#method.alloc
+    pub location: Option<&'static Location<'static>>,
+    /// Number of tracked objects in this group.
+    pub count: usize,
+    /// Total number of bytes used by the objects in this group.
+    pub bytes: usize,
+    /// Total number of bytes of heap memory owned by the objects in this
+    /// group, as reported by [`HeapSize`]. Only counted for objects allocated
+    /// with [`Proxy::alloc_measured`]. Objects allocated with
+    /// [`Proxy::alloc`] always add `0`, even if they own heap memory.
+    ///
+    /// [`HeapSize`]: ../heap_size/trait.HeapSize.html
+    /// [`Proxy::alloc`]: ../struct.Proxy.html#method.alloc
+    /// [`Proxy::alloc_measured`]: ../struct.Proxy.html#method.alloc_measured
+    pub heap_bytes: usize,
+    /// A backtrace captured when one of the objects was allocated, if any of
+    /// them were sampled.
+    pub backtrace: Option<Rc<Backtrace>>,
+}
+
+/// A type whose [`Trace`] impl didn't report all of its [`Gc`]s. Returned by
+/// [`Proxy::trace_suspects`].
+///
+/// Found when freeing objects of the type dropped references that their
+/// [`Trace`] impl never reported.
+///
+/// [`Trace`]: ../trace/trait.Trace.html
+/// [`Gc`]: ../ptr/struct.Gc.html
+/// [`Proxy::trace_suspects`]: ../struct.Proxy.html#method.trace_suspects
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceSuspect {
+    /// Name of the type of the freed objects, as given by [`type_name`].
+    ///
+    /// [`type_name`]: https://doc.rust-lang.org/std/any/fn.type_name.html
+    pub type_name: &'static str,
+    /// Source location of the [`Proxy::alloc`] call that created the freed
+    /// objects.
+    ///
+    /// `None` if they were allocated while site tracking was disabled.
+    ///
+    /// [`Proxy::alloc`]: ../struct.Proxy.html#method.alloc
+    pub location: Option<&'static Location<'static>>,
+    /// Number of references the freed objects dropped without having traced
+    /// them.
+    pub untraced_refs: usize,
+    /// Number of collections that found untraced references.
+    pub runs: usize,
+}
+
+/// An object that looks like it might be part of a cycle leaked by a
+/// [`Trace`] impl. Returned by [`Proxy::leak_suspects`].
+///
+/// [`Trace`]: ../trace/trait.Trace.html
+/// [`Proxy::leak_suspects`]: ../struct.Proxy.html#method.leak_suspects
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LeakSuspect {
+    /// Name of the type of the object, as given by [`type_name`].
+    ///
+    /// [`type_name`]: https://doc.rust-lang.org/std/any/fn.type_name.html
+    pub type_name: &'static str,
+    /// Source location of the [`Proxy::alloc`] call that created the object.
+    ///
+    /// `None` if the object was allocated while site tracking was disabled.
+    ///
+    /// [`Proxy::alloc`]: ../struct.Proxy.html#method.alloc
+    pub location: Option<&'static Location<'static>>,
+    /// Number of references to the object that weren't found by tracing.
+    pub external_refs: usize,
+    /// Number of collections in a row the object has looked leaked for.
+    pub runs: usize,
+}
+
+/// Memory usage of the collector's allocator. Returned by
+/// [`Proxy::slab_stats`].
+///
+/// Small objects are stored in fixed size slots that are grouped by size
+/// class. Objects too large for any size class are counted separately.
+///
+/// [`Proxy::slab_stats`]: ../struct.Proxy.html#method.slab_stats
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlabStats {
+    /// Usage of each size class, smallest first.
+    pub classes: Vec<SizeClassStats>,
+    /// Number of objects that were too large to fit in a size class.
+    pub large_objects: usize,
+    /// Total number of bytes used by those large objects.
+    pub large_bytes: usize,
+}
+
+impl SlabStats {
+    /// Number of bytes reserved for objects in size classes.
+    pub fn reserved_bytes(&self) -> usize {
+        self.classes
+            .iter()
+            .map(SizeClassStats::reserved_bytes)
+            .sum()
+    }
+
+    /// Number of bytes actually used by objects in size classes.
+    pub fn requested_bytes(&self) -> usize {
+        self.classes.iter().map(|class| class.requested_bytes).sum()
+    }
+
+    /// Fraction of the memory reserved for size classes that doesn't hold
+    /// object data, between `0.0` and `1.0`.
+    ///
+    /// This includes both unused slots and the unused tail of slots that are
+    /// larger than their object.
+    pub fn fragmentation(&self) -> f64 {
+        fragmentation(self.requested_bytes(), self.reserved_bytes())
+    }
+}
+
+/// Memory usage of a single size class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeClassStats {
+    /// Number of bytes in each slot.
+    pub slot_size: usize,
+    /// Number of slots the class has reserved memory for.
+    pub slots: usize,
+    /// Number of slots that hold an object.
+    pub used: usize,
+    /// Number of bytes used by the objects in this class.
+    pub requested_bytes: usize,
+}
+
+impl SizeClassStats {
+    /// Number of slots that are ready to be reused.
+    pub fn free_slots(&self) -> usize {
+        self.slots - self.used
+    }
+
+    /// Number of bytes reserved for this class.
+    pub fn reserved_bytes(&self) -> usize {
+        self.slots * self.slot_size
+    }
+
+    /// Fraction of this class's memory that doesn't hold object data, between
+    /// `0.0` and `1.0`.
+    pub fn fragmentation(&self) -> f64 {
+        fragmentation(self.requested_bytes, self.reserved_bytes())
+    }
+}
+
+fn fragmentation(requested: usize, reserved: usize) -> f64 {
+    if reserved == 0 {
+        return 0.0;
+    }
+    1.0 - requested as f64 / reserved as f64
+}
+
+/// Accumulates per-object information into histogram entries.
+#[derive(Default)]
+pub(crate) struct HistogramBuilder {
+    entries: HashMap<(&'static str, Option<Location<'static>>), HistogramEntry>,
+}
+
+impl HistogramBuilder {
+    pub fn add(
+        &mut self,
+        type_name: &'static str,
+        bytes: usize,
+        heap_bytes: usize,
+        site: Option<&AllocSite>,
+    ) {
+        let location = site.map(|site| site.location);
+        let entry = self
+            .entries
+            .entry((type_name, location.cloned()))
+            .or_insert_with(|| HistogramEntry {
+                type_name,
+                location,
+                count: 0,
+                bytes: 0,
+                heap_bytes: 0,
+                backtrace: None,
+            });
+        entry.count += 1;
+        entry.bytes += bytes;
+        entry.heap_bytes += heap_bytes;
+        if entry.backtrace.is_none() {
+            entry.backtrace = site.and_then(|site| site.backtrace.clone());
+        }
+    }
+
+    /// Entries with the most objects first
+    pub fn finish(self) -> Vec<HistogramEntry> {
+        let mut entries: Vec<_> = self.entries.into_values().collect();
+        entries.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.type_name.cmp(b.type_name))
+                .then_with(|| a.location.cmp(&b.location))
+        });
+        entries
+    }
+}
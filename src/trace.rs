@@ -22,21 +22,36 @@
 //!
 //! Tracing requires an immutable borrow of the contents. This will cause a panic
 //! if collection occurs while you hold a mutable reference to the contents.
+//! Use a [`GcCell`] instead if that can happen.
 //!
-//! ## Types not Implemented
+//! * `Rc` and `Arc`
+//!
+//! The contents of an `Rc` or `Arc` are only traced the first time the
+//! allocation is reached while tracing the gc heap, so values with many owners
+//! and reference cycles outside the gc heap are handled. Implement [`Trace`]
+//! for your own shared pointers with [`Tracer::add_shared_target`].
 //!
 //! * [`Cell`]
 //!
-//! Cell prevents you from taking a reference to its contents. This means that
-//! we cannot trace the contents of a [`Cell`].
+//! Cell doesn't let you take a reference to its contents, so tracing briefly
+//! takes them out of the cell and puts them back. This requires the contents
+//! to implement `Default`, which `Option<Gc<T>>` does.
 //!
-//! * [`Mutex`]
+//! * [`Mutex`] and [`RwLock`]
 //!
-//! Would deadlock if collection was triggered while the [`Mutex`] was locked.
+//! Tracing doesn't block on the lock. If it is held when a collection happens,
+//! the collection is postponed until a later allocation, and nothing is freed.
 //!
-//! * [`RwLock`]
+//! * Map keys and [`Result`] errors
 //!
-//! Same as above
+//! Map keys and [`Result`] errors are never traced, even when they implement
+//! [`Trace`]. Only the values of `HashMap` and `BTreeMap` (and of the maps
+//! from the optional `hashbrown`, `im` and `indexmap` features) and the `Ok`
+//! side of a [`Result`] are. A [`Gc`] used as a map key or as an error
+//! **leaks** if it's part of a cycle, unless the map or result is wrapped in a
+//! [`TraceAll`], which is the only way to trace keys and errors.
+//!
+//! ## Types not Implemented
 //!
 //! * Iterator Structs
 //!
@@ -128,7 +143,7 @@
 //!     doesnt_matter: bool,
 //!     also_trace_this: Vec<Gc<'a, DoesTrace<'a>>>,
 //! }
-//! 
+//!
 //!
 //! let mut col = Collector::new();
 //! let mut proxy = col.proxy();
@@ -142,22 +157,90 @@
 //! });
 //! ```
 //!
+//! ## Trait objects
+//!
+//! A trait object is traced through its vtable if its trait has [`Trace`] as a
+//! supertrait. Then `Box<dyn MyTrait>`, `Rc<dyn MyTrait>` and
+//! [`Gc`]`<dyn MyTrait>` implement [`Trace`], and deriving works without
+//! `#[ignore_trace]`.
+//!
+//! ```
+//! extern crate ters_gc;
+//! #[macro_use] extern crate ters_gc_derive;
+//!
+//! use std::rc::Rc;
+//! use ters_gc::trace::Trace;
+//! use ters_gc::{Collector, Gc};
+//!
+//! trait Behaviour: Trace {
+//!     fn act(&self) -> i32;
+//! }
+//!
+//! #[derive(Trace)]
+//! struct Follow<'a> {
+//!     target: Gc<'a, i32>,
+//! }
+//!
+//! impl<'a> Behaviour for Follow<'a> {
+//!     fn act(&self) -> i32 {
+//!         *self.target
+//!     }
+//! }
+//!
+//! #[derive(Trace)]
+//! struct Actor<'a> {
+//!     behaviour: Box<dyn Behaviour + 'a>,
+//!     shared: Rc<dyn Behaviour + 'a>,
+//! }
+//!
+//! let mut col = Collector::new();
+//! let mut proxy = col.proxy();
+//!
+//! let target = proxy.alloc(5);
+//! let actor = proxy.alloc(Actor {
+//!     behaviour: Box::new(Follow { target: target.clone() }),
+//!     shared: Rc::new(Follow { target }),
+//! });
+//! proxy.run();
+//! assert_eq!(actor.behaviour.act(), 5);
+//! ```
+//!
+//! The same works for a trait from another crate by declaring a trait with
+//! both as supertraits, implemented for every type that implements both.
 //!
+//! ```
+//! use std::error::Error;
+//! use ters_gc::trace::Trace;
 //!
+//! trait TraceError: Error + Trace {}
+//! impl<T: Error + Trace> TraceError for T {}
 //!
+//! struct Failure<'a> {
+//!     source: Option<Box<dyn TraceError + 'a>>,
+//! }
+//! ```
 //!
 //! [`Trace`]: trait.Trace.html
 //! [`Tracer::add_target`]: struct.Tracer.html#method.add_target
+//! [`Tracer::add_shared_target`]: struct.Tracer.html#method.add_shared_target
 //! [`Gc`]: ../ptr/struct.Gc.html
+//! [`Result`]: https://doc.rust-lang.org/std/result/enum.Result.html
+//! [`TraceAll`]: struct.TraceAll.html
+//! [`GcCell`]: ../cell/struct.GcCell.html
 //! [`RefCell`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
 //! [`Cell`]: https://doc.rust-lang.org/std/cell/struct.Cell.html
 //! [`Mutex`]: https://doc.rust-lang.org/std/sync/struct.Mutex.html
 //! [`RwLock`]: https://doc.rust-lang.org/std/sync/struct.RwLock.html
 
-use ptr::{Gc, GcBox, Weak};
+use ptr::{Gc, GcAny, Handle, Weak};
+use std::any::TypeId;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::ops::{Deref, DerefMut};
 use std::ptr::NonNull;
-use AsUntyped;
-use UntypedGcBox;
+use std::sync::atomic::{AtomicU8, Ordering};
 
 // Impls: For every object `obj` that impls Trace, call `tracer.add_entry(&obj)`.
 // Can act funny if you have Sp<Gc<T>> where Sp is a smart pointer that
@@ -173,46 +256,408 @@ pub trait Trace {
     ///
     /// [`Gc`]: ../ptr/struct.Gc.html
     fn trace(&self, _tracer: &mut Tracer);
+
+    /// Whether a value of this type can ever contain a [`Gc`].
+    ///
+    /// Defaults to `true`. Types that can't, like `u8` or `String`, return
+    /// `false` so that containers of them and objects in the gc heap made of
+    /// them aren't traced at all. The derive macro returns `false` when no
+    /// traced field needs tracing.
+    ///
+    /// Returning `false` for a type that does hold a [`Gc`] has the same effect
+    /// as a `trace` that skips it: cycles through it are never collected.
+    ///
+    /// This is a method rather than a `NEEDS_TRACE` associated constant. A
+    /// trait with an associated constant isn't dyn compatible, and `Trace`
+    /// has to work for trait objects like `Box<dyn Trace>`.
+    ///
+    /// The derived impl works out its answer once per type and caches it, so
+    /// calling it while tracing is cheap. It handles recursive types, like a
+    /// tree node holding a `Vec` of nodes. A type reached again while its own
+    /// answer is being worked out adds nothing, so only the other fields
+    /// decide.
+    ///
+    /// Pointers and cells that can hold unsized values, like `Box`, `Rc`,
+    /// `RefCell` and [`GcCell`], always need tracing. Their contents may be a
+    /// trait object, and an unsized type can't be asked, so `RefCell<Vec<u8>>`
+    /// isn't a leaf even though `Vec<u8>` is. `Cow` asks its owned type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::trace::{Trace, Tracer};
+    ///
+    /// struct Celsius(f64);
+    ///
+    /// impl Trace for Celsius {
+    ///     fn trace(&self, _: &mut Tracer) {}
+    ///     fn needs_trace() -> bool {
+    ///         false
+    ///     }
+    /// }
+    ///
+    /// assert!(!Vec::<Celsius>::needs_trace());
+    /// assert!(Option::<ters_gc::Gc<Celsius>>::needs_trace());
+    /// ```
+    ///
+    /// [`Gc`]: ../ptr/struct.Gc.html
+    /// [`GcCell`]: ../cell/struct.GcCell.html
+    #[inline]
+    fn needs_trace() -> bool
+    where
+        Self: Sized,
+    {
+        true
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
-pub(crate) struct TraceDest(pub NonNull<UntypedGcBox>);
+pub(crate) struct TraceDest(pub NonNull<Handle>);
 
 /// Destination for trace information.
+///
+/// A single tracer is used for every object traced during one pass over the
+/// gc heap, so it can remember which shared allocations it has already seen.
 #[derive(Debug, PartialEq, Eq)]
 pub struct Tracer {
     targets: Vec<TraceDest>,
+    visited_shared: HashSet<(*const (), usize)>,
+    postponed: bool,
 }
 
 impl Tracer {
     pub(crate) fn new() -> Tracer {
-        Tracer { targets: vec![] }
+        Tracer {
+            targets: vec![],
+            visited_shared: HashSet::new(),
+            postponed: false,
+        }
     }
     /// Add a trace target
     pub fn add_target<T: Trace + ?Sized>(&mut self, target: &T) {
         target.trace(self);
     }
-    fn add_box<T>(&mut self, gc_box: NonNull<GcBox<T>>) {
-        self.targets.push(TraceDest(gc_box.as_untyped()));
+    /// Add a trace target that may be shared by several owners, such as the
+    /// contents of an `Rc`.
+    ///
+    /// `target` is only traced the first time its address is seen during a
+    /// pass over the gc heap. This keeps a value with many owners from being
+    /// traced many times, and keeps reference cycles outside the gc heap from
+    /// recursing forever.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ters_gc::trace::{Trace, Tracer};
+    ///
+    /// struct MyRc<T>(std::rc::Rc<T>);
+    ///
+    /// impl<T: Trace> Trace for MyRc<T> {
+    ///     fn trace(&self, tracer: &mut Tracer) {
+    ///         tracer.add_shared_target(&*self.0);
+    ///     }
+    /// }
+    /// ```
+    pub fn add_shared_target<T: Trace + ?Sized>(&mut self, target: &T) {
+        let address: *const T = target;
+        // The size keeps a shared value from being mistaken for a smaller
+        // one stored at its start
+        let key = (address as *const (), size_of_val(target));
+        if self.visited_shared.insert(key) {
+            target.trace(self);
+        }
+    }
+    /// Give up on the current collection because a target can't be traced
+    /// right now, like a `Mutex` that is already locked.
+    ///
+    /// The collector finishes tracing, frees nothing, and tries again on a
+    /// later allocation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::cell::{Cell, RefCell};
+    /// use ters_gc::trace::{Trace, Tracer};
+    ///
+    /// struct Locked<T> {
+    ///     locked: Cell<bool>,
+    ///     value: RefCell<T>,
+    /// }
+    ///
+    /// impl<T: Trace> Trace for Locked<T> {
+    ///     fn trace(&self, tracer: &mut Tracer) {
+    ///         if self.locked.get() {
+    ///             tracer.postpone();
+    ///         } else {
+    ///             tracer.add_target(&*self.value.borrow());
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn postpone(&mut self) {
+        self.postponed = true;
+    }
+    pub(crate) fn is_postponed(&self) -> bool {
+        self.postponed
     }
-    pub(crate) fn results(self) -> ::std::vec::IntoIter<TraceDest> {
-        self.targets.into_iter()
+    fn add_handle(&mut self, handle: NonNull<Handle>) {
+        self.targets.push(TraceDest(handle));
+    }
+    /// Take the targets found since the last call, keeping the record of
+    /// visited shared allocations
+    pub(crate) fn results(&mut self) -> ::std::vec::IntoIter<TraceDest> {
+        ::std::mem::take(&mut self.targets).into_iter()
     }
 }
 
-impl<'a, T> Trace for Gc<'a, T> {
+impl<'a, T: ?Sized> Trace for Gc<'a, T> {
     fn trace(&self, tracer: &mut Tracer) {
-        if let Some(box_ptr) = self.get_box_ptr() {
-            tracer.add_box(box_ptr);
+        // Freed objects can't be reached
+        if self.get_box_ptr().is_some() {
+            tracer.add_handle(self.handle_ptr());
         }
     }
 }
-impl<'a, T> Trace for Weak<'a, T> {
+impl<'a> Trace for GcAny<'a> {
+    fn trace(&self, tracer: &mut Tracer) {
+        // Freed objects can't be reached
+        if self.get_box_ptr().is_some() {
+            tracer.add_handle(self.handle_ptr());
+        }
+    }
+}
+impl<'a, T: ?Sized> Trace for Weak<'a, T> {
     /// Noop
     #[inline]
     fn trace(&self, _: &mut Tracer) {
         // noop
     }
+    #[inline]
+    fn needs_trace() -> bool {
+        false
+    }
+}
+
+/// The `TypeId` of `T`, with its lifetimes erased.
+///
+/// Types that differ only in their lifetimes get the same id. Lifetimes can't
+/// change whether a type holds a [`Gc`], so that doesn't matter here.
+///
+/// [`Gc`]: ../ptr/struct.Gc.html
+fn lifetime_free_type_id<T: ?Sized>() -> TypeId {
+    use std::marker::PhantomData;
+
+    trait NonStaticAny {
+        fn type_id(&self) -> TypeId
+        where
+            Self: 'static;
+    }
+    impl<T: ?Sized> NonStaticAny for PhantomData<T> {
+        fn type_id(&self) -> TypeId
+        where
+            Self: 'static,
+        {
+            TypeId::of::<T>()
+        }
+    }
+    let phantom = PhantomData::<T>;
+    let phantom: &dyn NonStaticAny = &phantom;
+    // Unsafe is fine since only the lifetime changes, and `TypeId`s ignore
+    // lifetimes anyway
+    let phantom: &(dyn NonStaticAny + 'static) = unsafe { mem::transmute(phantom) };
+    phantom.type_id()
+}
+
+thread_local! {
+    /// Types whose derived `needs_trace` is being worked out further up the
+    /// stack
+    static DERIVING_NEEDS_TRACE: RefCell<HashSet<TypeId>> = RefCell::new(HashSet::new());
+    /// Derived `needs_trace` answers of generic types
+    static DERIVED_NEEDS_TRACE: RefCell<HashMap<TypeId, bool>> = RefCell::new(HashMap::new());
+}
+
+/// Stops working out a type's `needs_trace` once dropped, even on panic
+struct DerivingNeedsTrace(TypeId);
+
+impl Drop for DerivingNeedsTrace {
+    fn drop(&mut self) {
+        DERIVING_NEEDS_TRACE.with(|types| types.borrow_mut().remove(&self.0));
+    }
+}
+
+/// Returns what `fields` says about the type with id `type_id`, or `false`
+/// if the type is reached again from one of its own fields, since recursing
+/// would never end.
+///
+/// Also returns whether the answer is final. An answer worked out while an
+/// outer type's answer is pending can depend on the outer type's cut off
+/// answer, so only the outermost one is.
+fn derive_needs_trace<F: FnOnce() -> bool>(type_id: TypeId, fields: F) -> (bool, bool) {
+    let outermost = DERIVING_NEEDS_TRACE.with(|types| {
+        let mut types = types.borrow_mut();
+        let outermost = types.is_empty();
+        types.insert(type_id).then_some(outermost)
+    });
+    match outermost {
+        Some(outermost) => {
+            let _deriving = DerivingNeedsTrace(type_id);
+            (fields(), outermost)
+        }
+        None => (false, false),
+    }
+}
+
+/// Used by `#[derive(Trace)]` for generic types. Returns what `fields` says
+/// about `T`, working it out only once per type.
+#[doc(hidden)]
+pub fn derived_needs_trace<T: ?Sized, F: FnOnce() -> bool>(fields: F) -> bool {
+    let type_id = lifetime_free_type_id::<T>();
+    if let Some(needs_trace) =
+        DERIVED_NEEDS_TRACE.with(|cache| cache.borrow().get(&type_id).cloned())
+    {
+        return needs_trace;
+    }
+    let (needs_trace, is_final) = derive_needs_trace(type_id, fields);
+    if is_final {
+        DERIVED_NEEDS_TRACE.with(|cache| cache.borrow_mut().insert(type_id, needs_trace));
+    }
+    needs_trace
+}
+
+/// Used by `#[derive(Trace)]` for types that aren't generic, so that each
+/// type gets its own static cache.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct DerivedNeedsTrace(AtomicU8);
+
+impl DerivedNeedsTrace {
+    const UNKNOWN: u8 = 0;
+    const NO: u8 = 1;
+    const YES: u8 = 2;
+
+    pub const fn new() -> DerivedNeedsTrace {
+        DerivedNeedsTrace(AtomicU8::new(DerivedNeedsTrace::UNKNOWN))
+    }
+    /// Returns what `fields` says about `T`, working it out only once
+    pub fn get<T: ?Sized, F: FnOnce() -> bool>(&self, fields: F) -> bool {
+        match self.0.load(Ordering::Relaxed) {
+            DerivedNeedsTrace::NO => false,
+            DerivedNeedsTrace::YES => true,
+            _ => {
+                let (needs_trace, is_final) =
+                    derive_needs_trace(lifetime_free_type_id::<T>(), fields);
+                if is_final {
+                    let state = if needs_trace {
+                        DerivedNeedsTrace::YES
+                    } else {
+                        DerivedNeedsTrace::NO
+                    };
+                    self.0.store(state, Ordering::Relaxed);
+                }
+                needs_trace
+            }
+        }
+    }
+}
+
+impl Default for DerivedNeedsTrace {
+    fn default() -> DerivedNeedsTrace {
+        DerivedNeedsTrace::new()
+    }
+}
+
+/// Traces the parts of a value that its own [`Trace`] impl skips: the keys
+/// of a map, and the `Err` side of a `Result`.
+///
+/// Dereferences to the wrapped value.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+/// use ters_gc::trace::{Trace, TraceAll, Tracer};
+/// use ters_gc::{Collector, Gc};
+///
+/// struct Graph<'a> {
+///     weights: TraceAll<HashMap<Gc<'a, u32>, u32>>,
+/// }
+///
+/// impl<'a> Trace for Graph<'a> {
+///     fn trace(&self, tracer: &mut Tracer) {
+///         tracer.add_target(&self.weights);
+///     }
+/// }
+///
+/// let mut col = Collector::new();
+/// let mut proxy = col.proxy();
+///
+/// let node = proxy.alloc(7);
+/// let mut weights = TraceAll(HashMap::new());
+/// weights.insert(node, 2);
+/// let graph = proxy.alloc(Graph { weights });
+/// assert_eq!(graph.weights.len(), 1);
+/// ```
+///
+/// [`Trace`]: trait.Trace.html
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TraceAll<T>(pub T);
+
+impl<T> Deref for TraceAll<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for TraceAll<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Trace, E: Trace> Trace for TraceAll<Result<T, E>> {
+    /// Traces inner object, whether `Ok` or `Err`
+    fn trace(&self, tracer: &mut Tracer) {
+        match self.0 {
+            Ok(ref contents) => tracer.add_target(contents),
+            Err(ref err) => tracer.add_target(err),
+        }
+    }
+    fn needs_trace() -> bool {
+        T::needs_trace() || E::needs_trace()
+    }
+}
+
+impl<K: Trace, T: Trace, S: BuildHasher> Trace for TraceAll<HashMap<K, T, S>> {
+    /// Traces each key and value
+    fn trace(&self, tracer: &mut Tracer) {
+        if !Self::needs_trace() {
+            return;
+        }
+        for (key, tracee) in &self.0 {
+            tracer.add_target(key);
+            tracer.add_target(tracee);
+        }
+    }
+    fn needs_trace() -> bool {
+        K::needs_trace() || T::needs_trace()
+    }
+}
+
+impl<K: Trace, T: Trace> Trace for TraceAll<BTreeMap<K, T>> {
+    /// Traces each key and value
+    fn trace(&self, tracer: &mut Tracer) {
+        if !Self::needs_trace() {
+            return;
+        }
+        for (key, tracee) in &self.0 {
+            tracer.add_target(key);
+            tracer.add_target(tracee);
+        }
+    }
+    fn needs_trace() -> bool {
+        K::needs_trace() || T::needs_trace()
+    }
 }
 
 mod trace_impls {
@@ -221,6 +666,7 @@ mod trace_impls {
     use std::cmp::Eq;
     use std::cmp::Ord;
     use std::hash::Hash;
+    use std::sync::TryLockError;
 
     macro_rules! noop_impls {
         ($($T:ty)+) => {
@@ -231,6 +677,10 @@ mod trace_impls {
                     fn trace(&self, _: &mut Tracer) {
                         // noop
                     }
+                    #[inline]
+                    fn needs_trace() -> bool {
+                        false
+                    }
                 }
              )+
         }
@@ -242,11 +692,11 @@ mod trace_impls {
         u8 u16 u32 u64 u128
         isize usize
         f32 f64
-        char str
+        char
         String
         std::cmp::Ordering
-        std::ffi::CStr std::ffi::CString
-        std::ffi::OsStr std::ffi::OsString
+        std::ffi::CString
+        std::ffi::OsString
         std::fs::DirEntry std::fs::File
         std::fs::FileType std::fs::Metadata
         std::fs::OpenOptions std::fs::Permissions
@@ -257,17 +707,48 @@ mod trace_impls {
         std::net::SocketAddrV4 std::net::SocketAddrV6
         std::net::TcpStream std::net::UdpSocket
         std::net::IpAddr std::net::SocketAddr
-        std::path::Path std::path::PathBuf
+        std::num::NonZeroI8 std::num::NonZeroI16
+        std::num::NonZeroI32 std::num::NonZeroI64
+        std::num::NonZeroI128 std::num::NonZeroIsize
+        std::num::NonZeroU8 std::num::NonZeroU16
+        std::num::NonZeroU32 std::num::NonZeroU64
+        std::num::NonZeroU128 std::num::NonZeroUsize
+        std::ops::RangeFull
+        std::path::PathBuf
         std::sync::Condvar
         std::time::Duration std::time::Instant
         std::time::SystemTime
     }
-    impl<'a> Trace for &'a str {
+    // Unsized types can't say that they don't need tracing
+    macro_rules! unsized_noop_impls {
+        ($($T:ty)+) => {
+            $(
+                impl Trace for $T {
+                    /// Noop
+                    #[inline]
+                    fn trace(&self, _: &mut Tracer) {
+                        // noop
+                    }
+                }
+             )+
+        }
+    }
+    unsized_noop_impls! {
+        str
+        std::ffi::CStr
+        std::ffi::OsStr
+        std::path::Path
+    }
+    impl Trace for &str {
         /// Noop
         #[inline]
         fn trace(&self, _: &mut Tracer) {
             // noop
         }
+        #[inline]
+        fn needs_trace() -> bool {
+            false
+        }
     }
     macro_rules! noop_fn_impl {
         ($($T:tt)*) => {
@@ -277,6 +758,10 @@ mod trace_impls {
                 fn trace(&self, _: &mut Tracer) {
                     // noop
                 }
+                #[inline]
+                fn needs_trace() -> bool {
+                    false
+                }
             }
         }
     }
@@ -291,6 +776,10 @@ mod trace_impls {
         fn trace(&self, _: &mut Tracer) {
             // noop
         }
+        #[inline]
+        fn needs_trace() -> bool {
+            false
+        }
     }
     impl<T: ?Sized> Trace for *mut T {
         /// Noop
@@ -298,34 +787,126 @@ mod trace_impls {
         fn trace(&self, _: &mut Tracer) {
             // noop
         }
+        #[inline]
+        fn needs_trace() -> bool {
+            false
+        }
     }
 
-    impl<'a, T: Trace> Trace for [T] {
+    impl<T: Trace> Trace for [T] {
         /// Traces each element
         #[inline]
         fn trace(&self, tracer: &mut Tracer) {
+            if !T::needs_trace() {
+                return;
+            }
             for tracee in self.iter() {
                 tracer.add_target(tracee);
             }
         }
     }
-    macro_rules! array_impls {
-        ($($N:expr)+) => {
-            $(
-                impl<T: Trace> Trace for [T; $N] {
-                    /// Traces each element
-                    fn trace(&self, tracer: &mut Tracer) {
-                        tracer.add_target(&self[..]);
-                    }
+    impl<T: Trace, const N: usize> Trace for [T; N] {
+        /// Traces each element
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer.add_target(&self[..]);
+        }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
+    }
+    macro_rules! tuple_impls {
+        ($($T:ident)+) => {
+            impl<$($T: Trace),+> Trace for ($($T,)+) {
+                /// Traces each element
+                #[allow(non_snake_case)]
+                fn trace(&self, tracer: &mut Tracer) {
+                    let ($(ref $T,)+) = *self;
+                    $(tracer.add_target($T);)+
                 }
-             )+
+                fn needs_trace() -> bool {
+                    false $(|| $T::needs_trace())+
+                }
+            }
+        }
+    }
+    tuple_impls!(A);
+    tuple_impls!(A B);
+    tuple_impls!(A B C);
+    tuple_impls!(A B C D);
+    tuple_impls!(A B C D E);
+    tuple_impls!(A B C D E F);
+    tuple_impls!(A B C D E F G);
+    tuple_impls!(A B C D E F G H);
+    tuple_impls!(A B C D E F G H I);
+    tuple_impls!(A B C D E F G H I J);
+    tuple_impls!(A B C D E F G H I J K);
+    tuple_impls!(A B C D E F G H I J K L);
+    impl<T: ?Sized> Trace for std::marker::PhantomData<T> {
+        /// Noop
+        #[inline]
+        fn trace(&self, _: &mut Tracer) {
+            // noop
+        }
+        #[inline]
+        fn needs_trace() -> bool {
+            false
+        }
+    }
+    impl<T: Trace> Trace for std::ops::Range<T> {
+        /// Traces both bounds
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer.add_target(&self.start);
+            tracer.add_target(&self.end);
+        }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
+    }
+    impl<T: Trace> Trace for std::ops::RangeInclusive<T> {
+        /// Traces both bounds
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer.add_target(self.start());
+            tracer.add_target(self.end());
+        }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
+    }
+    impl<T: Trace> Trace for std::ops::RangeFrom<T> {
+        /// Traces the start bound
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer.add_target(&self.start);
+        }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
+    }
+    impl<T: Trace> Trace for std::ops::RangeTo<T> {
+        /// Traces the end bound
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer.add_target(&self.end);
+        }
+        fn needs_trace() -> bool {
+            T::needs_trace()
         }
     }
-    array_impls! {
-        0  1  2  3  4  5  6  7  8  9
-        10 11 12 13 14 15 16 17 18 19
-        20 21 22 23 24 25 26 27 28 29
-        30 31 32
+    impl<T: Trace> Trace for std::ops::RangeToInclusive<T> {
+        /// Traces the end bound
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer.add_target(&self.end);
+        }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
+    }
+    impl<T: Trace> Trace for std::cmp::Reverse<T> {
+        /// Traces inner object
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer.add_target(&self.0);
+        }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
     }
 
     impl<T: Trace> Trace for Option<T> {
@@ -335,6 +916,9 @@ mod trace_impls {
                 tracer.add_target(contents);
             }
         }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
     }
     impl<T: Trace, E> Trace for Result<T, E> {
         /// Traces inner object if `Ok`
@@ -343,33 +927,60 @@ mod trace_impls {
                 tracer.add_target(contents);
             }
         }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
+    }
+    impl<T: Trace + ?Sized> Trace for std::pin::Pin<Box<T>> {
+        /// Traces inner object (via deref)
+        fn trace(&self, tracer: &mut Tracer) {
+            let contents: &T = self;
+            tracer.add_target(contents);
+        }
+        fn needs_trace() -> bool {
+            Box::<T>::needs_trace()
+        }
     }
     impl<T: Trace + ?Sized> Trace for Box<T> {
         /// Traces inner object (via deref)
         fn trace(&self, tracer: &mut Tracer) {
-            let contents: &T = &*self;
+            let contents: &T = self;
             tracer.add_target(contents);
         }
     }
-    impl<'a, T: Trace + 'a + ToOwned + ?Sized> Trace for std::borrow::Cow<'a, T> {
+    impl<'a, T: Trace + 'a + ToOwned + ?Sized> Trace for std::borrow::Cow<'a, T>
+    where
+        T::Owned: Trace,
+    {
         /// Traces inner object (via deref)
         fn trace(&self, tracer: &mut Tracer) {
-            tracer.add_target(&*self);
+            tracer.add_target(&**self);
+        }
+        fn needs_trace() -> bool {
+            // Borrowed or not, the value is what the owned type holds
+            T::Owned::needs_trace()
         }
     }
     impl<T: Trace> Trace for Vec<T> {
         /// Traces each element
         fn trace(&self, tracer: &mut Tracer) {
+            if !Self::needs_trace() {
+                return;
+            }
             for tracee in self {
                 tracer.add_target(tracee);
             }
         }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
     }
     impl<T: Trace + ?Sized> Trace for std::rc::Rc<T> {
-        /// Traces inner object (via deref)
+        /// Traces inner object (via deref) the first time the allocation is
+        /// reached in a pass
         fn trace(&self, tracer: &mut Tracer) {
-            let contents: &T = &*self;
-            tracer.add_target(contents);
+            let contents: &T = self;
+            tracer.add_shared_target(contents);
         }
     }
     impl<T: Trace + ?Sized> Trace for std::rc::Weak<T> {
@@ -378,12 +989,17 @@ mod trace_impls {
         fn trace(&self, _: &mut Tracer) {
             // noop
         }
+        #[inline]
+        fn needs_trace() -> bool {
+            false
+        }
     }
     impl<T: Trace + ?Sized> Trace for std::sync::Arc<T> {
-        /// Traces inner object (via deref)
+        /// Traces inner object (via deref) the first time the allocation is
+        /// reached in a pass
         fn trace(&self, tracer: &mut Tracer) {
-            let contents: &T = &*self;
-            tracer.add_target(contents);
+            let contents: &T = self;
+            tracer.add_shared_target(contents);
         }
     }
     impl<T: Trace + ?Sized> Trace for std::sync::Weak<T> {
@@ -392,6 +1008,47 @@ mod trace_impls {
         fn trace(&self, _: &mut Tracer) {
             // noop
         }
+        #[inline]
+        fn needs_trace() -> bool {
+            false
+        }
+    }
+    impl<T: Trace + Default> Trace for std::cell::Cell<T> {
+        /// Takes the inner object out of the cell (leaving `T::default()`),
+        /// traces it, then puts it back
+        fn trace(&self, tracer: &mut Tracer) {
+            if !Self::needs_trace() {
+                return;
+            }
+            let contents = self.take();
+            tracer.add_target(&contents);
+            self.set(contents);
+        }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
+    }
+    impl<T: Trace + ?Sized> Trace for std::sync::Mutex<T> {
+        /// Traces the inner object if the mutex can be locked without
+        /// blocking. Postpones the collection otherwise.
+        fn trace(&self, tracer: &mut Tracer) {
+            match self.try_lock() {
+                Ok(contents) => tracer.add_target(&*contents),
+                Err(TryLockError::Poisoned(err)) => tracer.add_target(&*err.into_inner()),
+                Err(TryLockError::WouldBlock) => tracer.postpone(),
+            }
+        }
+    }
+    impl<T: Trace + ?Sized> Trace for std::sync::RwLock<T> {
+        /// Traces the inner object if the lock can be read without blocking.
+        /// Postpones the collection otherwise.
+        fn trace(&self, tracer: &mut Tracer) {
+            match self.try_read() {
+                Ok(contents) => tracer.add_target(&*contents),
+                Err(TryLockError::Poisoned(err)) => tracer.add_target(&*err.into_inner()),
+                Err(TryLockError::WouldBlock) => tracer.postpone(),
+            }
+        }
     }
     impl<T: Trace + ?Sized> Trace for std::cell::RefCell<T> {
         /// Borrows (Via `RefCell::borrow`) self and traces inner object
@@ -402,58 +1059,100 @@ mod trace_impls {
     impl<T: Trace> Trace for std::collections::VecDeque<T> {
         /// Traces each element
         fn trace(&self, tracer: &mut Tracer) {
+            if !Self::needs_trace() {
+                return;
+            }
             for tracee in self {
                 tracer.add_target(tracee);
             }
         }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
     }
     impl<T: Trace> Trace for std::collections::LinkedList<T> {
         /// Traces each element
         fn trace(&self, tracer: &mut Tracer) {
+            if !Self::needs_trace() {
+                return;
+            }
             for tracee in self {
                 tracer.add_target(tracee);
             }
         }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
     }
     impl<T: Trace, K: Eq + Hash> Trace for std::collections::HashMap<K, T> {
         /// Traces each value
         fn trace(&self, tracer: &mut Tracer) {
+            if !Self::needs_trace() {
+                return;
+            }
             for tracee in self.values() {
                 tracer.add_target(tracee);
             }
         }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
     }
-    impl<T: Trace, K: Eq + Hash> Trace for std::collections::BTreeMap<K, T> {
+    impl<T: Trace, K> Trace for std::collections::BTreeMap<K, T> {
         /// Traces each value
         fn trace(&self, tracer: &mut Tracer) {
+            if !Self::needs_trace() {
+                return;
+            }
             for tracee in self.values() {
                 tracer.add_target(tracee);
             }
         }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
     }
     impl<T: Trace + Eq + Hash> Trace for std::collections::HashSet<T> {
         /// Traces each value
         fn trace(&self, tracer: &mut Tracer) {
+            if !Self::needs_trace() {
+                return;
+            }
             for tracee in self {
                 tracer.add_target(tracee);
             }
         }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
     }
     impl<T: Trace + Eq + Hash> Trace for std::collections::BTreeSet<T> {
         /// Traces each value
         fn trace(&self, tracer: &mut Tracer) {
+            if !Self::needs_trace() {
+                return;
+            }
             for tracee in self {
                 tracer.add_target(tracee);
             }
         }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
     }
     impl<T: Trace + Ord> Trace for std::collections::BinaryHeap<T> {
         /// Traces each value
         fn trace(&self, tracer: &mut Tracer) {
+            if !Self::needs_trace() {
+                return;
+            }
             for tracee in self {
                 tracer.add_target(tracee);
             }
         }
+        fn needs_trace() -> bool {
+            T::needs_trace()
+        }
     }
     impl<T, U> Trace for std::io::Chain<T, U> {
         /// Noop
@@ -461,6 +1160,10 @@ mod trace_impls {
         fn trace(&self, _: &mut Tracer) {
             // noop
         }
+        #[inline]
+        fn needs_trace() -> bool {
+            false
+        }
     }
     impl<T> Trace for std::io::Cursor<T> {
         /// Noop
@@ -468,6 +1171,10 @@ mod trace_impls {
         fn trace(&self, _: &mut Tracer) {
             // noop
         }
+        #[inline]
+        fn needs_trace() -> bool {
+            false
+        }
     }
     impl<T> Trace for std::io::Take<T> {
         /// Noop
@@ -475,6 +1182,10 @@ mod trace_impls {
         fn trace(&self, _: &mut Tracer) {
             // noop
         }
+        #[inline]
+        fn needs_trace() -> bool {
+            false
+        }
     }
     impl<T> Trace for std::num::Wrapping<T> {
         /// Noop
@@ -482,18 +1193,292 @@ mod trace_impls {
         fn trace(&self, _: &mut Tracer) {
             // noop
         }
+        #[inline]
+        fn needs_trace() -> bool {
+            false
+        }
     }
 
-
     // Things chosen not to implement
-    // std::sync::Mutex - Not sure what behavior I want
-    // std::sync::RwLock - Not sure what behavior I want
     // std::iter::* - Too many structs
     // Iterators in general - Too lazy to do all them
-    // std::ops::Range* - Thats a lot of structs
     // std::any::Any - Not sure how to do this one
 }
 
+// Impls for containers from other crates, each behind a feature named after
+// the crate
+mod third_party_impls {
+    #[cfg(feature = "arrayvec")]
+    mod arrayvec_impls {
+        use arrayvec::{ArrayString, ArrayVec};
+        use trace::{Trace, Tracer};
+
+        impl<T: Trace, const CAP: usize> Trace for ArrayVec<T, CAP> {
+            /// Traces each element
+            fn trace(&self, tracer: &mut Tracer) {
+                tracer.add_target(&self[..]);
+            }
+            fn needs_trace() -> bool {
+                T::needs_trace()
+            }
+        }
+        impl<const CAP: usize> Trace for ArrayString<CAP> {
+            /// Noop
+            #[inline]
+            fn trace(&self, _: &mut Tracer) {
+                // noop
+            }
+            #[inline]
+            fn needs_trace() -> bool {
+                false
+            }
+        }
+    }
+
+    #[cfg(feature = "hashbrown")]
+    mod hashbrown_impls {
+        use hashbrown::{HashMap, HashSet};
+        use trace::{Trace, TraceAll, Tracer};
+
+        impl<K, T: Trace, S> Trace for HashMap<K, T, S> {
+            /// Traces each value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for tracee in self.values() {
+                    tracer.add_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                T::needs_trace()
+            }
+        }
+        impl<K: Trace, T: Trace, S> Trace for TraceAll<HashMap<K, T, S>> {
+            /// Traces each key and value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for (key, tracee) in &self.0 {
+                    tracer.add_target(key);
+                    tracer.add_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                K::needs_trace() || T::needs_trace()
+            }
+        }
+        impl<T: Trace, S> Trace for HashSet<T, S> {
+            /// Traces each value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for tracee in self {
+                    tracer.add_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                T::needs_trace()
+            }
+        }
+    }
+
+    // Clones of an im container share their nodes, and with them the
+    // elements, so each element is only traced the first time it's seen
+    #[cfg(feature = "im")]
+    mod im_impls {
+        use im::{HashMap, HashSet, OrdMap, OrdSet, Vector};
+        use std::hash::{BuildHasher, Hash};
+        use trace::{Trace, TraceAll, Tracer};
+
+        impl<T: Trace + Clone> Trace for Vector<T> {
+            /// Traces each element
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for tracee in self {
+                    tracer.add_shared_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                T::needs_trace()
+            }
+        }
+        impl<K, T, S> Trace for HashMap<K, T, S>
+        where
+            K: Hash + Eq + Clone,
+            T: Trace + Clone,
+            S: BuildHasher,
+        {
+            /// Traces each value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for tracee in self.values() {
+                    tracer.add_shared_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                T::needs_trace()
+            }
+        }
+        impl<K, T, S> Trace for TraceAll<HashMap<K, T, S>>
+        where
+            K: Trace + Hash + Eq + Clone,
+            T: Trace + Clone,
+            S: BuildHasher,
+        {
+            /// Traces each key and value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for (key, tracee) in &self.0 {
+                    tracer.add_shared_target(key);
+                    tracer.add_shared_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                K::needs_trace() || T::needs_trace()
+            }
+        }
+        impl<T, S> Trace for HashSet<T, S>
+        where
+            T: Trace + Hash + Eq + Clone,
+            S: BuildHasher,
+        {
+            /// Traces each value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for tracee in self {
+                    tracer.add_shared_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                T::needs_trace()
+            }
+        }
+        impl<K: Ord + Clone, T: Trace + Clone> Trace for OrdMap<K, T> {
+            /// Traces each value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for tracee in self.values() {
+                    tracer.add_shared_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                T::needs_trace()
+            }
+        }
+        impl<K: Trace + Ord + Clone, T: Trace + Clone> Trace for TraceAll<OrdMap<K, T>> {
+            /// Traces each key and value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for (key, tracee) in &self.0 {
+                    tracer.add_shared_target(key);
+                    tracer.add_shared_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                K::needs_trace() || T::needs_trace()
+            }
+        }
+        impl<T: Trace + Ord + Clone> Trace for OrdSet<T> {
+            /// Traces each value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for tracee in self {
+                    tracer.add_shared_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                T::needs_trace()
+            }
+        }
+    }
+
+    #[cfg(feature = "indexmap")]
+    mod indexmap_impls {
+        use indexmap::{IndexMap, IndexSet};
+        use trace::{Trace, TraceAll, Tracer};
+
+        impl<K, T: Trace, S> Trace for IndexMap<K, T, S> {
+            /// Traces each value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for tracee in self.values() {
+                    tracer.add_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                T::needs_trace()
+            }
+        }
+        impl<K: Trace, T: Trace, S> Trace for TraceAll<IndexMap<K, T, S>> {
+            /// Traces each key and value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for (key, tracee) in &self.0 {
+                    tracer.add_target(key);
+                    tracer.add_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                K::needs_trace() || T::needs_trace()
+            }
+        }
+        impl<T: Trace, S> Trace for IndexSet<T, S> {
+            /// Traces each value
+            fn trace(&self, tracer: &mut Tracer) {
+                if !Self::needs_trace() {
+                    return;
+                }
+                for tracee in self {
+                    tracer.add_target(tracee);
+                }
+            }
+            fn needs_trace() -> bool {
+                T::needs_trace()
+            }
+        }
+    }
+
+    #[cfg(feature = "smallvec")]
+    mod smallvec_impls {
+        use smallvec::{Array, SmallVec};
+        use trace::{Trace, Tracer};
+
+        impl<A: Array> Trace for SmallVec<A>
+        where
+            A::Item: Trace,
+        {
+            /// Traces each element
+            fn trace(&self, tracer: &mut Tracer) {
+                tracer.add_target(&self[..]);
+            }
+            fn needs_trace() -> bool {
+                A::Item::needs_trace()
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -559,6 +1544,48 @@ mod tests {
         tracer.add_target(&tracee);
     }
 
+    #[test]
+    fn trace_large_array() {
+        let mut tracer = Tracer::new();
+        let tracee: [MustTrace; 100] = std::array::from_fn(|_| MustTrace::new());
+        tracer.add_target(&tracee);
+    }
+    #[test]
+    fn trace_tuples() {
+        let mut tracer = Tracer::new();
+        let one = (MustTrace::new(),);
+        let pair = (String::new(), MustTrace::new());
+        let many = (
+            MustTrace::new(),
+            1,
+            MustTrace::new(),
+            'a',
+            MustTrace::new(),
+            (),
+            MustTrace::new(),
+            false,
+            MustTrace::new(),
+            0.0,
+            MustTrace::new(),
+            MustTrace::new(),
+        );
+        tracer.add_target(&one);
+        tracer.add_target(&pair);
+        tracer.add_target(&many);
+    }
+    #[test]
+    fn trace_wrappers() {
+        use std::cmp::Reverse;
+
+        let mut tracer = Tracer::new();
+        tracer.add_target(&(MustTrace::new()..MustTrace::new()));
+        tracer.add_target(&(MustTrace::new()..=MustTrace::new()));
+        tracer.add_target(&(MustTrace::new()..));
+        tracer.add_target(&(..MustTrace::new()));
+        tracer.add_target(&(..=MustTrace::new()));
+        tracer.add_target(&Reverse(MustTrace::new()));
+        tracer.add_target(&Box::pin(MustTrace::new()));
+    }
     #[test]
     fn trace_slice() {
         let mut tracer = Tracer::new();
@@ -567,8 +1594,279 @@ mod tests {
         tracer.add_target(tra_slice);
     }
     #[test]
+    fn trace_result() {
+        let mut tracer = Tracer::new();
+        let ok: Result<MustTrace, MustTrace> = Ok(MustTrace::new());
+        let err: Result<MustTrace, MustTrace> = Err(MustTrace::new());
+        tracer.add_target(&TraceAll(ok));
+        tracer.add_target(&TraceAll(err));
+    }
+    #[test]
+    fn untraced_keys_and_errors() {
+        use std::collections::{BTreeMap, HashMap};
+        use std::error::Error;
+        use std::io;
+        use Collector;
+
+        // Can't implement `Trace`, so must not need to
+        #[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+        struct NonTraceKey(u8);
+
+        struct Holder<'a> {
+            io: Result<Gc<'a, u8>, io::Error>,
+            boxed: Result<Gc<'a, u8>, Box<dyn Error>>,
+            hash_map: HashMap<NonTraceKey, Gc<'a, u8>>,
+            btree_map: BTreeMap<NonTraceKey, Gc<'a, u8>>,
+        }
+        impl<'a> Trace for Holder<'a> {
+            fn trace(&self, tracer: &mut Tracer) {
+                tracer.add_target(&self.io);
+                tracer.add_target(&self.boxed);
+                tracer.add_target(&self.hash_map);
+                tracer.add_target(&self.btree_map);
+            }
+        }
+
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        let value = proxy.alloc(1);
+        let holder = proxy.alloc(Holder {
+            io: Ok(value.clone()),
+            boxed: Err("no value".into()),
+            hash_map: vec![(NonTraceKey(0), value.clone())].into_iter().collect(),
+            btree_map: vec![(NonTraceKey(0), value.clone())].into_iter().collect(),
+        });
+        drop(value);
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 2);
+        drop(holder);
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 0);
+    }
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn trace_all_frees_cycles_through_keys_and_errors() {
+        use std::cell::RefCell;
+        use std::collections::HashMap;
+        use std::hash::{Hash, Hasher};
+        use Collector;
+
+        // Compared and hashed by the id alone
+        struct Node<'a> {
+            id: u8,
+            keys: RefCell<TraceAll<HashMap<Gc<'a, Node<'a>>, u8>>>,
+            err: RefCell<TraceAll<Result<u8, Gc<'a, Node<'a>>>>>,
+        }
+        impl<'a> Node<'a> {
+            fn new(id: u8) -> Node<'a> {
+                Node {
+                    id,
+                    keys: RefCell::new(TraceAll(HashMap::new())),
+                    err: RefCell::new(TraceAll(Ok(0))),
+                }
+            }
+        }
+        impl<'a> PartialEq for Node<'a> {
+            fn eq(&self, other: &Node<'a>) -> bool {
+                self.id == other.id
+            }
+        }
+        impl<'a> Eq for Node<'a> {}
+        impl<'a> Hash for Node<'a> {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                self.id.hash(state);
+            }
+        }
+        impl<'a> Trace for Node<'a> {
+            fn trace(&self, tracer: &mut Tracer) {
+                tracer.add_target(&self.keys);
+                tracer.add_target(&self.err);
+            }
+        }
+
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        let a = proxy.alloc(Node::new(0));
+        let b = proxy.alloc(Node::new(1));
+        // `a` only reaches `b` through a map key, and `b` only reaches `a`
+        // through an error
+        a.keys.borrow_mut().insert(b.clone(), 0);
+        *b.err.borrow_mut() = TraceAll(Err(a.clone()));
+        drop(a);
+        drop(b);
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 0);
+    }
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn trace_map_keys() {
+        use std::collections::{BTreeMap, HashMap};
+        use std::hash::{Hash, Hasher};
+
+        // Ordered and hashed by the id alone
+        struct Key(u8, MustTrace);
+        impl PartialEq for Key {
+            fn eq(&self, other: &Key) -> bool {
+                self.0 == other.0
+            }
+        }
+        impl Eq for Key {}
+        impl PartialOrd for Key {
+            fn partial_cmp(&self, other: &Key) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+        impl Ord for Key {
+            fn cmp(&self, other: &Key) -> std::cmp::Ordering {
+                self.0.cmp(&other.0)
+            }
+        }
+        impl Hash for Key {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                self.0.hash(state);
+            }
+        }
+        impl Trace for Key {
+            fn trace(&self, tracer: &mut Tracer) {
+                tracer.add_target(&self.1);
+            }
+        }
+
+        let mut tracer = Tracer::new();
+        let mut hash_map = HashMap::new();
+        let mut btree_map = BTreeMap::new();
+        for id in 0..5 {
+            hash_map.insert(Key(id, MustTrace::new()), MustTrace::new());
+            btree_map.insert(Key(id, MustTrace::new()), MustTrace::new());
+        }
+        tracer.add_target(&TraceAll(hash_map));
+        tracer.add_target(&TraceAll(btree_map));
+    }
+    #[test]
+    fn trace_shared_once() {
+        use std::rc::Rc;
+        use std::sync::Arc;
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct CountTrace(AtomicUsize);
+        impl Trace for CountTrace {
+            fn trace(&self, _: &mut Tracer) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let mut tracer = Tracer::new();
+        let rc = Rc::new(CountTrace(AtomicUsize::new(0)));
+        let arc = Arc::new(CountTrace(AtomicUsize::new(0)));
+        tracer.add_target(&vec![rc.clone(), rc.clone(), rc.clone()]);
+        tracer.add_target(&vec![arc.clone(), arc.clone()]);
+        assert_eq!(rc.0.load(Ordering::SeqCst), 1);
+        assert_eq!(arc.0.load(Ordering::SeqCst), 1);
+    }
+    #[test]
+    fn trace_cell() {
+        let mut tracer = Tracer::new();
+        let tracee = Cell::new(Some(MustTrace::new()));
+        tracer.add_target(&tracee);
+        assert!(tracee.take().is_some());
+    }
+    #[test]
+    fn trace_locks() {
+        use std::sync::{Mutex, RwLock};
+
+        let mut tracer = Tracer::new();
+        let mutex = Mutex::new(MustTrace::new());
+        let rw_lock = RwLock::new(MustTrace::new());
+        tracer.add_target(&mutex);
+        tracer.add_target(&rw_lock);
+        assert!(!tracer.is_postponed());
+
+        let _lock = mutex.lock().unwrap();
+        tracer.add_target(&mutex);
+        assert!(tracer.is_postponed());
+    }
+    #[test]
+    fn trace_rc_cycle() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        struct Node(RefCell<Option<Rc<Node>>>);
+        impl Trace for Node {
+            fn trace(&self, tracer: &mut Tracer) {
+                tracer.add_target(&self.0);
+            }
+        }
+
+        let a = Rc::new(Node(RefCell::new(None)));
+        let b = Rc::new(Node(RefCell::new(Some(a.clone()))));
+        *a.0.borrow_mut() = Some(b.clone());
+
+        let mut tracer = Tracer::new();
+        tracer.add_target(&a);
+
+        *a.0.borrow_mut() = None;
+    }
+    #[test]
+    fn trace_trait_objects() {
+        use std::rc::Rc;
+
+        trait Behaviour: Trace {}
+        impl Behaviour for MustTrace {}
+
+        let mut tracer = Tracer::new();
+        let boxed: Box<dyn Behaviour> = Box::new(MustTrace::new());
+        let shared: Rc<dyn Behaviour> = Rc::new(MustTrace::new());
+        tracer.add_target(&boxed);
+        tracer.add_target(&shared);
+        assert!(Box::<dyn Behaviour>::needs_trace());
+    }
+    #[test]
+    fn needs_trace_combines() {
+        use std::collections::HashMap;
+        use Gc;
+
+        assert!(!u8::needs_trace());
+        assert!(!Vec::<u8>::needs_trace());
+        assert!(!HashMap::<String, i64>::needs_trace());
+        assert!(!<(String, [u32; 4], Option<char>)>::needs_trace());
+        assert!(!std::borrow::Cow::<str>::needs_trace());
+        assert!(!std::borrow::Cow::<[u8]>::needs_trace());
+        assert!(Gc::<u8>::needs_trace());
+        assert!(Vec::<Gc<u8>>::needs_trace());
+        assert!(TraceAll::<HashMap<Gc<u8>, ()>>::needs_trace());
+        assert!(<(String, Option<Gc<u8>>)>::needs_trace());
+        assert!(MustTrace::needs_trace());
+    }
+    #[test]
+    fn leaf_containers_skip_elements() {
+        struct NeverTrace;
+        impl Trace for NeverTrace {
+            fn trace(&self, _: &mut Tracer) {
+                panic!("traced a leaf");
+            }
+            fn needs_trace() -> bool {
+                false
+            }
+        }
+
+        let mut tracer = Tracer::new();
+        tracer.add_target(&vec![NeverTrace, NeverTrace]);
+        tracer.add_target(&[NeverTrace, NeverTrace][..]);
+    }
+    #[test]
     fn trace_noops() {
-        use std::mem::transmute;
+        // Functions that give us pointers of the correct type
+        fn dummy_fn0() {}
+        fn dummy_fn1() -> i8 {
+            0
+        }
+        fn dummy_fn2(_: i8) -> i8 {
+            0
+        }
+        fn dummy_fn3(_: i8, _: u8, _: isize, _: usize) -> i8 {
+            0
+        }
 
         let mut tracer = Tracer::new();
 
@@ -600,12 +1898,16 @@ mod tests {
             char, 'a'
             Box<str>, "Hello".to_string().into_boxed_str()
 
-            // Unsafe used to create dummy pointers of the correct type
-            // The pointers are never dereferenced
-            fn(), unsafe { transmute(0usize) }
-            fn() -> i8,  unsafe { transmute(0usize) }
-            fn(i8) -> i8, unsafe { transmute(0usize) }
-            fn(i8, u8, isize, usize) -> i8, unsafe { transmute(0usize) }
+            fn(), dummy_fn0
+            fn() -> i8, dummy_fn1
+            fn(i8) -> i8, dummy_fn2
+            fn(i8, u8, isize, usize) -> i8, dummy_fn3
+
+            std::marker::PhantomData<MustTrace>, std::marker::PhantomData
+            std::num::NonZeroU32, std::num::NonZeroU32::new(1).unwrap()
+            std::num::NonZeroIsize, std::num::NonZeroIsize::new(-1).unwrap()
+            std::ops::RangeFull, std::ops::RangeFull
+            std::ops::Range<usize>, std::ops::Range { start: 0, end: 5 }
         );
         let t: &str = "Hello";
         tracer.add_target(&t);
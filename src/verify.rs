@@ -0,0 +1,267 @@
+//! Consistency checks for the gc heap.
+//!
+//! Only available with the `debug-checks` feature.
+//!
+//! [`Proxy::verify_heap`] walks every tracked object and reports the
+//! [`HeapViolation`]s it finds. A healthy heap has none. Violations usually
+//! point at a bug in the collector itself or in `unsafe` code that touches the
+//! gc heap, so it is a good idea to verify the heap after every collection in
+//! tests.
+//!
+//! # Examples
+//!
+//! ```
+//! use ters_gc::Collector;
+//!
+//! let mut col = Collector::new();
+//! let mut proxy = col.proxy();
+//!
+//! let _num = proxy.alloc(1);
+//! proxy.run();
+//!
+//! assert!(proxy.verify_heap().is_empty());
+//! ```
+//!
+//! [`Proxy::verify_heap`]: ../struct.Proxy.html#method.verify_heap
+//! [`HeapViolation`]: enum.HeapViolation.html
+
+use allocator::Allocator;
+use ptr::Handle;
+use std::collections::HashMap;
+use std::ptr::NonNull;
+use trace::Tracer;
+
+/// A broken heap invariant found by [`Proxy::verify_heap`].
+///
+/// Objects are identified by the name of their type and the address of their
+/// allocation.
+///
+/// [`Proxy::verify_heap`]: ../struct.Proxy.html#method.verify_heap
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeapViolation {
+    /// A tracked object's [`Trace`] impl reported a pointer to an object that
+    /// the collector doesn't track.
+    ///
+    /// [`Trace`]: ../trace/trait.Trace.html
+    UntrackedChild {
+        /// Type of the object holding the pointer
+        parent_type: &'static str,
+        /// Address of the object holding the pointer
+        parent: usize,
+        /// The address that was pointed to
+        child: usize,
+    },
+    /// An object has fewer strong references than there are pointers to it
+    /// inside the gc heap.
+    RefCountTooLow {
+        /// Type of the object
+        type_name: &'static str,
+        /// Address of the object
+        address: usize,
+        /// Strong reference count stored in the object
+        strong: usize,
+        /// Number of pointers to the object found by tracing the heap
+        internal: usize,
+    },
+    /// An object is still marked from a previous collection.
+    StaleMark {
+        /// Type of the object
+        type_name: &'static str,
+        /// Address of the object
+        address: usize,
+    },
+    /// An object's weak reference count has underflowed.
+    NegativeWeakCount {
+        /// Type of the object
+        type_name: &'static str,
+        /// Address of the object
+        address: usize,
+        /// The weak count, interpreted as a signed number
+        weak: isize,
+    },
+}
+
+pub(crate) fn verify(allocator: &Allocator) -> Vec<HeapViolation> {
+    let mut violations = vec![];
+    let mut internal_refs: HashMap<NonNull<Handle>, usize> = HashMap::new();
+    let mut tracer = Tracer::new();
+
+    for info in allocator.items.iter() {
+        let address = info.ptr.as_ptr() as usize;
+
+        if info.is_marked_reachable() || info.inter_marks() != 0 {
+            violations.push(HeapViolation::StaleMark {
+                type_name: info.type_name,
+                address,
+            });
+        }
+
+        let weak = info.weak_count() as isize;
+        if weak < 0 {
+            violations.push(HeapViolation::NegativeWeakCount {
+                type_name: info.type_name,
+                address,
+                weak,
+            });
+        }
+
+        for child in info.children(&mut tracer) {
+            if allocator.info_for_handle(child).is_some() {
+                *internal_refs.entry(child).or_insert(0) += 1;
+            } else {
+                // Unsafe is fine since the `Gc` that was traced keeps its
+                // handle alive, and only live objects are traced
+                let child = unsafe { child.as_ref() }.box_ptr();
+                violations.push(HeapViolation::UntrackedChild {
+                    parent_type: info.type_name,
+                    parent: address,
+                    child: child.map_or(0, |child| child.as_ptr() as usize),
+                });
+            }
+        }
+    }
+
+    for (handle, internal) in internal_refs {
+        let info = allocator
+            .info_for_handle(handle)
+            .expect("only tracked children are counted");
+        let strong = info.ref_count();
+        if strong < internal {
+            violations.push(HeapViolation::RefCountTooLow {
+                type_name: info.type_name,
+                address: info.ptr.as_ptr() as usize,
+                strong,
+                internal,
+            });
+        }
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use trace::{Trace, Tracer};
+    use {Collector, Gc};
+
+    struct Node<'a> {
+        next: RefCell<Option<Gc<'a, Node<'a>>>>,
+    }
+    impl<'a> Trace for Node<'a> {
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer.add_target(&self.next);
+        }
+    }
+
+    #[test]
+    fn healthy_heap_has_no_violations() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        let head = proxy.alloc(Node {
+            next: RefCell::new(None),
+        });
+        let tail = proxy.alloc(Node {
+            next: RefCell::new(Some(head.clone())),
+        });
+        *head.next.borrow_mut() = Some(tail.clone());
+        let _weak = Gc::downgrade(&tail);
+
+        assert!(proxy.verify_heap().is_empty());
+        proxy.run();
+        assert!(proxy.verify_heap().is_empty());
+        drop(head);
+        drop(tail);
+        proxy.run();
+        assert!(proxy.verify_heap().is_empty());
+    }
+
+    #[test]
+    fn detects_untracked_child() {
+        let mut other_col = Collector::new();
+        let mut other_proxy = other_col.proxy();
+        let foreign = other_proxy.alloc(Node {
+            next: RefCell::new(None),
+        });
+
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        let node = proxy.alloc(Node {
+            next: RefCell::new(Some(foreign.clone())),
+        });
+
+        let violations = proxy.verify_heap();
+        assert_eq!(violations.len(), 1);
+        match violations[0] {
+            HeapViolation::UntrackedChild { parent, child, .. } => {
+                assert_eq!(parent, node.nonnull_box_ptr().as_ptr() as usize);
+                assert_eq!(child, foreign.nonnull_box_ptr().as_ptr() as usize);
+            }
+            other => panic!("unexpected violation {:?}", other),
+        }
+        node.next.borrow_mut().take();
+    }
+
+    #[test]
+    fn detects_low_ref_count() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        let leaf = proxy.alloc(Node {
+            next: RefCell::new(None),
+        });
+        let _parent = proxy.alloc(Node {
+            next: RefCell::new(Some(leaf.clone())),
+        });
+        leaf.handle().decr_ref();
+        leaf.handle().decr_ref();
+
+        let violations = proxy.verify_heap();
+        assert_eq!(violations.len(), 1);
+        match violations[0] {
+            HeapViolation::RefCountTooLow {
+                strong, internal, ..
+            } => {
+                assert_eq!(strong, 0);
+                assert_eq!(internal, 1);
+            }
+            other => panic!("unexpected violation {:?}", other),
+        }
+        leaf.handle().incr_ref();
+        leaf.handle().incr_ref();
+    }
+
+    #[test]
+    fn detects_stale_marks() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        let _num = proxy.alloc(1);
+        for info in proxy.collector.allocator.items.iter() {
+            info.mark_reachable();
+        }
+
+        let violations = proxy.verify_heap();
+        assert_eq!(violations.len(), 1);
+        match violations[0] {
+            HeapViolation::StaleMark { type_name, .. } => assert_eq!(type_name, "i32"),
+            other => panic!("unexpected violation {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detects_negative_weak_count() {
+        let mut col = Collector::new();
+        let mut proxy = col.proxy();
+        let num = proxy.alloc(1);
+        // What `decr_weak` would leave behind in a release build
+        num.handle().set_weak_count(usize::MAX);
+
+        let violations = proxy.verify_heap();
+        assert_eq!(violations.len(), 1);
+        match violations[0] {
+            HeapViolation::NegativeWeakCount { weak, .. } => assert_eq!(weak, -1),
+            other => panic!("unexpected violation {:?}", other),
+        }
+        num.handle().set_weak_count(0);
+    }
+}
@@ -1,12 +1,10 @@
-extern crate proc_macro;
+extern crate proc_macro2;
 #[macro_use]
 extern crate synstructure;
 #[macro_use]
 extern crate quote;
 
-use proc_macro::TokenStream;
-
-fn trace_derive(mut s: synstructure::Structure) -> TokenStream {
+fn trace_derive(mut s: synstructure::Structure) -> proc_macro2::TokenStream {
     // No way to check whether a field implements a trait, so have an attribute
     // to ignore fields that don't implement Trace.
     // https://github.com/dtolnay/syn/issues/77
@@ -15,10 +13,7 @@ fn trace_derive(mut s: synstructure::Structure) -> TokenStream {
             .ast()
             .attrs
             .iter()
-            .any(|attr| match attr.interpret_meta() {
-                Some(meta) => meta.name() == "ignore_trace",
-                None => false,
-            })
+            .any(|attr| attr.path().is_ident("ignore_trace"))
     });
 
     let body = s.each(|bind_info| {
@@ -27,6 +22,34 @@ fn trace_derive(mut s: synstructure::Structure) -> TokenStream {
         }
     });
 
+    // Only needs tracing if one of the traced fields does. Field types can
+    // name the type itself, so the recursion is cut off by the guard.
+    let field_types = s
+        .variants()
+        .iter()
+        .flat_map(|variant| variant.bindings())
+        .map(|bind_info| &bind_info.ast().ty);
+    let fields = quote! {
+        || false #(|| <#field_types as ters_gc::trace::Trace>::needs_trace())*
+    };
+    // A static in a generic impl is shared by every instantiation, so only
+    // types without type or const parameters can cache the answer in one.
+    // Lifetimes don't change the answer.
+    let generics = &s.ast().generics;
+    let needs_trace = if generics.type_params().next().is_none()
+        && generics.const_params().next().is_none()
+    {
+        quote! {
+            static NEEDS_TRACE: ters_gc::trace::DerivedNeedsTrace =
+                ters_gc::trace::DerivedNeedsTrace::new();
+            NEEDS_TRACE.get::<Self, _>(#fields)
+        }
+    } else {
+        quote! {
+            ters_gc::trace::derived_needs_trace::<Self, _>(#fields)
+        }
+    };
+
     s.gen_impl(quote! {
         extern crate ters_gc;
         gen impl ters_gc::trace::Trace for @Self {
@@ -35,8 +58,40 @@ fn trace_derive(mut s: synstructure::Structure) -> TokenStream {
                     #body
                 }
             }
+            fn needs_trace() -> bool {
+                #needs_trace
+            }
+        }
+    })
+}
+
+fn heap_size_derive(mut s: synstructure::Structure) -> proc_macro2::TokenStream {
+    // Same as `ignore_trace`, for fields that don't implement HeapSize
+    s.filter(|bind_info| {
+        !bind_info
+            .ast()
+            .attrs
+            .iter()
+            .any(|attr| attr.path().is_ident("ignore_heap_size"))
+    });
+
+    let body = s.fold(quote!(0), |acc, bind_info| {
+        quote! {
+            #acc + ters_gc::heap_size::HeapSize::heap_size(#bind_info)
+        }
+    });
+
+    s.gen_impl(quote! {
+        extern crate ters_gc;
+        gen impl ters_gc::heap_size::HeapSize for @Self {
+            fn heap_size(&self) -> usize {
+                match *self {
+                    #body
+                }
+            }
         }
-    }).into()
+    })
 }
 
 decl_derive!([Trace, attributes(ignore_trace)] => trace_derive);
+decl_derive!([HeapSize, attributes(ignore_heap_size)] => heap_size_derive);
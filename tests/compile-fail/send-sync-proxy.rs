@@ -6,9 +6,11 @@ use std::thread;
 fn proxy_not_send() {
     let mut col = Collector::new();
     let proxy = col.proxy();
-    thread::spawn(move || { //~ ERROR Send` is not satisfied
-        //~^ ERROR Send` is not satisfied
-        //~| cannot be sent between threads safely
+    thread::spawn(move || { //~ ERROR cannot be sent between threads safely
+                            //~^ ERROR cannot be sent between threads safely
+                            //~^^ ERROR cannot be sent between threads safely
+                            //~^^^ ERROR cannot be sent between threads safely
+                            //~^^^^ ERROR cannot be sent between threads safely
         drop(proxy);
     });
 }
@@ -21,6 +23,9 @@ fn proxy_not_sync() {
                                 //~^ ERROR cannot be shared between threads safely
                                 //~^^ ERROR cannot be shared between threads safely
                                 //~^^^ ERROR cannot be shared between threads safely
+                                //~^^^^ ERROR cannot be shared between threads safely
+                                //~^^^^^ ERROR cannot be shared between threads safely
+                                //~^^^^^^ ERROR cannot be shared between threads safely
                                 //~| Sync` is not implemented
         drop(proxy_ref);
     });
@@ -7,9 +7,7 @@ fn gc_not_send() {
     let mut col = Collector::new();
     let mut proxy = col.proxy();
     let num = proxy.alloc(5);
-    thread::spawn(move || { //~ ERROR Send` is not satisfied
-                            //~^ ERROR Send` is not satisfied
-                            //~| cannot be sent between threads safely
+    thread::spawn(move || { //~ ERROR cannot be sent between threads safely
         drop(num);
     });
 }
@@ -19,9 +17,7 @@ fn weak_not_send() {
     let mut proxy = col.proxy();
     let num = proxy.alloc(5);
     let num = Gc::downgrade(&num);
-    thread::spawn(move || { //~ ERROR Send` is not satisfied
-                            //~^ ERROR Send` is not satisfied
-                            //~| cannot be sent between threads safely
+    thread::spawn(move || { //~ ERROR cannot be sent between threads safely
         drop(num);
     });
 }
@@ -33,7 +29,6 @@ fn gc_not_sync() {
     let num = proxy.alloc(5);
     let num_ref = &num;
     thread::spawn(move || { //~ ERROR cannot be shared between threads safely
-                            //~^ ERROR cannot be shared between threads safely
                             //~| Sync` is not implemented for
         drop(num_ref);
     });
@@ -46,7 +41,6 @@ fn weak_not_sync() {
     let num = Gc::downgrade(&num);
     let num_ref = &num;
     thread::spawn(move || { //~ ERROR cannot be shared between threads safely
-                            //~^ ERROR cannot be shared between threads safely
                             //~| Sync` is not implemented for
         drop(num_ref);
     });
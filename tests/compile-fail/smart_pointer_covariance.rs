@@ -17,13 +17,11 @@ fn weak_covariant() {
 
 fn gc_covariant_cannot_extend_lifetime() {
     fn expect(_: Gc<&'static i32>) { unimplemented!() }
-    fn provide<'a>(m: Gc<&'a i32>) { expect(m); } //~ ERROR mismatched types
-                                                  //~| lifetime mismatch
+    fn provide<'a>(m: Gc<&'a i32>) { expect(m); } //~ ERROR borrowed data escapes outside of function
 }
 fn weak_covariant_cannot_extend_lifetime() {
     fn expect(_: Weak<&'static i32>) { unimplemented!() }
-    fn provide<'a>(m: Weak<&'a i32>) { expect(m); } //~ ERROR mismatched types
-                                                    //~| lifetime mismatch
+    fn provide<'a>(m: Weak<&'a i32>) { expect(m); } //~ ERROR borrowed data escapes outside of function
 }
 
 fn _covariant_with_gc_alloc_lifetime() {
@@ -47,13 +45,11 @@ fn _covariant_with_weak_alloc_lifetime() {
 
 fn gc_covariant_cannot_extend_alloc_lifetime() {
     fn expect(_: Gc<'static, ()>) { unimplemented!() }
-    fn provide<'a>(m: Gc<'a, ()>) { expect(m); } //~ ERROR mismatched types
-                                                  //~| lifetime mismatch
+    fn provide<'a>(m: Gc<'a, ()>) { expect(m); } //~ ERROR borrowed data escapes outside of function
 }
 fn weak_covariant_cannot_extend_alloc_lifetime() {
     fn expect(_: Weak<'static, ()>) { unimplemented!() }
-    fn provide<'a>(m: Weak<'a, ()>) { expect(m); } //~ ERROR mismatched types
-                                                  //~| lifetime mismatch
+    fn provide<'a>(m: Weak<'a, ()>) { expect(m); } //~ ERROR borrowed data escapes outside of function
 }
 
 fn main() {}
@@ -69,14 +65,12 @@ fn weak_contravariant() {
 
 fn gc_contravariant_cannot_extend_lifetime() {
     fn expect<'a, 'g>(gc: Gc<'g, &'a i32>) -> Gc<'g, &'static i32> {
-        gc //~ ERROR mismatched types
-           //~| lifetime mismatch
+        gc //~ ERROR lifetime may not live long enough
     } 
 }
 fn weak_contravariant_cannot_extend_lifetime() {
     fn expect<'a, 'w>(wk: Weak<'w, &'a i32>) -> Weak<'w, &'static i32> {
-        wk //~ ERROR mismatched types
-           //~| lifetime mismatch
+        wk //~ ERROR lifetime may not live long enough
     } 
 }
 
@@ -92,13 +86,11 @@ fn weak_contravariant_alloc_lifetime() {
 
 fn gc_contravariant_cannot_extend_alloc_lifetime() {
     fn expect<'g>(m: Gc<'g, ()>) -> Gc<'static, ()> {
-        m //~ ERROR mismatched types
-          //~| lifetime mismatch
+        m //~ ERROR lifetime may not live long enough
     }
 }
 fn weak_contravariant_cannot_extend_alloc_lifetime() {
     fn expect<'g>(m: Weak<'g, ()>) -> Weak<'static, ()> {
-        m //~ ERROR mismatched types
-          //~| lifetime mismatch
+        m //~ ERROR lifetime may not live long enough
     }
 }
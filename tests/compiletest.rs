@@ -1,22 +1,44 @@
 extern crate compiletest_rs as compiletest; // https://github.com/laumann/compiletest-rs
 
+use std::fs;
 use std::path::PathBuf;
 
-fn run_mode(mode: &'static str) {
-    let mut config = compiletest::Config::default();
-
-    // config.verbose = true; // Uncomment when compiletest misbehaves
+/// The most recently built `ters_gc` rlib.
+///
+/// Stale rlibs from builds with other features or dependencies pile up in
+/// `target/debug/deps`, which makes `extern crate ters_gc` ambiguous (E0464).
+fn newest_rlib() -> Option<PathBuf> {
+    fs::read_dir("target/debug/deps")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with("libters_gc-") && name.ends_with(".rlib")
+        })
+        .max_by_key(|entry| entry.metadata().and_then(|meta| meta.modified()).ok())
+        .map(|entry| entry.path())
+}
 
-    config.mode = mode.parse().expect("Invalid mode");
+fn run_mode(mode: &'static str) {
+    let mut config = compiletest::Config {
+        // verbose: true, // Uncomment when compiletest misbehaves
+        mode: mode.parse().expect("Invalid mode"),
+        src_base: PathBuf::from(format!("tests/{}", mode)),
+        ..Default::default()
+    };
 
-    config.src_base = PathBuf::from(format!("tests/{}", mode));
     config.link_deps(); // Populate config.target_rustcflags with dependencies on the path
                         // Correctly link deps. Above line errors with "multiple input filenames provided"
 
     // config.target_rustcflags = Some("-L target/debug/ -L target/debug/deps/".to_owned());
     // The above line has problems when the crate has been compiled
     // by both nightly and stable.
-    config.target_rustcflags = Some("-L target/debug/deps/".to_owned());
+    let mut flags = "-L target/debug/deps".to_owned();
+    if let Some(rlib) = newest_rlib() {
+        flags.push_str(&format!(" --extern ters_gc={}", rlib.display()));
+    }
+    config.target_rustcflags = Some(flags);
     config.clean_rmeta(); // If your tests import the parent crate, this helps with E0464
 
     compiletest::run_tests(&config);
@@ -2,6 +2,8 @@ extern crate ters_gc;
 #[macro_use]
 extern crate ters_gc_derive;
 
+use std::rc::Rc;
+use ters_gc::heap_size::*;
 use ters_gc::trace::*;
 use ters_gc::*;
 
@@ -19,6 +21,145 @@ struct GcWithNoTrace<'a>(Gc<'a, i32>, #[ignore_trace] NoTrace);
 #[derive(Trace)]
 struct GcEmpty;
 
+#[derive(HeapSize)]
+struct WithHeap<'a>(String, Gc<'a, i32>, #[ignore_heap_size] NoTrace);
+
+#[derive(HeapSize)]
+enum SizedEnum {
+    Empty,
+    Named { name: String, ids: Vec<u32> },
+}
+
+#[derive(Trace)]
+struct Leaf {
+    name: String,
+    ids: Vec<u32>,
+    #[ignore_trace]
+    _ignored: NoTrace,
+}
+
+#[derive(Trace)]
+struct Tree {
+    children: Vec<Tree>,
+    n: u32,
+}
+
+#[derive(Trace)]
+struct GcTree<'a> {
+    children: Vec<GcTree<'a>>,
+    num: Option<Gc<'a, i32>>,
+}
+
+#[derive(Trace)]
+struct Outer<'a>(Option<Box<Inner<'a>>>, Gc<'a, i32>);
+
+#[derive(Trace)]
+struct Inner<'a>(Vec<Outer<'a>>);
+
+#[derive(Trace)]
+struct Labelled<T: Trace>(Vec<T>, String);
+
+trait Behaviour: Trace {}
+
+#[derive(Trace)]
+struct Follow<'a>(Gc<'a, Actor<'a>>);
+impl<'a> Behaviour for Follow<'a> {}
+
+#[derive(Trace)]
+struct Actor<'a> {
+    behaviour: GcCell<Option<Box<dyn Behaviour + 'a>>>,
+    shared: GcCell<Option<Rc<dyn Behaviour + 'a>>>,
+}
+
+#[test]
+fn derive_traces_trait_objects() {
+    let mut col = Collector::new();
+    let mut proxy = col.proxy();
+
+    {
+        let actor = || Actor {
+            behaviour: GcCell::new(None),
+            shared: GcCell::new(None),
+        };
+        let a = proxy.alloc(actor());
+        let b = proxy.alloc(actor());
+        *a.behaviour.borrow_mut() = Some(Box::new(Follow(b.clone())));
+        *b.shared.borrow_mut() = Some(Rc::new(Follow(a.clone())));
+
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 2);
+    }
+    proxy.run();
+    assert_eq!(proxy.num_tracked(), 0);
+}
+
+#[test]
+fn derive_needs_trace_combines_fields() {
+    assert!(!Leaf::needs_trace());
+    assert!(!GcEmpty::needs_trace());
+    assert!(GcI32::needs_trace());
+    assert!(GcWithNoTrace::needs_trace());
+}
+
+#[test]
+fn derive_needs_trace_handles_recursive_types() {
+    assert!(!Tree::needs_trace());
+    assert!(GcTree::needs_trace());
+    assert!(Outer::needs_trace());
+    assert!(Inner::needs_trace());
+
+    let mut col = Collector::new();
+    let mut proxy = col.proxy();
+
+    let num = proxy.alloc(5);
+    let _tree = proxy.alloc(Tree {
+        children: vec![Tree {
+            children: vec![],
+            n: 1,
+        }],
+        n: 0,
+    });
+    let _gc_tree = proxy.alloc(GcTree {
+        children: vec![GcTree {
+            children: vec![],
+            num: Some(num.clone()),
+        }],
+        num: None,
+    });
+    drop(num);
+    proxy.run();
+    assert_eq!(proxy.num_tracked(), 3);
+}
+
+#[test]
+fn derive_needs_trace_is_worked_out_per_type() {
+    // Asked in the other order than above, after `Outer` was cut off while
+    // working out `Inner`
+    assert!(Inner::needs_trace());
+    assert!(Outer::needs_trace());
+    assert!(Inner::needs_trace());
+
+    assert!(!Labelled::<u8>::needs_trace());
+    assert!(Labelled::<Gc<u8>>::needs_trace());
+    assert!(!Labelled::<u8>::needs_trace());
+}
+
+#[test]
+fn derive_heap_size_sums_fields() {
+    let mut col = Collector::new();
+    let mut proxy = col.proxy();
+
+    let sized = WithHeap(String::with_capacity(3), proxy.alloc(1), NoTrace);
+    assert_eq!(sized.heap_size(), 3);
+
+    assert_eq!(SizedEnum::Empty.heap_size(), 0);
+    let named = SizedEnum::Named {
+        name: String::with_capacity(2),
+        ids: Vec::with_capacity(5),
+    };
+    assert_eq!(named.heap_size(), 2 + 5 * 4);
+}
+
 #[test]
 fn derive_trace_compiles() {
     let mut col = Collector::new();
@@ -44,10 +44,12 @@ impl<'a> Graph<'a> {
         self.nodes.iter().find(|node| node.name == name).cloned()
     }
 
+    // `Node`s hash by name, so the `RefCell` in them doesn't affect the key
+    #[allow(clippy::mutable_key_type)]
     fn path_for(&self, src: GcNode<'a>, dest: GcNode<'a>) -> Option<Vec<GcNode<'a>>> {
         // Want lower distance -> higher priority
         fn dist_to_priority(distance: u64) -> u64 {
-            std::u64::MAX - distance
+            u64::MAX - distance
         }
 
         // This __will__ store `Gc`s in the heap where the collector can't
@@ -61,7 +63,7 @@ impl<'a> Graph<'a> {
             .nodes
             .iter()
             .cloned()
-            .map(|node| (node, std::u64::MAX))
+            .map(|node| (node, u64::MAX))
             .collect();
         *distances.get_mut(&src).unwrap() = 0;
         let mut prev_in_path: HashMap<GcNode<'a>, GcNode<'a>> = HashMap::new();
@@ -96,12 +98,8 @@ impl<'a> Graph<'a> {
 
         let mut path = Vec::new();
         path.push(dest);
-        loop {
-            if let Some(node) = prev_in_path.get(path.last().unwrap()) {
-                path.push(node.clone());
-            } else {
-                break;
-            }
+        while let Some(node) = prev_in_path.get(path.last().unwrap()) {
+            path.push(node.clone());
         }
 
         path.reverse();
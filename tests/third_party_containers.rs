@@ -0,0 +1,229 @@
+// Cycles through containers from other crates, enabled by the feature of the
+// same name
+#![allow(dead_code)]
+
+#[cfg(feature = "arrayvec")]
+extern crate arrayvec;
+#[cfg(feature = "hashbrown")]
+extern crate hashbrown;
+#[cfg(feature = "im")]
+extern crate im;
+#[cfg(feature = "indexmap")]
+extern crate indexmap;
+#[cfg(feature = "smallvec")]
+extern crate smallvec;
+extern crate ters_gc;
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+#[cfg(any(feature = "hashbrown", feature = "im", feature = "indexmap"))]
+use ters_gc::trace::TraceAll;
+use ters_gc::trace::{Trace, Tracer};
+use ters_gc::{Collector, Gc, GcCell};
+
+type Edge<'a> = Gc<'a, Node<'a>>;
+type Wrap = for<'a> fn(Edge<'a>) -> Box<dyn Trace + 'a>;
+
+struct Node<'a> {
+    edges: GcCell<Option<Box<dyn Trace + 'a>>>,
+}
+
+impl<'a> Trace for Node<'a> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.add_target(&self.edges);
+    }
+}
+
+/// A set element that is compared by id
+#[derive(Clone)]
+struct Key<'a>(u32, Edge<'a>);
+
+impl<'a> Trace for Key<'a> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.add_target(&self.1);
+    }
+}
+impl<'a> PartialEq for Key<'a> {
+    fn eq(&self, other: &Key<'a>) -> bool {
+        self.0 == other.0
+    }
+}
+impl<'a> Eq for Key<'a> {}
+impl<'a> PartialOrd for Key<'a> {
+    fn partial_cmp(&self, other: &Key<'a>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<'a> Ord for Key<'a> {
+    fn cmp(&self, other: &Key<'a>) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+impl<'a> Hash for Key<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+/// Links two objects to each other through the container `wrap` makes, and
+/// checks that the cycle is collected once nothing else points to it
+fn collects_cycle(wrap: Wrap) {
+    let mut col = Collector::new();
+    let mut proxy = col.proxy();
+
+    {
+        let a = proxy.alloc(Node {
+            edges: GcCell::new(None),
+        });
+        let b = proxy.alloc(Node {
+            edges: GcCell::new(None),
+        });
+        *a.edges.borrow_mut() = Some(wrap(b.clone()));
+        *b.edges.borrow_mut() = Some(wrap(a.clone()));
+
+        proxy.run();
+        assert_eq!(proxy.num_tracked(), 2);
+    }
+    proxy.run();
+    assert_eq!(proxy.num_tracked(), 0);
+}
+
+#[cfg(feature = "arrayvec")]
+#[test]
+fn arrayvec_cycles_are_collected() {
+    fn array_vec(edge: Edge) -> Box<dyn Trace + '_> {
+        let mut vec = arrayvec::ArrayVec::<_, 4>::new();
+        vec.push(edge);
+        Box::new(vec)
+    }
+    collects_cycle(array_vec);
+}
+
+#[cfg(feature = "hashbrown")]
+#[test]
+fn hashbrown_cycles_are_collected() {
+    fn hash_map(edge: Edge) -> Box<dyn Trace + '_> {
+        let mut map = hashbrown::HashMap::new();
+        map.insert(0u32, edge);
+        Box::new(map)
+    }
+    fn hash_map_keys(edge: Edge) -> Box<dyn Trace + '_> {
+        let mut map = hashbrown::HashMap::new();
+        map.insert(Key(0, edge), 0u32);
+        Box::new(TraceAll(map))
+    }
+    fn hash_set(edge: Edge) -> Box<dyn Trace + '_> {
+        let mut set = hashbrown::HashSet::new();
+        set.insert(Key(0, edge));
+        Box::new(set)
+    }
+    collects_cycle(hash_map);
+    collects_cycle(hash_map_keys);
+    collects_cycle(hash_set);
+}
+
+#[cfg(feature = "im")]
+#[test]
+fn im_cycles_are_collected() {
+    fn vector(edge: Edge) -> Box<dyn Trace + '_> {
+        Box::new(im::Vector::unit(edge))
+    }
+    fn hash_map(edge: Edge) -> Box<dyn Trace + '_> {
+        Box::new(im::HashMap::unit(0u32, edge))
+    }
+    fn hash_map_keys(edge: Edge) -> Box<dyn Trace + '_> {
+        Box::new(TraceAll(im::HashMap::unit(Key(0, edge), 0u32)))
+    }
+    fn hash_set(edge: Edge) -> Box<dyn Trace + '_> {
+        Box::new(im::HashSet::unit(Key(0, edge)))
+    }
+    fn ord_map(edge: Edge) -> Box<dyn Trace + '_> {
+        Box::new(im::OrdMap::unit(0u32, edge))
+    }
+    fn ord_map_keys(edge: Edge) -> Box<dyn Trace + '_> {
+        Box::new(TraceAll(im::OrdMap::unit(Key(0, edge), 0u32)))
+    }
+    fn ord_set(edge: Edge) -> Box<dyn Trace + '_> {
+        Box::new(im::OrdSet::unit(Key(0, edge)))
+    }
+    collects_cycle(vector);
+    collects_cycle(hash_map);
+    collects_cycle(hash_map_keys);
+    collects_cycle(hash_set);
+    collects_cycle(ord_map);
+    collects_cycle(ord_map_keys);
+    collects_cycle(ord_set);
+}
+
+#[cfg(feature = "im")]
+#[test]
+fn im_shared_nodes_are_traced_once() {
+    struct Holder<T>(T);
+    impl<T: Trace> Trace for Holder<T> {
+        fn trace(&self, tracer: &mut Tracer) {
+            tracer.add_target(&self.0);
+        }
+    }
+
+    // Clones the container `$make` returns into two unreachable objects, so
+    // both hold the same nodes, and checks that the object in those nodes
+    // survives because it's still referenced from outside the heap
+    macro_rules! survives_in_shared_nodes {
+        (|$leaf:ident| $make:expr) => {{
+            let mut col = Collector::new();
+            let mut proxy = col.proxy();
+
+            let $leaf = proxy.alloc(Node {
+                edges: GcCell::new(None),
+            });
+            let container = {
+                let $leaf = $leaf.clone();
+                $make
+            };
+            proxy.alloc(Holder(container.clone()));
+            proxy.alloc(Holder(container));
+
+            proxy.run();
+            assert_eq!(proxy.num_tracked(), 1);
+            assert!($leaf.edges.borrow().is_none());
+        }};
+    }
+    survives_in_shared_nodes!(|leaf| std::iter::repeat_n(leaf, 200).collect::<im::Vector<_>>());
+    survives_in_shared_nodes!(|leaf| im::HashMap::unit(0u32, leaf));
+    survives_in_shared_nodes!(|leaf| im::HashSet::unit(Key(0, leaf)));
+    survives_in_shared_nodes!(|leaf| im::OrdMap::unit(0u32, leaf));
+    survives_in_shared_nodes!(|leaf| im::OrdSet::unit(Key(0, leaf)));
+}
+
+#[cfg(feature = "indexmap")]
+#[test]
+fn indexmap_cycles_are_collected() {
+    fn index_map(edge: Edge) -> Box<dyn Trace + '_> {
+        let mut map = indexmap::IndexMap::new();
+        map.insert(0u32, edge);
+        Box::new(map)
+    }
+    fn index_map_keys(edge: Edge) -> Box<dyn Trace + '_> {
+        let mut map = indexmap::IndexMap::new();
+        map.insert(Key(0, edge), 0u32);
+        Box::new(TraceAll(map))
+    }
+    fn index_set(edge: Edge) -> Box<dyn Trace + '_> {
+        let mut set = indexmap::IndexSet::new();
+        set.insert(Key(0, edge));
+        Box::new(set)
+    }
+    collects_cycle(index_map);
+    collects_cycle(index_map_keys);
+    collects_cycle(index_set);
+}
+
+#[cfg(feature = "smallvec")]
+#[test]
+fn smallvec_cycles_are_collected() {
+    fn small_vec(edge: Edge) -> Box<dyn Trace + '_> {
+        let vec: smallvec::SmallVec<[_; 2]> = smallvec::smallvec![edge];
+        Box::new(vec)
+    }
+    collects_cycle(small_vec);
+}